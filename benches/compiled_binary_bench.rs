@@ -1,12 +1,41 @@
+use std::{fs, path::PathBuf, process::Command};
+
 use criterion::{Criterion, criterion_group, criterion_main};
+use unicorn::{
+    backend::{Compiler, linker::Linker},
+    frontend::module::Resolver,
+    middleware::LoweredModule,
+};
+
+const PROGRAM: &str = "./examples/hello.uniq";
+
+/// Builds the example program into a fresh directory and returns it with the
+/// path of the executable.
+fn build() -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("unicorn-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let exe = dir.join("hello");
+
+    let source = fs::read_to_string(PROGRAM).unwrap();
+    let modules = Resolver::default().resolve(PROGRAM, source).unwrap();
+    Compiler::default()
+        .compile_modules_executable(
+            LoweredModule::lower_all(modules).unwrap(),
+            &exe,
+            &Linker::default(),
+        )
+        .unwrap();
+    (dir, exe)
+}
 
 fn bench_aot_binary(c: &mut Criterion) {
+    let (dir, exe) = build();
     let mut time_sum = 0;
     let mut time_count = 0;
 
     c.bench_function("compiled_binary", |b| {
         b.iter(|| {
-            let status = std::process::Command::new("./build/aot-test")
+            let status = Command::new(&exe)
                 .stdout(std::process::Stdio::null())
                 .status()
                 .unwrap();
@@ -16,6 +45,7 @@ fn bench_aot_binary(c: &mut Criterion) {
         });
     });
 
+    fs::remove_dir_all(&dir).unwrap();
     println!("Average time: {} mcs", time_sum as f64 / time_count as f64)
 }
 
@@ -0,0 +1,167 @@
+use std::{
+    ffi::{CStr, c_char},
+    time::Instant,
+};
+
+pub mod scheduler;
+
+/// Runtime function callable from unicorn code, every param and return is pointer-sized.
+/// Params and return are named by their unicorn type, as the type checker sees them.
+pub struct RuntimeFunction {
+    pub name: &'static str,
+    pub params: &'static [&'static str],
+    pub returns: Option<&'static str>,
+    pub address: *const u8,
+}
+
+/// The runtime functions declared by the compiler and registered in the JIT.
+pub const FUNCTIONS: &[RuntimeFunction] = &[
+    RuntimeFunction {
+        name: "stdprint",
+        params: &["i64"],
+        returns: None,
+        address: stdprint as *const u8,
+    },
+    RuntimeFunction {
+        name: "stdprint_u64",
+        params: &["u64"],
+        returns: None,
+        address: stdprint_u64 as *const u8,
+    },
+    RuntimeFunction {
+        name: "stdprint_f32",
+        params: &["f32"],
+        returns: None,
+        address: stdprint_f32 as *const u8,
+    },
+    RuntimeFunction {
+        name: "stdprint_f64",
+        params: &["f64"],
+        returns: None,
+        address: stdprint_f64 as *const u8,
+    },
+    RuntimeFunction {
+        name: "stdprint_bool",
+        params: &["bool"],
+        returns: None,
+        address: stdprint_bool as *const u8,
+    },
+    RuntimeFunction {
+        name: "stdprint_char",
+        params: &["char"],
+        returns: None,
+        address: stdprint_char as *const u8,
+    },
+    RuntimeFunction {
+        name: "stdprint_str",
+        params: &["str"],
+        returns: None,
+        address: stdprint_str as *const u8,
+    },
+    RuntimeFunction {
+        name: "str_len",
+        params: &["str"],
+        returns: Some("i64"),
+        address: str_len as *const u8,
+    },
+    RuntimeFunction {
+        name: "add",
+        params: &["i64", "i64"],
+        returns: Some("i64"),
+        address: add as *const u8,
+    },
+    RuntimeFunction {
+        name: "now",
+        params: &[],
+        returns: Some("i64"),
+        address: now as *const u8,
+    },
+    RuntimeFunction {
+        name: "elapsed",
+        params: &["i64"],
+        returns: Some("i64"),
+        address: elapsed as *const u8,
+    },
+];
+
+#[unsafe(no_mangle)]
+pub extern "C" fn stdprint(val: i64) {
+    println!("{val}")
+}
+
+/// Variants of `stdprint` by the type name of their argument, which they take
+/// as the 64-bit pattern of its value. Other integers are printed by `stdprint`.
+pub const PRINTERS: &[(&str, &str)] = &[
+    ("u64", "stdprint_u64"),
+    ("f32", "stdprint_f32"),
+    ("f64", "stdprint_f64"),
+    ("bool", "stdprint_bool"),
+    ("char", "stdprint_char"),
+    ("str", "stdprint_str"),
+];
+
+#[unsafe(no_mangle)]
+pub extern "C" fn stdprint_u64(val: u64) {
+    println!("{val}")
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn stdprint_f32(val: u64) {
+    println!("{:?}", f32::from_bits(val as u32))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn stdprint_f64(val: u64) {
+    println!("{:?}", f64::from_bits(val))
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn stdprint_bool(val: i64) {
+    println!("{}", val != 0)
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn stdprint_char(val: u64) {
+    println!(
+        "{}",
+        char::from_u32(val as u32).unwrap_or(char::REPLACEMENT_CHARACTER)
+    )
+}
+
+/// # Safety
+///
+/// `val` must point to NUL-terminated bytes, as string literals do.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn stdprint_str(val: *const c_char) {
+    println!("{}", unsafe { CStr::from_ptr(val) }.to_string_lossy())
+}
+
+/// Length in bytes of a string, without its NUL terminator.
+///
+/// # Safety
+///
+/// `val` must point to NUL-terminated bytes, as string literals do.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn str_len(val: *const c_char) -> i64 {
+    unsafe { CStr::from_ptr(val) }.count_bytes() as i64
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn add(a: i64, b: i64) -> i64 {
+    a + b
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn now() -> *const Instant {
+    let start = Box::new(Instant::now());
+    Box::into_raw(start) as *const _
+}
+
+/// # Safety
+///
+/// `instant` must come from [`now`] and is consumed by this call.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn elapsed(instant: *const Instant) -> i64 {
+    let instant: Box<Instant> = unsafe { Box::from_raw(instant as *mut _) };
+    instant.elapsed().as_micros() as i64
+}
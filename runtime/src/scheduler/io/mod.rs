@@ -0,0 +1,558 @@
+use std::{
+    collections::HashMap,
+    ffi::{CStr, CString, OsStr, c_char, c_void},
+    fs,
+    io::{Error, ErrorKind},
+    mem::{self, ManuallyDrop},
+    net::{Ipv4Addr, TcpListener},
+    os::{
+        fd::{FromRawFd, IntoRawFd},
+        unix::ffi::OsStrExt,
+    },
+    path::Path,
+    ptr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+};
+
+use super::{Process, Scheduler, Wait, Worker};
+use crate::RuntimeFunction;
+
+/// I/O functions callable from unicorn code, exported as `unicorn_<name>`.
+/// Each takes the worker of the running process before its params: a process
+/// whose I/O would block waits until its file descriptor is ready and calls
+/// the function again. Strings they return live until the process exits.
+pub const FUNCTIONS: &[RuntimeFunction] = &[
+    RuntimeFunction {
+        name: "read_file",
+        params: &["str"],
+        returns: Some("str"),
+        address: unicorn_read_file as *const u8,
+    },
+    RuntimeFunction {
+        name: "write_file",
+        params: &["str", "str"],
+        returns: Some("i64"),
+        address: unicorn_write_file as *const u8,
+    },
+    RuntimeFunction {
+        name: "read_line",
+        params: &[],
+        returns: Some("str"),
+        address: unicorn_read_line as *const u8,
+    },
+    RuntimeFunction {
+        name: "listen",
+        params: &["i64"],
+        returns: Some("i64"),
+        address: unicorn_listen as *const u8,
+    },
+    RuntimeFunction {
+        name: "local_port",
+        params: &["i64"],
+        returns: Some("i64"),
+        address: unicorn_local_port as *const u8,
+    },
+    RuntimeFunction {
+        name: "accept",
+        params: &["i64"],
+        returns: Some("i64"),
+        address: unicorn_accept as *const u8,
+    },
+    RuntimeFunction {
+        name: "connect",
+        params: &["i64"],
+        returns: Some("i64"),
+        address: unicorn_connect as *const u8,
+    },
+    RuntimeFunction {
+        name: "read",
+        params: &["i64"],
+        returns: Some("str"),
+        address: unicorn_read as *const u8,
+    },
+    RuntimeFunction {
+        name: "write",
+        params: &["i64", "str"],
+        returns: Some("i64"),
+        address: unicorn_write as *const u8,
+    },
+    RuntimeFunction {
+        name: "close",
+        params: &["i64"],
+        returns: None,
+        address: unicorn_close as *const u8,
+    },
+];
+
+/// Bytes a `read` takes at most.
+const READ_SIZE: usize = 4096;
+
+/// Data of the event of [`Poller::wake`], the others carry their file descriptor.
+const WAKE: u64 = u64::MAX;
+
+pub(super) struct Io {
+    pub(super) poller: Poller,
+    stdin: Mutex<LineReader>,
+}
+
+impl Io {
+    pub(super) fn new() -> Self {
+        Self {
+            poller: Poller::new(),
+            stdin: Mutex::new(LineReader::default()),
+        }
+    }
+}
+
+/// Lines of stdin read so far but not taken yet.
+#[derive(Default)]
+struct LineReader {
+    buffer: Vec<u8>,
+    eof: bool,
+}
+
+/// Readiness of the file descriptors processes wait on, through epoll. Every
+/// wait is one-shot: the processes waiting on a file descriptor are all woken
+/// by its next event and wait again when their I/O still would block.
+pub(super) struct Poller {
+    epoll: i32,
+    /// Event file which interrupts the thread blocked in [`Poller::poll`].
+    wake: i32,
+    /// Processes waiting on each file descriptor, with the events they wait for.
+    waiters: Mutex<HashMap<i32, Vec<(usize, u32)>>>,
+    /// Processes in `waiters` or woken but not queued yet.
+    waiting: AtomicUsize,
+    /// Set while a thread blocks in [`Poller::poll`].
+    pub(super) polling: AtomicBool,
+}
+
+impl Poller {
+    fn new() -> Self {
+        let epoll = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        let wake = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if epoll < 0 || wake < 0 {
+            eprintln!("cannot poll for I/O: {}", Error::last_os_error());
+            std::process::abort()
+        }
+        let mut event = libc::epoll_event {
+            events: libc::EPOLLIN as u32,
+            u64: WAKE,
+        };
+        unsafe { libc::epoll_ctl(epoll, libc::EPOLL_CTL_ADD, wake, &mut event) };
+        Self {
+            epoll,
+            wake,
+            waiters: Mutex::new(HashMap::new()),
+            waiting: AtomicUsize::new(0),
+            polling: AtomicBool::new(false),
+        }
+    }
+
+    pub(super) fn is_waiting(&self) -> bool {
+        self.waiting.load(Ordering::SeqCst) > 0
+    }
+
+    /// Makes process `pid` wait for `events` of `fd`, `false` when epoll does
+    /// not watch it, as regular files, which are always ready.
+    pub(super) fn register(&self, fd: i32, events: u32, pid: usize) -> bool {
+        let mut waiters = self.waiters.lock().unwrap();
+        let fd_waiters = waiters.entry(fd).or_default();
+        let mut event = libc::epoll_event {
+            events: fd_waiters
+                .iter()
+                .fold(events, |all, (_, events)| all | events)
+                | libc::EPOLLONESHOT as u32,
+            u64: fd as u64,
+        };
+        let watched = [libc::EPOLL_CTL_MOD, libc::EPOLL_CTL_ADD]
+            .into_iter()
+            .any(|op| unsafe { libc::epoll_ctl(self.epoll, op, fd, &mut event) } == 0);
+        if !watched {
+            if fd_waiters.is_empty() {
+                waiters.remove(&fd);
+            }
+            return false;
+        }
+        fd_waiters.push((pid, events));
+        self.waiting.fetch_add(1, Ordering::SeqCst);
+        true
+    }
+
+    /// Waits at most `timeout` milliseconds, forever when negative, for events
+    /// and returns the processes waiting on their file descriptors.
+    pub(super) fn poll(&self, timeout: i32) -> Vec<usize> {
+        let mut events = [libc::epoll_event { events: 0, u64: 0 }; 64];
+        let count = unsafe {
+            libc::epoll_wait(
+                self.epoll,
+                events.as_mut_ptr(),
+                events.len() as i32,
+                timeout,
+            )
+        };
+        let mut ready = Vec::new();
+        let mut waiters = self.waiters.lock().unwrap();
+        for event in &events[..count.max(0) as usize] {
+            match event.u64 {
+                WAKE => {
+                    let mut count = 0u64;
+                    unsafe { libc::read(self.wake, (&raw mut count).cast(), 8) };
+                }
+                fd => ready.extend(
+                    waiters
+                        .remove(&(fd as i32))
+                        .into_iter()
+                        .flatten()
+                        .map(|(pid, _)| pid),
+                ),
+            }
+        }
+        ready
+    }
+
+    /// Takes the processes waiting on `fd`, which is closed.
+    pub(super) fn forget(&self, fd: i32) -> Vec<usize> {
+        let waiters = self.waiters.lock().unwrap().remove(&fd);
+        waiters.into_iter().flatten().map(|(pid, _)| pid).collect()
+    }
+
+    /// The processes of `woken` were taken from the waiters and are queued.
+    pub(super) fn woken(&self, woken: usize) {
+        self.waiting.fetch_sub(woken, Ordering::SeqCst);
+    }
+
+    /// Interrupts the thread blocked in [`Poller::poll`], if any.
+    pub(super) fn notify(&self) {
+        if self.polling.load(Ordering::SeqCst) {
+            let count = 1u64;
+            unsafe { libc::write(self.wake, (&raw const count).cast(), 8) };
+        }
+    }
+}
+
+impl Drop for Poller {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.epoll);
+            libc::close(self.wake);
+        }
+    }
+}
+
+impl Scheduler {
+    /// Queues the processes of `pids`, which waited for I/O.
+    pub(super) fn wake_io(&self, queue: usize, pids: Vec<usize>) {
+        for &pid in &pids {
+            let process = self.process(pid as i64).unwrap();
+            let mut state = process.state.lock().unwrap();
+            if !matches!(state.waiting, Some(Wait::Io(..))) {
+                continue;
+            }
+            state.waiting = None;
+            drop(state);
+            self.active.fetch_add(1, Ordering::SeqCst);
+            self.push(queue, process);
+        }
+        self.io.poller.woken(pids.len());
+    }
+}
+
+/// Ends the turn of the running process, which calls the I/O function again
+/// once `events` of `fd` are ready.
+fn wait(worker: &mut Worker, fd: i32, events: i32) -> i64 {
+    worker.yielded = 1;
+    worker.park = Some((Wait::Io(fd, events as u32), None));
+    0
+}
+
+fn would_block() -> bool {
+    Error::last_os_error().kind() == ErrorKind::WouldBlock
+}
+
+fn running(worker: &Worker) -> Arc<Process> {
+    worker.process.clone().unwrap()
+}
+
+/// String of `bytes` without their NULs, owned by the running process.
+fn string(worker: &Worker, mut bytes: Vec<u8>) -> i64 {
+    bytes.retain(|byte| *byte != 0);
+    let string = CString::new(bytes).unwrap();
+    let ptr = string.as_ptr() as i64;
+    running(worker).state.lock().unwrap().strings.push(string);
+    ptr
+}
+
+/// # Safety
+///
+/// `string` must point to NUL-terminated bytes.
+unsafe fn bytes<'a>(string: *const c_char) -> &'a [u8] {
+    unsafe { CStr::from_ptr(string) }.to_bytes()
+}
+
+fn ready(fd: i32, events: i16) -> bool {
+    let mut poll = libc::pollfd {
+        fd,
+        events,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut poll, 1, 0) > 0 }
+}
+
+/// Contents of the file at `path`, empty when it cannot be read.
+///
+/// # Safety
+///
+/// `worker` is the one the running process got and `path` is a string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn unicorn_read_file(worker: *mut Worker, path: *const c_char) -> i64 {
+    let worker = unsafe { &*worker };
+    let path = Path::new(OsStr::from_bytes(unsafe { bytes(path) }));
+    string(worker, fs::read(path).unwrap_or_default())
+}
+
+/// Replaces the file at `path` with `contents` and returns their length, `-1`
+/// when it cannot be written.
+///
+/// # Safety
+///
+/// `path` and `contents` are strings.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn unicorn_write_file(
+    _worker: *mut Worker,
+    path: *const c_char,
+    contents: *const c_char,
+) -> i64 {
+    let path = Path::new(OsStr::from_bytes(unsafe { bytes(path) }));
+    let contents = unsafe { bytes(contents) };
+    match fs::write(path, contents) {
+        Ok(()) => contents.len() as i64,
+        Err(_) => -1,
+    }
+}
+
+/// Next line of stdin with its newline, empty at the end of the input.
+///
+/// # Safety
+///
+/// `worker` is the one the running process got.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn unicorn_read_line(worker: *mut Worker) -> i64 {
+    let worker = unsafe { &mut *worker };
+    let scheduler = unsafe { &*worker.scheduler };
+    let mut stdin = scheduler.io.stdin.lock().unwrap();
+    loop {
+        if let Some(end) = stdin.buffer.iter().position(|byte| *byte == b'\n') {
+            let line = stdin.buffer.drain(..=end).collect();
+            return string(worker, line);
+        }
+        if stdin.eof {
+            let rest = mem::take(&mut stdin.buffer);
+            return string(worker, rest);
+        }
+        // Stdin stays blocking, it is only read once it is ready.
+        if !ready(libc::STDIN_FILENO, libc::POLLIN) {
+            return wait(worker, libc::STDIN_FILENO, libc::EPOLLIN);
+        }
+        let mut buffer = [0u8; READ_SIZE];
+        let count =
+            unsafe { libc::read(libc::STDIN_FILENO, buffer.as_mut_ptr().cast(), READ_SIZE) };
+        match count {
+            1.. => stdin.buffer.extend_from_slice(&buffer[..count as usize]),
+            _ => stdin.eof = true,
+        }
+    }
+}
+
+/// Listens for TCP connections on `port` of localhost, any free one for `0`,
+/// and returns the listening socket, `-1` when it cannot.
+#[unsafe(no_mangle)]
+pub extern "C" fn unicorn_listen(_worker: *mut Worker, port: i64) -> i64 {
+    let Ok(port) = u16::try_from(port) else {
+        return -1;
+    };
+    match TcpListener::bind((Ipv4Addr::LOCALHOST, port)) {
+        Ok(listener) if listener.set_nonblocking(true).is_ok() => listener.into_raw_fd() as i64,
+        _ => -1,
+    }
+}
+
+/// Port a socket is bound to, `-1` for anything else.
+///
+/// # Safety
+///
+/// `fd` is not owned by anything but the program.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn unicorn_local_port(_worker: *mut Worker, fd: i64) -> i64 {
+    if fd < 0 {
+        return -1;
+    }
+    let listener = ManuallyDrop::new(unsafe { TcpListener::from_raw_fd(fd as i32) });
+    match listener.local_addr() {
+        Ok(addr) => addr.port() as i64,
+        Err(_) => -1,
+    }
+}
+
+/// Next connection to a listening socket, `-1` when it fails.
+///
+/// # Safety
+///
+/// `worker` is the one the running process got.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn unicorn_accept(worker: *mut Worker, fd: i64) -> i64 {
+    let worker = unsafe { &mut *worker };
+    let flags = libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC;
+    let stream = unsafe { libc::accept4(fd as i32, ptr::null_mut(), ptr::null_mut(), flags) };
+    match stream {
+        0.. => stream as i64,
+        _ if would_block() => wait(worker, fd as i32, libc::EPOLLIN),
+        _ => -1,
+    }
+}
+
+/// Connects to `port` of localhost and returns the socket, `-1` when it fails.
+///
+/// # Safety
+///
+/// `worker` is the one the running process got.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn unicorn_connect(worker: *mut Worker, port: i64) -> i64 {
+    let worker = unsafe { &mut *worker };
+    let process = running(worker);
+    let mut state = process.state.lock().unwrap();
+    // The socket is writable once the connection is made or failed.
+    if let Some(fd) = state.connecting.take() {
+        let mut error = 0i32;
+        let mut len = mem::size_of::<i32>() as libc::socklen_t;
+        let status = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_ERROR,
+                (&raw mut error).cast::<c_void>(),
+                &mut len,
+            )
+        };
+        if status == 0 && error == 0 {
+            return fd as i64;
+        }
+        unsafe { libc::close(fd) };
+        return -1;
+    }
+
+    let Ok(port) = u16::try_from(port) else {
+        return -1;
+    };
+    let kind = libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC;
+    let fd = unsafe { libc::socket(libc::AF_INET, kind, 0) };
+    if fd < 0 {
+        return -1;
+    }
+    let addr = libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: port.to_be(),
+        sin_addr: libc::in_addr {
+            s_addr: u32::from(Ipv4Addr::LOCALHOST).to_be(),
+        },
+        sin_zero: [0; 8],
+    };
+    let connected = unsafe {
+        libc::connect(
+            fd,
+            (&raw const addr).cast::<libc::sockaddr>(),
+            mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    };
+    if connected == 0 {
+        return fd as i64;
+    }
+    if Error::last_os_error().raw_os_error() == Some(libc::EINPROGRESS) {
+        state.connecting = Some(fd);
+        drop(state);
+        return wait(worker, fd, libc::EPOLLOUT);
+    }
+    unsafe { libc::close(fd) };
+    -1
+}
+
+/// Bytes available on `fd`, at most 4096, empty at its end or when it fails.
+///
+/// # Safety
+///
+/// `worker` is the one the running process got.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn unicorn_read(worker: *mut Worker, fd: i64) -> i64 {
+    let worker = unsafe { &mut *worker };
+    let mut buffer = vec![0u8; READ_SIZE];
+    let count = unsafe { libc::read(fd as i32, buffer.as_mut_ptr().cast(), READ_SIZE) };
+    match count {
+        0.. => {
+            buffer.truncate(count as usize);
+            string(worker, buffer)
+        }
+        _ if would_block() => wait(worker, fd as i32, libc::EPOLLIN),
+        _ => string(worker, Vec::new()),
+    }
+}
+
+/// Writes all of `data` to `fd` and returns its length, `-1` when it fails.
+///
+/// # Safety
+///
+/// `worker` is the one the running process got and `data` is a string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn unicorn_write(worker: *mut Worker, fd: i64, data: *const c_char) -> i64 {
+    let worker = unsafe { &mut *worker };
+    let data = unsafe { bytes(data) };
+    let process = running(worker);
+    let mut state = process.state.lock().unwrap();
+    while state.written < data.len() {
+        let rest = &data[state.written..];
+        // A peer which closed its socket must not stop the program with SIGPIPE.
+        let mut count = unsafe {
+            libc::send(
+                fd as i32,
+                rest.as_ptr().cast(),
+                rest.len(),
+                libc::MSG_NOSIGNAL,
+            )
+        };
+        if count < 0 && Error::last_os_error().raw_os_error() == Some(libc::ENOTSOCK) {
+            count = unsafe { libc::write(fd as i32, rest.as_ptr().cast(), rest.len()) };
+        }
+        match count {
+            1.. => state.written += count as usize,
+            // Nothing was written and `errno` says nothing about it.
+            0 => {
+                state.written = 0;
+                return -1;
+            }
+            _ if would_block() => {
+                drop(state);
+                return wait(worker, fd as i32, libc::EPOLLOUT);
+            }
+            _ => {
+                state.written = 0;
+                return -1;
+            }
+        }
+    }
+    mem::take(&mut state.written) as i64
+}
+
+/// Closes `fd`, the processes waiting on it get its error.
+///
+/// # Safety
+///
+/// `worker` is the one the running process got.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn unicorn_close(worker: *mut Worker, fd: i64) {
+    let worker = unsafe { &*worker };
+    let scheduler = unsafe { &*worker.scheduler };
+    let waiting = scheduler.io.poller.forget(fd as i32);
+    unsafe { libc::close(fd as i32) };
+    scheduler.wake_io(worker.index, waiting);
+}
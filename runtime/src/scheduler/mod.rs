@@ -0,0 +1,908 @@
+use std::{
+    collections::VecDeque,
+    env,
+    ffi::CString,
+    sync::{
+        Arc, Condvar, Mutex, RwLock,
+        atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+use io::Io;
+use timer::{Clock, Timer, TimerWheel};
+
+pub mod io;
+mod timer;
+
+/// Creates the root context of a process with id `pid` running `function`,
+/// with a copy of the `args_len` values at `args` as its arguments.
+pub type CreateProcess =
+    extern "C" fn(function: usize, args: *const i64, args_len: i64, pid: i64) -> usize;
+
+/// Runs a process from `next_block` of the function of `ctx` for one turn and
+/// returns the block it resumes at, `-1` once it finished or failed. The
+/// context it resumes in is left in [`Worker::current_ctx`].
+pub type RunSlice = extern "C" fn(ctx: usize, next_block: i64, worker: *mut Worker) -> i64;
+
+/// Frees the calls, variables and arena of a process which did not finish.
+pub type FreeProcess = extern "C" fn(ctx: usize);
+
+/// Environment variable with the number of scheduler threads, when the program
+/// was not compiled with one.
+pub const THREADS_VAR: &str = "UNICORN_THREADS";
+
+/// Reasons a process exits with. An exit message has the reason in its low
+/// [`EXIT_REASON_BITS`] and the id of the process which exited above them.
+pub const EXIT_NORMAL: i64 = 0;
+pub const EXIT_DIVISION_BY_ZERO: i64 = 1;
+pub const EXIT_INDEX_OUT_OF_BOUNDS: i64 = 2;
+/// The process ran past its last block, which the compiler never emits.
+pub const EXIT_END_OF_BLOCKS: i64 = 3;
+/// The linked or monitored process was never spawned.
+pub const EXIT_NO_PROCESS: i64 = 4;
+/// Signed division of the smallest value of its type by `-1`.
+pub const EXIT_OVERFLOW: i64 = 5;
+/// An allocation of the process failed.
+pub const EXIT_OUT_OF_MEMORY: i64 = 6;
+pub const EXIT_REASON_BITS: i64 = 8;
+
+/// Turns a thread runs between two checks for I/O, unless it has nothing to run.
+const POLL_TURNS: usize = 61;
+
+/// Scheduler functions called by the generated code, by symbol name.
+pub const SYMBOLS: &[(&str, *const u8)] = &[
+    ("unicorn_start", unicorn_start as *const u8),
+    ("unicorn_spawn", unicorn_spawn as *const u8),
+    ("unicorn_send", unicorn_send as *const u8),
+    ("unicorn_receive", unicorn_receive as *const u8),
+    ("unicorn_link", unicorn_link as *const u8),
+    ("unicorn_monitor", unicorn_monitor as *const u8),
+    ("unicorn_restart", unicorn_restart as *const u8),
+    ("unicorn_await", unicorn_await as *const u8),
+    ("unicorn_sleep", unicorn_sleep as *const u8),
+    ("unicorn_now_ms", unicorn_now_ms as *const u8),
+];
+
+/// State of a scheduler thread, the generated code gets a pointer to it and
+/// reads and writes its first fields.
+#[repr(C)]
+pub struct Worker {
+    /// Context whose function runs next, switched by calls and returns.
+    current_ctx: usize,
+    /// Value of the root function of the process which just finished.
+    result: i64,
+    /// Set by a process waiting for a message or a timer, which ends its turn.
+    yielded: i64,
+    /// Reason the running process failed with, set when it stops at a fault.
+    fault: i64,
+    scheduler: *const Scheduler,
+    index: usize,
+    /// Process of the running turn.
+    process: Option<Arc<Process>>,
+    /// What the running process waits for once it yielded, and for how many
+    /// milliseconds at most.
+    park: Option<(Wait, Option<i64>)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Wait {
+    Message,
+    Timer,
+    /// Epoll events of a file descriptor.
+    Io(i32, u32),
+}
+
+struct Process {
+    pid: usize,
+    /// Function and arguments the process was spawned with, to restart it.
+    function: usize,
+    args: Vec<i64>,
+    state: Mutex<ProcessState>,
+}
+
+struct ProcessState {
+    ctx: usize,
+    next_block: i64,
+    mailbox: VecDeque<i64>,
+    /// Out of every run queue until a message arrives, its timer fires or its
+    /// I/O is ready.
+    waiting: Option<Wait>,
+    /// Timer which wakes the process, a fired or cancelled one is ignored.
+    timer: Option<u64>,
+    /// Set when the timer woke a process waiting for a message.
+    timed_out: bool,
+    /// Strings the process read, freed once it exits.
+    strings: Vec<CString>,
+    /// Bytes of the pending `write` written so far.
+    written: usize,
+    /// Socket of the pending `connect`.
+    connecting: Option<i32>,
+    /// Reason the process exited with, once it did.
+    exited: Option<i64>,
+    /// Processes told when this one fails, both ways.
+    links: Vec<usize>,
+    /// Processes told when this one exits, for any reason.
+    monitors: Vec<usize>,
+}
+
+/// Processes run in turns on a set of threads. Every thread owns a queue of
+/// the processes it runs in turn and steals from the others once it is empty.
+pub struct Scheduler {
+    create_process: CreateProcess,
+    run_slice: RunSlice,
+    free_process: FreeProcess,
+    /// Processes by id, exited ones included.
+    processes: RwLock<Vec<Arc<Process>>>,
+    queues: Vec<Mutex<VecDeque<Arc<Process>>>>,
+    /// Processes in a queue or running. None left and no timer pending means
+    /// that every live process waits for a message no process can send anymore.
+    active: AtomicI64,
+    clock: Clock,
+    timers: Mutex<TimerWheel>,
+    /// Timers in the wheel, read without locking it.
+    pending_timers: AtomicUsize,
+    timer_ids: AtomicU64,
+    io: Io,
+    /// Set once the main process finished, with its value in `result`.
+    finished: AtomicBool,
+    result: AtomicI64,
+    sleepers: AtomicUsize,
+    sleep: Mutex<()>,
+    wake: Condvar,
+}
+
+impl Scheduler {
+    fn new(
+        create_process: CreateProcess,
+        run_slice: RunSlice,
+        free_process: FreeProcess,
+        threads: usize,
+        virtual_clock: bool,
+    ) -> Self {
+        Self {
+            create_process,
+            run_slice,
+            free_process,
+            processes: RwLock::new(Vec::new()),
+            queues: (0..threads).map(|_| Mutex::new(VecDeque::new())).collect(),
+            active: AtomicI64::new(0),
+            clock: Clock::new(virtual_clock),
+            timers: Mutex::new(TimerWheel::new()),
+            pending_timers: AtomicUsize::new(0),
+            timer_ids: AtomicU64::new(0),
+            io: Io::new(),
+            finished: AtomicBool::new(false),
+            result: AtomicI64::new(0),
+            sleepers: AtomicUsize::new(0),
+            sleep: Mutex::new(()),
+            wake: Condvar::new(),
+        }
+    }
+
+    /// Adds a process starting at block 0 of `function` and returns its id.
+    fn spawn(&self, queue: usize, function: usize, args: Vec<i64>) -> i64 {
+        let process = {
+            let mut processes = self.processes.write().unwrap();
+            let process = Arc::new(Process {
+                pid: processes.len(),
+                function,
+                args,
+                state: Mutex::new(ProcessState {
+                    ctx: 0,
+                    next_block: 0,
+                    mailbox: VecDeque::new(),
+                    waiting: None,
+                    timer: None,
+                    timed_out: false,
+                    strings: Vec::new(),
+                    written: 0,
+                    connecting: None,
+                    exited: None,
+                    links: Vec::new(),
+                    monitors: Vec::new(),
+                }),
+            });
+            processes.push(process.clone());
+            process
+        };
+        let pid = process.pid as i64;
+        let ctx = (self.create_process)(
+            function,
+            process.args.as_ptr(),
+            process.args.len() as i64,
+            pid,
+        );
+        if ctx == 0 {
+            // Its context could not be allocated, nothing watches the process yet.
+            eprintln!("process {pid} failed: {}", describe(EXIT_OUT_OF_MEMORY));
+            if pid == 0 {
+                std::process::abort()
+            }
+            process.state.lock().unwrap().exited = Some(EXIT_OUT_OF_MEMORY);
+            return pid;
+        }
+        process.state.lock().unwrap().ctx = ctx;
+        self.active.fetch_add(1, Ordering::SeqCst);
+        self.push(queue, process);
+        pid
+    }
+
+    fn process(&self, pid: i64) -> Option<Arc<Process>> {
+        self.processes.read().unwrap().get(pid as usize).cloned()
+    }
+
+    /// Messages to an exited process are dropped.
+    fn send(&self, queue: usize, pid: i64, message: i64) {
+        let Some(process) = self.process(pid) else {
+            return;
+        };
+        let mut state = process.state.lock().unwrap();
+        if state.exited.is_some() {
+            return;
+        }
+        state.mailbox.push_back(message);
+        if state.waiting == Some(Wait::Message) {
+            state.waiting = None;
+            state.timer = None;
+            drop(state);
+            self.active.fetch_add(1, Ordering::SeqCst);
+            self.push(queue, process);
+        }
+    }
+
+    /// Adds the running process to the `links` or `monitors` of process `pid`.
+    /// When `pid` already exited, the running process gets the exit message
+    /// right away if `told` by the reason.
+    fn watch(
+        &self,
+        worker: &Worker,
+        pid: i64,
+        watchers: fn(&mut ProcessState) -> &mut Vec<usize>,
+        told: fn(i64) -> bool,
+    ) -> bool {
+        let own = worker.process.as_ref().unwrap().pid;
+        let reason = match self.process(pid) {
+            Some(process) => {
+                let mut state = process.state.lock().unwrap();
+                match state.exited {
+                    Some(reason) => reason,
+                    None => {
+                        let watchers = watchers(&mut state);
+                        if !watchers.contains(&own) {
+                            watchers.push(own);
+                        }
+                        return true;
+                    }
+                }
+            }
+            None => EXIT_NO_PROCESS,
+        };
+        if told(reason) {
+            self.send(worker.index, own as i64, exit_message(pid, reason));
+        }
+        false
+    }
+
+    fn link(&self, worker: &Worker, pid: i64) {
+        let process = worker.process.as_ref().unwrap();
+        if process.pid as i64 == pid || !self.watch(worker, pid, |state| &mut state.links, failed) {
+            return;
+        }
+        let mut state = process.state.lock().unwrap();
+        if !state.links.contains(&(pid as usize)) {
+            state.links.push(pid as usize);
+        }
+    }
+
+    fn monitor(&self, worker: &Worker, pid: i64) {
+        self.watch(worker, pid, |state| &mut state.monitors, |_| true);
+    }
+
+    /// Spawns a new process with the function and arguments `pid` was spawned
+    /// with. An unknown `pid` gives `-1`, which links and monitors report as
+    /// [`EXIT_NO_PROCESS`].
+    fn restart(&self, queue: usize, pid: i64) -> i64 {
+        let Some(process) = self.process(pid) else {
+            return -1;
+        };
+        self.spawn(queue, process.function, process.args.clone())
+    }
+
+    fn push(&self, queue: usize, process: Arc<Process>) {
+        self.queues[queue].lock().unwrap().push_back(process);
+        if self.sleepers.load(Ordering::SeqCst) > 0 {
+            let _sleep = self.sleep.lock().unwrap();
+            self.wake.notify_one();
+        }
+        self.io.poller.notify();
+    }
+
+    /// Next process of the own queue of a thread, or one stolen from the back
+    /// of the queue of another thread.
+    fn pop(&self, queue: usize) -> Option<Arc<Process>> {
+        if let Some(process) = self.queues[queue].lock().unwrap().pop_front() {
+            return Some(process);
+        }
+        let threads = self.queues.len();
+        (1..threads).find_map(|i| {
+            self.queues[(queue + i) % threads]
+                .lock()
+                .unwrap()
+                .pop_back()
+        })
+    }
+
+    fn work(&self, index: usize) {
+        let mut worker = Worker {
+            current_ctx: 0,
+            result: 0,
+            yielded: 0,
+            fault: EXIT_NORMAL,
+            scheduler: self,
+            index,
+            process: None,
+            park: None,
+        };
+        let mut turns = 0;
+        while !self.finished.load(Ordering::SeqCst) {
+            self.expire(index);
+            if turns % POLL_TURNS == 0 && self.io.poller.is_waiting() {
+                self.wake_io(index, self.io.poller.poll(0));
+            }
+            turns += 1;
+            match self.pop(index) {
+                Some(process) => self.turn(&mut worker, process),
+                None => self.idle(index),
+            }
+        }
+    }
+
+    /// Sleeps until a process is queued, the next timer is due or the program
+    /// ends. One of the sleeping threads waits for I/O meanwhile.
+    fn idle(&self, queue: usize) {
+        let deadline = self.timers.lock().unwrap().next_deadline();
+        let poller = &self.io.poller;
+        if poller.is_waiting() && !poller.polling.swap(true, Ordering::SeqCst) {
+            let mut ready = Vec::new();
+            if self
+                .queues
+                .iter()
+                .all(|queue| queue.lock().unwrap().is_empty())
+                && !self.finished.load(Ordering::SeqCst)
+            {
+                let timeout = deadline.map_or(-1, |deadline| {
+                    (deadline - self.clock.now()).clamp(0, i32::MAX as i64) as i32
+                });
+                ready = poller.poll(timeout);
+            }
+            poller.polling.store(false, Ordering::SeqCst);
+            self.wake_io(queue, ready);
+            return;
+        }
+        let sleep = self.sleep.lock().unwrap();
+        self.sleepers.fetch_add(1, Ordering::SeqCst);
+        let empty = self
+            .queues
+            .iter()
+            .all(|queue| queue.lock().unwrap().is_empty());
+        if empty && !self.finished.load(Ordering::SeqCst) {
+            match deadline {
+                Some(deadline) => {
+                    let timeout =
+                        Duration::from_millis((deadline - self.clock.now()).max(0) as u64);
+                    drop(self.wake.wait_timeout(sleep, timeout).unwrap());
+                }
+                None => drop(self.wake.wait(sleep).unwrap()),
+            }
+        }
+        self.sleepers.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Fires the timers due by now, unless another thread is at it.
+    fn expire(&self, queue: usize) {
+        if self.pending_timers.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+        let Ok(mut timers) = self.timers.try_lock() else {
+            return;
+        };
+        let now = self.clock.now();
+        self.fire(queue, &mut timers, now);
+    }
+
+    /// Wakes the processes of the timers due by `now`, which holds the wheel
+    /// locked so that no thread sees every process parked and no timer.
+    fn fire(&self, queue: usize, timers: &mut TimerWheel, now: i64) {
+        let fired = timers.advance(now);
+        self.pending_timers.fetch_sub(fired.len(), Ordering::SeqCst);
+        for timer in &fired {
+            let process = self.process(timer.pid as i64).unwrap();
+            let mut state = process.state.lock().unwrap();
+            if state.timer != Some(timer.id) {
+                continue;
+            }
+            state.timer = None;
+            let Some(wait) = state.waiting.take() else {
+                continue;
+            };
+            state.timed_out = wait == Wait::Message;
+            drop(state);
+            self.active.fetch_add(1, Ordering::SeqCst);
+            self.push(queue, process);
+        }
+        // Only timers of processes woken by a message fired.
+        if !fired.is_empty()
+            && timers.is_empty()
+            && self.active.load(Ordering::SeqCst) == 0
+            && !self.io.poller.is_waiting()
+        {
+            deadlock()
+        }
+    }
+
+    /// Parks the running process until its timer fires or, waiting for a
+    /// message, one arrives.
+    fn park(&self, worker: &Worker, process: Arc<Process>, wait: Wait, timeout: Option<i64>) {
+        let mut state = process.state.lock().unwrap();
+        if wait == Wait::Message && !state.mailbox.is_empty() {
+            drop(state);
+            self.push(worker.index, process);
+            return;
+        }
+        let timer = timeout.map(|timeout| Timer {
+            deadline: self.clock.now() + timeout,
+            pid: process.pid,
+            id: self.timer_ids.fetch_add(1, Ordering::SeqCst),
+        });
+        state.waiting = Some(wait);
+        state.timer = timer.map(|timer| timer.id);
+        state.timed_out = false;
+        drop(state);
+        if let Wait::Io(fd, events) = wait
+            && !self.io.poller.register(fd, events, process.pid)
+        {
+            process.state.lock().unwrap().waiting = None;
+            self.push(worker.index, process);
+            return;
+        }
+        if let Some(timer) = timer {
+            self.timers.lock().unwrap().insert(timer);
+            self.pending_timers.fetch_add(1, Ordering::SeqCst);
+        }
+        self.deactivate(worker.index);
+    }
+
+    fn turn(&self, worker: &mut Worker, process: Arc<Process>) {
+        let (ctx, next_block) = {
+            let state = process.state.lock().unwrap();
+            (state.ctx, state.next_block)
+        };
+        worker.process = Some(process.clone());
+        let next_block = (self.run_slice)(ctx, next_block, worker);
+
+        if next_block == -1 {
+            // A failed process stopped in the middle of its calls.
+            let reason = std::mem::replace(&mut worker.fault, EXIT_NORMAL);
+            if failed(reason) {
+                (self.free_process)(worker.current_ctx);
+            }
+            self.exit(worker, &process, reason);
+            worker.process = None;
+            return;
+        }
+        worker.process = None;
+
+        {
+            let mut state = process.state.lock().unwrap();
+            state.ctx = worker.current_ctx;
+            state.next_block = next_block;
+        }
+        worker.yielded = 0;
+        match worker.park.take() {
+            Some((wait, timeout)) => self.park(worker, process, wait, timeout),
+            None => self.push(worker.index, process),
+        }
+    }
+
+    /// Tells the monitors of the process that it exited, and its links when it
+    /// failed. The program ends with its main process, the first one.
+    fn exit(&self, worker: &Worker, process: &Arc<Process>, reason: i64) {
+        let (links, monitors) = {
+            let mut state = process.state.lock().unwrap();
+            state.exited = Some(reason);
+            state.mailbox = VecDeque::new();
+            state.strings = Vec::new();
+            (
+                std::mem::take(&mut state.links),
+                std::mem::take(&mut state.monitors),
+            )
+        };
+        if failed(reason) {
+            eprintln!("process {} failed: {}", process.pid, describe(reason));
+            if process.pid == 0 {
+                std::process::abort()
+            }
+        }
+        if process.pid == 0 {
+            self.result.store(worker.result, Ordering::SeqCst);
+            self.finished.store(true, Ordering::SeqCst);
+            self.io.poller.notify();
+            let _sleep = self.sleep.lock().unwrap();
+            self.wake.notify_all();
+            return;
+        }
+
+        let message = exit_message(process.pid as i64, reason);
+        for pid in monitors {
+            self.send(worker.index, pid as i64, message);
+        }
+        if failed(reason) {
+            for pid in links {
+                self.send(worker.index, pid as i64, message);
+            }
+        }
+        self.deactivate(worker.index);
+    }
+
+    /// Once no process is left to run, the next timers are the only way on. A
+    /// virtual clock moves right to them.
+    fn deactivate(&self, queue: usize) {
+        if self.active.fetch_sub(1, Ordering::SeqCst) != 1 {
+            return;
+        }
+        let mut timers = self.timers.lock().unwrap();
+        while self.active.load(Ordering::SeqCst) == 0 {
+            let Some(deadline) = timers.next_deadline() else {
+                if self.io.poller.is_waiting() {
+                    return;
+                }
+                deadlock()
+            };
+            if !self.clock.advance(deadline) {
+                return;
+            }
+            self.fire(queue, &mut timers, deadline);
+        }
+    }
+}
+
+fn deadlock() -> ! {
+    eprintln!("deadlock: every process waits for a message");
+    std::process::abort()
+}
+
+fn failed(reason: i64) -> bool {
+    reason != EXIT_NORMAL
+}
+
+fn exit_message(pid: i64, reason: i64) -> i64 {
+    (pid << EXIT_REASON_BITS) | reason
+}
+
+fn describe(reason: i64) -> &'static str {
+    match reason {
+        EXIT_DIVISION_BY_ZERO => "division by zero",
+        EXIT_INDEX_OUT_OF_BOUNDS => "index out of bounds",
+        EXIT_END_OF_BLOCKS => "end of blocks",
+        EXIT_NO_PROCESS => "no such process",
+        EXIT_OVERFLOW => "integer overflow",
+        EXIT_OUT_OF_MEMORY => "out of memory",
+        _ => "unknown reason",
+    }
+}
+
+/// Scheduler threads: the compiled count, else [`THREADS_VAR`], else one per CPU.
+fn threads(compiled: i64) -> usize {
+    if compiled > 0 {
+        return compiled as usize;
+    }
+    env::var(THREADS_VAR)
+        .ok()
+        .and_then(|threads| threads.parse().ok())
+        .filter(|threads| *threads > 0)
+        .or_else(|| thread::available_parallelism().ok().map(usize::from))
+        .unwrap_or(1)
+}
+
+/// Runs the main process, running `function` without arguments, and every
+/// process it spawns on `threads` threads, the calling one included, and
+/// returns the value of the main process. With a single thread processes run
+/// in a fixed order, and with a `virtual_clock` time only passes once every
+/// process waits, right to the next timer.
+#[unsafe(no_mangle)]
+pub extern "C" fn unicorn_start(
+    function: usize,
+    create_process: CreateProcess,
+    run_slice: RunSlice,
+    free_process: FreeProcess,
+    threads: i64,
+    virtual_clock: i64,
+) -> i64 {
+    let scheduler = Scheduler::new(
+        create_process,
+        run_slice,
+        free_process,
+        self::threads(threads),
+        virtual_clock != 0,
+    );
+    scheduler.spawn(0, function, Vec::new());
+    thread::scope(|scope| {
+        for index in 1..scheduler.queues.len() {
+            let scheduler = &scheduler;
+            scope.spawn(move || scheduler.work(index));
+        }
+        scheduler.work(0);
+    });
+
+    // Processes still waiting or in a queue when the main one finished.
+    for process in scheduler.processes.into_inner().unwrap() {
+        let state = process.state.lock().unwrap();
+        if state.exited.is_none() {
+            (scheduler.free_process)(state.ctx);
+        }
+    }
+    scheduler.result.into_inner()
+}
+
+/// # Safety
+///
+/// `worker` is the one the running process got and `args` points to `args_len` values.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn unicorn_spawn(
+    worker: *mut Worker,
+    function: usize,
+    args: *const i64,
+    args_len: i64,
+) -> i64 {
+    let worker = unsafe { &*worker };
+    let args = unsafe { std::slice::from_raw_parts(args, args_len as usize) }.to_vec();
+    unsafe { &*worker.scheduler }.spawn(worker.index, function, args)
+}
+
+/// # Safety
+///
+/// `worker` is the one the running process got.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn unicorn_send(worker: *mut Worker, pid: i64, message: i64) {
+    let worker = unsafe { &*worker };
+    unsafe { &*worker.scheduler }.send(worker.index, pid, message);
+}
+
+/// Takes the oldest message of the running process. Without one, sets
+/// [`Worker::yielded`] so that the process waits for it and returns `0`.
+///
+/// # Safety
+///
+/// `worker` is the one the running process got.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn unicorn_receive(worker: *mut Worker) -> i64 {
+    let worker = unsafe { &mut *worker };
+    let process = worker.process.as_ref().unwrap();
+    match process.state.lock().unwrap().mailbox.pop_front() {
+        Some(message) => message,
+        None => {
+            worker.yielded = 1;
+            worker.park = Some((Wait::Message, None));
+            0
+        }
+    }
+}
+
+/// Whether the running process has a message within `timeout` milliseconds:
+/// `1` once one is in its mailbox, `0` once its timer fired. Without either,
+/// sets [`Worker::yielded`] so that the process waits for the first of them
+/// and calls this again.
+///
+/// # Safety
+///
+/// `worker` is the one the running process got.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn unicorn_await(worker: *mut Worker, timeout: i64) -> i64 {
+    let worker = unsafe { &mut *worker };
+    let process = worker.process.as_ref().unwrap();
+    let mut state = process.state.lock().unwrap();
+    if !state.mailbox.is_empty() {
+        state.timed_out = false;
+        return 1;
+    }
+    if std::mem::take(&mut state.timed_out) || timeout <= 0 {
+        return 0;
+    }
+    worker.yielded = 1;
+    worker.park = Some((Wait::Message, Some(timeout)));
+    0
+}
+
+/// Ends the turn of the running process, which runs again in `ms` milliseconds
+/// at the earliest. Messages do not wake it.
+///
+/// # Safety
+///
+/// `worker` is the one the running process got.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn unicorn_sleep(worker: *mut Worker, ms: i64) {
+    let worker = unsafe { &mut *worker };
+    worker.yielded = 1;
+    worker.park = Some((Wait::Timer, Some(ms.max(0))));
+}
+
+/// Milliseconds since the program started, on the clock of the scheduler.
+///
+/// # Safety
+///
+/// `worker` is the one the running process got.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn unicorn_now_ms(worker: *mut Worker) -> i64 {
+    let worker = unsafe { &*worker };
+    unsafe { &*worker.scheduler }.clock.now()
+}
+
+/// Links the running process with process `pid`: when one of them fails, the
+/// other gets an exit message.
+///
+/// # Safety
+///
+/// `worker` is the one the running process got.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn unicorn_link(worker: *mut Worker, pid: i64) {
+    let worker = unsafe { &*worker };
+    unsafe { &*worker.scheduler }.link(worker, pid);
+}
+
+/// The running process gets an exit message once process `pid` exits.
+///
+/// # Safety
+///
+/// `worker` is the one the running process got.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn unicorn_monitor(worker: *mut Worker, pid: i64) {
+    let worker = unsafe { &*worker };
+    unsafe { &*worker.scheduler }.monitor(worker, pid);
+}
+
+/// Spawns process `pid` again with its function and arguments and returns the
+/// id of the new process, `-1` when `pid` was never spawned.
+///
+/// # Safety
+///
+/// `worker` is the one the running process got.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn unicorn_restart(worker: *mut Worker, pid: i64) -> i64 {
+    let worker = unsafe { &*worker };
+    unsafe { &*worker.scheduler }.restart(worker.index, pid)
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::Ordering;
+
+    use super::{EXIT_NORMAL, Scheduler, Wait, Worker};
+
+    extern "C" fn create_process(_: usize, _: *const i64, _: i64, pid: i64) -> usize {
+        pid as usize + 1
+    }
+
+    extern "C" fn run_slice(_: usize, _: i64, _: *mut Worker) -> i64 {
+        -1
+    }
+
+    extern "C" fn free_process(_: usize) {}
+
+    fn scheduler(threads: usize, virtual_clock: bool) -> Scheduler {
+        Scheduler::new(
+            create_process,
+            run_slice,
+            free_process,
+            threads,
+            virtual_clock,
+        )
+    }
+
+    fn worker(scheduler: &Scheduler, index: usize) -> Worker {
+        Worker {
+            current_ctx: 0,
+            result: 0,
+            yielded: 0,
+            fault: EXIT_NORMAL,
+            scheduler,
+            index,
+            process: None,
+            park: None,
+        }
+    }
+
+    fn pid(scheduler: &Scheduler, queue: usize) -> Option<usize> {
+        scheduler.pop(queue).map(|process| process.pid)
+    }
+
+    #[test]
+    fn pop_takes_the_front_and_steals_from_the_back() {
+        let scheduler = scheduler(2, false);
+        for _ in 0..3 {
+            scheduler.spawn(1, 0, Vec::new());
+        }
+        assert_eq!(scheduler.active.load(Ordering::SeqCst), 3);
+
+        assert_eq!(pid(&scheduler, 1), Some(0));
+        assert_eq!(pid(&scheduler, 0), Some(2));
+        assert_eq!(pid(&scheduler, 0), Some(1));
+        assert_eq!(pid(&scheduler, 0), None);
+        assert_eq!(pid(&scheduler, 1), None);
+    }
+
+    #[test]
+    fn park_waits_for_a_message() {
+        let scheduler = scheduler(1, false);
+        let worker = worker(&scheduler, 0);
+        scheduler.spawn(0, 0, Vec::new());
+        scheduler.spawn(0, 0, Vec::new());
+        let _main = scheduler.pop(0).unwrap();
+        let process = scheduler.pop(0).unwrap();
+
+        scheduler.park(&worker, process.clone(), Wait::Message, None);
+        assert_eq!(process.state.lock().unwrap().waiting, Some(Wait::Message));
+        assert_eq!(scheduler.active.load(Ordering::SeqCst), 1);
+        assert_eq!(pid(&scheduler, 0), None);
+
+        scheduler.send(0, 1, 42);
+        assert_eq!(scheduler.active.load(Ordering::SeqCst), 2);
+        let woken = scheduler.pop(0).unwrap();
+        assert_eq!(woken.pid, 1);
+        let state = woken.state.lock().unwrap();
+        assert_eq!(state.waiting, None);
+        assert_eq!(state.mailbox.front(), Some(&42));
+    }
+
+    #[test]
+    fn park_with_a_message_requeues_right_away() {
+        let scheduler = scheduler(1, false);
+        let worker = worker(&scheduler, 0);
+        scheduler.spawn(0, 0, Vec::new());
+        let process = scheduler.pop(0).unwrap();
+        scheduler.send(0, 0, 7);
+
+        scheduler.park(&worker, process, Wait::Message, None);
+        assert_eq!(scheduler.active.load(Ordering::SeqCst), 1);
+        assert_eq!(pid(&scheduler, 0), Some(0));
+    }
+
+    #[test]
+    fn deactivate_moves_a_virtual_clock_to_the_next_timer() {
+        let scheduler = scheduler(1, true);
+        let worker = worker(&scheduler, 0);
+        scheduler.spawn(0, 0, Vec::new());
+        let process = scheduler.pop(0).unwrap();
+
+        // The last active process parks, so its timer is due right away.
+        scheduler.park(&worker, process.clone(), Wait::Message, Some(5));
+        assert_eq!(scheduler.clock.now(), 5);
+        assert_eq!(scheduler.active.load(Ordering::SeqCst), 1);
+        assert_eq!(scheduler.pending_timers.load(Ordering::SeqCst), 0);
+        let state = process.state.lock().unwrap();
+        assert_eq!(state.waiting, None);
+        assert!(state.timed_out);
+        drop(state);
+        assert_eq!(pid(&scheduler, 0), Some(0));
+    }
+
+    #[test]
+    fn deactivate_waits_while_other_processes_run() {
+        let scheduler = scheduler(1, true);
+        let worker = worker(&scheduler, 0);
+        scheduler.spawn(0, 0, Vec::new());
+        scheduler.spawn(0, 0, Vec::new());
+        let _main = scheduler.pop(0).unwrap();
+        let process = scheduler.pop(0).unwrap();
+
+        scheduler.park(&worker, process.clone(), Wait::Timer, Some(5));
+        assert_eq!(scheduler.clock.now(), 0);
+        assert_eq!(scheduler.pending_timers.load(Ordering::SeqCst), 1);
+        assert_eq!(process.state.lock().unwrap().waiting, Some(Wait::Timer));
+
+        // A message does not wake a sleeping process.
+        scheduler.send(0, 1, 3);
+        assert_eq!(pid(&scheduler, 0), None);
+    }
+}
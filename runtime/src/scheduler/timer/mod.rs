@@ -0,0 +1,201 @@
+use std::{
+    sync::atomic::{AtomicI64, Ordering},
+    time::Instant,
+};
+
+/// Slots of the wheel, one per millisecond of a turn.
+const SLOTS: usize = 256;
+
+/// Milliseconds since the scheduler started. A virtual clock only moves when
+/// the scheduler advances it, so that programs waiting on timers run the same
+/// way every time.
+pub enum Clock {
+    Real(Instant),
+    Virtual(AtomicI64),
+}
+
+impl Clock {
+    pub fn new(virtual_clock: bool) -> Self {
+        if virtual_clock {
+            Self::Virtual(AtomicI64::new(0))
+        } else {
+            Self::Real(Instant::now())
+        }
+    }
+
+    pub fn now(&self) -> i64 {
+        match self {
+            Self::Real(start) => start.elapsed().as_millis() as i64,
+            Self::Virtual(now) => now.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Moves a virtual clock forward to `now`, a real one moves by itself and
+    /// returns `false`.
+    pub fn advance(&self, now: i64) -> bool {
+        match self {
+            Self::Real(_) => false,
+            Self::Virtual(clock) => {
+                clock.fetch_max(now, Ordering::SeqCst);
+                true
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Timer {
+    pub deadline: i64,
+    pub pid: usize,
+    /// Tells the timer apart from earlier ones of the same process, which were
+    /// cancelled.
+    pub id: u64,
+}
+
+/// Hashed timer wheel: a timer sits in the slot of its deadline and fires once
+/// the wheel turned past it. Timers further than a turn away stay in their
+/// slot until the turn they are due in.
+pub struct TimerWheel {
+    slots: Vec<Vec<Timer>>,
+    /// Millisecond every earlier timer fired by.
+    current: i64,
+    len: usize,
+    /// Earliest deadline of the timers in the wheel.
+    next: Option<i64>,
+}
+
+impl TimerWheel {
+    pub fn new() -> Self {
+        Self {
+            slots: vec![Vec::new(); SLOTS],
+            current: 0,
+            len: 0,
+            next: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// A timer already due is moved to the next tick, so that it fires when the
+    /// wheel turns next and [`TimerWheel::next_deadline`] is ahead of the wheel.
+    pub fn insert(&mut self, mut timer: Timer) {
+        timer.deadline = timer.deadline.max(self.current + 1);
+        self.slots[timer.deadline as usize % SLOTS].push(timer);
+        self.len += 1;
+        self.next = Some(
+            self.next
+                .map_or(timer.deadline, |next| next.min(timer.deadline)),
+        );
+    }
+
+    pub fn next_deadline(&self) -> Option<i64> {
+        self.next
+    }
+
+    /// Turns the wheel to `now` and returns the timers due by then.
+    pub fn advance(&mut self, now: i64) -> Vec<Timer> {
+        let mut fired = Vec::new();
+        if now <= self.current {
+            return fired;
+        }
+        let mut due = |slot: &mut Vec<Timer>| {
+            slot.retain(|timer| {
+                if timer.deadline <= now {
+                    fired.push(*timer);
+                    false
+                } else {
+                    true
+                }
+            })
+        };
+        if now - self.current >= SLOTS as i64 {
+            self.slots.iter_mut().for_each(due);
+        } else {
+            for tick in self.current + 1..=now {
+                due(&mut self.slots[tick as usize % SLOTS]);
+            }
+        }
+        self.current = now;
+        self.len -= fired.len();
+        // Only the timers left have to be looked at once the earliest one fired.
+        if self.next.is_some_and(|next| next <= now) {
+            self.next = self
+                .slots
+                .iter()
+                .flatten()
+                .map(|timer| timer.deadline)
+                .min();
+        }
+        fired
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SLOTS, Timer, TimerWheel};
+
+    fn timer(deadline: i64, pid: usize) -> Timer {
+        Timer {
+            deadline,
+            pid,
+            id: 0,
+        }
+    }
+
+    fn pids(timers: Vec<Timer>) -> Vec<usize> {
+        let mut pids = timers.iter().map(|timer| timer.pid).collect::<Vec<_>>();
+        pids.sort();
+        pids
+    }
+
+    #[test]
+    fn insert_moves_due_timers_to_the_next_tick() {
+        let mut wheel = TimerWheel::new();
+        assert!(wheel.is_empty());
+        assert_eq!(wheel.next_deadline(), None);
+
+        wheel.advance(10);
+        wheel.insert(timer(4, 1));
+        wheel.insert(timer(30, 2));
+        assert!(!wheel.is_empty());
+        assert_eq!(wheel.next_deadline(), Some(11));
+        assert_eq!(pids(wheel.advance(11)), vec![1]);
+        assert_eq!(wheel.next_deadline(), Some(30));
+    }
+
+    #[test]
+    fn advance_fires_timers_by_their_deadline() {
+        let mut wheel = TimerWheel::new();
+        wheel.insert(timer(5, 1));
+        wheel.insert(timer(5, 2));
+        wheel.insert(timer(9, 3));
+
+        assert!(wheel.advance(4).is_empty());
+        assert_eq!(pids(wheel.advance(5)), vec![1, 2]);
+        assert!(wheel.advance(5).is_empty());
+        assert_eq!(wheel.next_deadline(), Some(9));
+        assert_eq!(pids(wheel.advance(20)), vec![3]);
+        assert!(wheel.is_empty());
+        assert_eq!(wheel.next_deadline(), None);
+    }
+
+    #[test]
+    fn advance_across_wheel_turns() {
+        let mut wheel = TimerWheel::new();
+        let turn = SLOTS as i64;
+        // Same slot as the first timer, one turn later.
+        wheel.insert(timer(3, 1));
+        wheel.insert(timer(turn + 3, 2));
+        wheel.insert(timer(3 * turn + 7, 3));
+
+        assert_eq!(pids(wheel.advance(turn - 1)), vec![1]);
+        assert_eq!(wheel.next_deadline(), Some(turn + 3));
+        assert!(wheel.advance(turn + 2).is_empty());
+        assert_eq!(pids(wheel.advance(turn + 3)), vec![2]);
+        // A jump of more than a turn still fires every due timer once.
+        assert_eq!(pids(wheel.advance(5 * turn)), vec![3]);
+        assert!(wheel.is_empty());
+    }
+}
@@ -0,0 +1,1109 @@
+use crate::{
+    backend::Compiler,
+    frontend::{parser::parse, typeck::check},
+    middleware::{Expressions, LoweredModule},
+};
+use anyhow::{Result, anyhow, bail};
+use cranelift::{
+    jit::{JITBuilder, JITModule},
+    module::default_libcall_names,
+    native,
+    prelude::{
+        Configurable,
+        settings::{self},
+    },
+};
+
+/// Compiles `.uniq` sources straight into memory and runs them in-process,
+/// without an object file or a linker.
+pub type JitCompiler = Compiler<JITModule>;
+
+impl Default for Compiler<JITModule> {
+    fn default() -> Self {
+        Self::with_symbols([])
+    }
+}
+
+impl Compiler<JITModule> {
+    /// Resolves `symbols` before the runtime functions and the C library, for
+    /// instance to count the allocations of the generated code.
+    pub fn with_symbols(symbols: impl IntoIterator<Item = (&'static str, *const u8)>) -> Self {
+        Self::jit("speed_and_size", symbols).unwrap()
+    }
+
+    /// Compiles for the host with the cranelift `opt_level`, `none`, `speed`
+    /// or `speed_and_size`.
+    pub fn with_opt_level(opt_level: &str) -> Result<Self> {
+        Self::jit(opt_level, [])
+    }
+
+    fn jit(
+        opt_level: &str,
+        symbols: impl IntoIterator<Item = (&'static str, *const u8)>,
+    ) -> Result<Self> {
+        let mut flag_builder = settings::builder();
+        flag_builder.set("opt_level", opt_level)?;
+        flag_builder.set("use_colocated_libcalls", "false")?;
+        flag_builder.set("is_pic", "false")?;
+        let isa_builder =
+            native::builder().map_err(|msg| anyhow!("Host machine not supported: {msg}"))?;
+        let isa = isa_builder.finish(settings::Flags::new(flag_builder))?;
+        let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+        builder.symbols(
+            unicorn_runtime::FUNCTIONS
+                .iter()
+                .map(|function| (function.name, function.address)),
+        );
+        builder.symbols(unicorn_runtime::scheduler::SYMBOLS.iter().copied());
+        builder.symbols(
+            unicorn_runtime::scheduler::io::FUNCTIONS
+                .iter()
+                .map(|function| (format!("unicorn_{}", function.name), function.address)),
+        );
+        builder.symbols(symbols);
+        let module = JITModule::new(builder);
+
+        Ok(Self::new(module))
+    }
+
+    /// Translates `input`, finalizes the generated code and returns what `main` returned.
+    pub fn run(self, input: &str) -> Result<i64> {
+        let frontend_ast = parse(input)?;
+        let types = check(&frontend_ast)?;
+        let middleware_ast = Expressions::lower(frontend_ast, &types)?;
+
+        self.run_modules(vec![LoweredModule {
+            name: None,
+            expressions: middleware_ast,
+            imports: vec![],
+        }])
+    }
+
+    /// Translates the modules of a program into one image, ordered as by
+    /// [`LoweredModule::lower_all`], and runs its `main` like [`Compiler::run`].
+    pub fn run_modules(mut self, modules: Vec<LoweredModule>) -> Result<i64> {
+        let mut main_id = None;
+        for module in modules {
+            main_id = self.translate_module(module)?.or(main_id);
+        }
+        let Some(main_id) = main_id else {
+            bail!("Modules have no `main` to run")
+        };
+        self.module.finalize_definitions()?;
+
+        let main_ptr = self.module.get_finalized_function(main_id);
+        // SAFETY: `main` is emitted by `Compiler::translate` with the `() -> i64` signature.
+        let main: extern "C" fn() -> i64 = unsafe { std::mem::transmute(main_ptr) };
+        let result = main();
+
+        // SAFETY: no pointer into the JIT memory outlives this call.
+        unsafe { self.module.free_memory() };
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+
+    use crate::{
+        backend::jit::JitCompiler, diagnostics::Diagnostic, frontend::module::Resolver,
+        middleware::LoweredModule,
+    };
+
+    #[test]
+    fn jit_basic_return() {
+        assert_eq!(
+            JitCompiler::default().run("main: -> i64 { 20 }").unwrap(),
+            20
+        );
+    }
+
+    #[test]
+    fn jit_runtime_call() {
+        let code = r#"
+            main: -> i64 {
+                let a: i64 = 20
+                let b: i64 = add { a 22 }
+                b
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), 42);
+    }
+
+    #[test]
+    fn jit_arithmetic() {
+        let code = r#"
+            main: -> i64 {
+                let a: i64 = 7
+                let b: i64 = a * 6 - (a + 1) / 2 % 3
+                b
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), 41);
+    }
+
+    #[test]
+    fn jit_comparison_and_logic() {
+        let code = r#"
+            main: -> i64 {
+                let a: bool = 3 < 4 && 4 <= 4 && !(2 > 3) && 5 >= 5 && 1 != 2 && 7 == 7
+                let b: bool = 3 > 4 || 0
+                if a && !b { 10 } else { 0 }
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), 10);
+    }
+
+    #[test]
+    fn jit_logic_short_circuits() {
+        let code = r#"
+            main: -> i64 {
+                let b: i64 = 0
+                let x: i64 = if b != 0 && 10 / b > 1 { 1 } else { 2 }
+                let y: bool = b == 0 || 10 / b > 1
+                let z: bool = 3 && b || 7
+                if y && z { x * 10 + 1 } else { 0 }
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), 21);
+    }
+
+    #[test]
+    fn jit_floats() {
+        let code = r#"
+            main: -> i64 {
+                let a: f64 = 1.5
+                let b: f64 = a * 2e1 / 4.0 - 0.5
+                let c: f32 = 0.25
+                let d: f32 = c + c
+                stdprint { b }
+                if b == 7.0 && d > 0.4 && d < 0.6 { 1 } else { 0 }
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), 1);
+    }
+
+    #[test]
+    fn jit_negation() {
+        let code = r#"
+            main: -> i64 {
+                let a: i64 = -5
+                let b: i8 = -7 + 1
+                let c: f64 = -2.5
+                let d: i64 = -a * 10
+                if -c == 2.5 && -b == 6 && -(-b) == b { a - d } else { 0 }
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), -55);
+    }
+
+    #[test]
+    fn jit_bools_and_chars() {
+        let code = r#"
+            letter: -> char {
+                let yes: bool = true
+                let c: char = if yes != false && 'a' < 'b' { 'z' } else { '\n' }
+                stdprint { yes }
+                stdprint { c }
+                c
+            }
+            main: -> i64 {
+                let c: char = letter {}
+                if c == 'z' { 1 } else { 0 }
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), 1);
+    }
+
+    #[test]
+    fn jit_integer_widths_wrap() {
+        let code = r#"
+            main: -> u8 {
+                let a: u8 = 250
+                a + 10
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), 4);
+        let code = r#"
+            main: -> i8 {
+                let a: i8 = 127
+                a + 1
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), -128);
+    }
+
+    #[test]
+    fn jit_unsigned_operators() {
+        let code = r#"
+            main: -> u64 {
+                let a: u64 = 18446744073709551615
+                let b: u64 = a / 2
+                if a > 1 { b } else { 0 }
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), i64::MAX);
+    }
+
+    #[test]
+    fn jit_float_remainder() {
+        let diagnostic = diagnostic("main: -> i64 { let a: f64 = 1.5\n let b: f64 = a % 2.0\n 0 }");
+        assert_eq!(diagnostic.message, "Expected an integer, found `f64`");
+    }
+
+    #[test]
+    fn jit_literal_out_of_range() {
+        let diagnostic = diagnostic("main: -> i8 { let a: i8 = 300 a }");
+        assert_eq!(diagnostic.message, "Literal `300` does not fit in `i8`");
+    }
+
+    #[test]
+    fn jit_strings() {
+        let code = r#"
+            let greeting: str = "hello"
+            main: -> i64 {
+                stdprint { &greeting }
+                let s: str = "wörld"
+                stdprint { s }
+                str_len { &greeting } * 10 + str_len { s }
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), 56);
+    }
+
+    #[test]
+    fn jit_undefined_data() {
+        let diagnostic = diagnostic("main: -> i64 { let s: str = &greeting\n 0 }");
+        assert_eq!(diagnostic.message, "Data `greeting` is not defined");
+    }
+
+    #[test]
+    fn jit_structs() {
+        let code = r#"
+            type Point { x: i32, y: u8, visible: bool }
+            type Line { tag: i8, start: Point, end: Point, width: f64 }
+            len: l(Line) -> i32 { l.end.x - l.start.x }
+            main: -> i32 {
+                let a: Point = Point { x: -5, y: 200, visible: true }
+                let l: Line = Line {
+                    tag: 1,
+                    start: a,
+                    end: Point { visible: false, y: 1, x: 3 },
+                    width: 1.5
+                }
+                if l.start.visible && !l.end.visible && l.width > 1.0 && l.start.y == 200 {
+                    len { l }
+                } else {
+                    0
+                }
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), 8);
+    }
+
+    #[test]
+    fn jit_enums_and_match() {
+        let code = r#"
+            type Point { x: i64, y: i64 }
+            type Shape = Circle(i64, bool) | Rect(Point, i64) | Empty
+            type List = Cons(Shape, List) | Nil
+            area: s(Shape) -> i64 {
+                match s {
+                    Circle(r, big) => if big { 6 * r * r } else { 3 * r * r },
+                    Rect(p, scale) => p.x * p.y * (match Some(scale) { Some(k) => k, _ => 1 }),
+                    Empty => 0
+                }
+            }
+            total: l(List) acc(i64) -> i64 {
+                match l {
+                    Nil => acc,
+                    Cons(shape, rest) => total { rest acc + area { shape } }
+                }
+            }
+            type Option = Some(i64) | None
+            main: -> i64 {
+                let shapes: List = Cons(Circle(2, false), Cons(Circle(1, true), Cons(Rect(Point { x: 2, y: 3 }, 2), Cons(Empty, Nil))))
+                total { shapes 0 }
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), 30);
+    }
+
+    #[test]
+    fn jit_arrays() {
+        let code = r#"
+            sum: xs([i64]) -> i64 {
+                let total: i64 = 0
+                let i: i64 = 0
+                let n: i64 = len { xs }
+                while i < n {
+                    total = total + xs[i]
+                    i = i + 1
+                }
+                total
+            }
+            main: -> i64 {
+                let xs: [i64] = [1 2 add { 1 2 }]
+                let i: i64 = 4
+                while i <= 10 {
+                    push { xs i }
+                    i = i + 1
+                }
+                let empty: [u8] = []
+                push { empty 7 }
+                let nested: [[i64]] = [xs [8]]
+                let seven: i64 = if empty[0] == 7 { 10000 } else { 0 }
+                sum { xs } + len { nested } * 100 + nested[1][0] * 1000 + seven
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), 18255);
+    }
+
+    #[test]
+    fn jit_call_operands() {
+        let code = r#"
+            main: -> i64 {
+                let a: i64 = add { 1 2 } * add { 3 4 } + 1
+                a
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), 22);
+    }
+
+    #[test]
+    fn jit_if_else() {
+        let code = r#"
+            main: -> i64 {
+                let a: i64 = 5
+                let b: i64 = if a > 3 { let c: i64 = a * 2
+                    c + 1 } else { 0 }
+                let d: i64 = if a == 0 { 100 } else if a < 5 { 200 } else { let e: i64 = 3
+                    e }
+                let f: i64 = if b < 0 { 1 }
+                let g: i64 = add { if a { 10 } else { 20 } 1 }
+                b * 1000 + d * 100 + f * 10 + g
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), 11311);
+    }
+
+    #[test]
+    fn jit_if_branch_scope() {
+        let diagnostic = diagnostic("main: -> i64 { if 1 { let a: i64 = 1 } else { 2 }\n a }");
+        assert_eq!(diagnostic.message, "Variable `a` is not defined");
+    }
+
+    #[test]
+    fn jit_recursive_calls() {
+        let code = r#"
+            fib: n(i64) -> i64 {
+                if n < 2 { n } else { fib { n - 1 } + fib { n - 2 } }
+            }
+            main: -> i64 {
+                fib { 15 }
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), 610);
+    }
+
+    #[test]
+    fn jit_tail_calls() {
+        let code = r#"
+            count: n(i64) acc(i64) -> i64 {
+                if n == 0 { acc } else { count { n - 1 acc + 2 } }
+            }
+            main: -> i64 {
+                let a: i64 = count { 1000000 0 }
+                a % 251
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), 2000000 % 251);
+    }
+
+    #[test]
+    fn jit_nested_call_arguments() {
+        let code = r#"
+            twice: x(i64) -> i64 { add { x x } }
+            main: -> i64 {
+                add { add { 1 twice { 2 } } add { 3 4 } }
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), 12);
+    }
+
+    #[test]
+    fn jit_loops() {
+        let code = r#"
+            main: -> i64 {
+                let i: i64 = 0
+                let sum: i64 = 0
+                while i < 10 {
+                    i = i + 1
+                    sum = sum + i
+                }
+                let j: i64 = 0
+                loop {
+                    j = j + 1
+                    if j == 7 { break }
+                }
+                sum * 10 + j
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), 557);
+    }
+
+    #[test]
+    fn jit_preempted_process_resumes() {
+        // Far more blocks than one reduction budget, the scheduler switches
+        // away from the process and back at every budget.
+        let code = r#"
+            count: n(i64) acc(i64) -> i64 {
+                if n == 0 { acc } else { count { n - 1 acc + n } }
+            }
+            main: -> i64 {
+                let total: i64 = 0
+                let i: i64 = 0
+                while i < 1000 {
+                    total = total + count { i 0 }
+                    i = i + 1
+                }
+                total
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), 166_666_500);
+    }
+
+    #[test]
+    fn jit_break_outside_loop() {
+        let diagnostic = diagnostic("main: -> i64 { break }");
+        assert_eq!(diagnostic.message, "`break` outside of a loop");
+    }
+
+    #[test]
+    fn jit_nested_functions() {
+        let code = r#"
+            main: -> i64 {
+                let base: i64 = 100
+                offset: x(i64) -> i64 { x + base }
+                sum_to: n(i64) -> i64 {
+                    if n == 0 { 0 } else { n + sum_to { n - 1 } }
+                }
+                offset { sum_to { 4 } }
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), 110);
+    }
+
+    #[test]
+    fn jit_opt_levels() {
+        let code = "main: -> i64 { let x: i64 = 6\n x * 7 }";
+        for opt_level in ["none", "speed", "speed_and_size"] {
+            let compiler = JitCompiler::with_opt_level(opt_level).unwrap();
+            assert_eq!(compiler.run(code).unwrap(), 42);
+        }
+        assert!(JitCompiler::with_opt_level("fastest").is_err());
+    }
+
+    #[test]
+    fn jit_forward_calls() {
+        let code = r#"
+            main: -> i64 { double { 21 } }
+            double: x(i64) -> i64 { x * 2 }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), 42);
+    }
+
+    #[test]
+    fn jit_mutual_recursion() {
+        let code = r#"
+            is_even: n(i64) -> i64 { if n == 0 { 1 } else { is_odd { n - 1 } } }
+            is_odd: n(i64) -> i64 { if n == 0 { 0 } else { is_even { n - 1 } } }
+            main: -> i64 { is_even { 10 } * 10 + is_odd { 7 } }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), 11);
+    }
+
+    #[test]
+    fn jit_function_values() {
+        let code = r#"
+            apply: f(x(i64) -> i64) v(i64) -> i64 { f { v } }
+            double: x(i64) -> i64 { x * 2 }
+            main: -> i64 {
+                let k: i64 = 3
+                scale: x(i64) -> i64 { x * k }
+                let a: i64 = apply { double 5 }
+                let b: i64 = apply { scale 5 }
+                a * 100 + b
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), 1015);
+    }
+
+    thread_local! {
+        /// Blocks the generated code allocated and did not free yet, the code
+        /// runs on the thread of the test.
+        static LIVE_ALLOCATIONS: Cell<isize> = const { Cell::new(0) };
+    }
+
+    unsafe extern "C" {
+        fn malloc(size: usize) -> *mut u8;
+        fn free(ptr: *mut u8);
+    }
+
+    extern "C" fn counted_malloc(size: usize) -> *mut u8 {
+        LIVE_ALLOCATIONS.with(|live| live.set(live.get() + 1));
+        unsafe { malloc(size) }
+    }
+
+    extern "C" fn counted_free(ptr: *mut u8) {
+        LIVE_ALLOCATIONS.with(|live| live.set(live.get() - 1));
+        unsafe { free(ptr) }
+    }
+
+    /// Size of the arrays [`failing_malloc`] fails to allocate, 13 elements.
+    const FAILING_SIZE: usize = 13 * 8 + 16;
+
+    extern "C" fn failing_malloc(size: usize) -> *mut u8 {
+        match size {
+            FAILING_SIZE => std::ptr::null_mut(),
+            size => unsafe { malloc(size) },
+        }
+    }
+
+    #[test]
+    fn jit_allocation_failure_fails_process() {
+        let code = r#"
+            big: -> i64 {
+                let xs: [i64] = [1 2 3 4 5 6 7 8 9 10 11 12 13]
+                len { xs }
+            }
+            main: -> i64 {
+                let child: pid = spawn { big }
+                monitor { child }
+                let e: exit = receive
+                exit_reason { e }
+            }
+        "#;
+        let compiler = JitCompiler::with_symbols([("malloc", failing_malloc as *const u8)]);
+        assert_eq!(compiler.run(code).unwrap(), 6);
+    }
+
+    /// Runs `code` with counted `malloc` and `free` and returns its result and
+    /// how many of its allocations are left once it finished.
+    fn run_counting_allocations(code: &str) -> (i64, isize) {
+        LIVE_ALLOCATIONS.with(|live| live.set(0));
+        let compiler = JitCompiler::with_symbols([
+            ("malloc", counted_malloc as *const u8),
+            ("free", counted_free as *const u8),
+        ])
+        .with_threads(1);
+        let result = compiler.run(code).unwrap();
+        (result, LIVE_ALLOCATIONS.with(Cell::get))
+    }
+
+    #[test]
+    fn jit_frees_process_memory() {
+        let code = r#"
+            type Point { x: i64, y: i64 }
+            type List = Cons(Point, List) | Nil
+            sum: l(List) acc(i64) -> i64 {
+                match l {
+                    Nil => acc,
+                    Cons(p, rest) => sum { rest acc + p.x * p.y }
+                }
+            }
+            main: -> i64 {
+                let k: i64 = 2
+                scale: x(i64) -> i64 { x * k }
+                let xs: [i64] = [1]
+                let i: i64 = 0
+                while i < 20 {
+                    push { xs scale { i } }
+                    i = i + 1
+                }
+                let points: List = Cons(Point { x: 2, y: 3 }, Cons(Point { x: xs[20], y: 1 }, Nil))
+                sum { points len { xs } }
+            }
+        "#;
+        assert_eq!(run_counting_allocations(code), (65, 0));
+    }
+
+    #[test]
+    fn jit_processes_exchange_messages() {
+        let code = r#"
+            worker: parent(pid) -> i64 {
+                let total: i64 = 0
+                let n: i64 = receive
+                while n != 0 {
+                    total = total + n
+                    n = receive
+                }
+                send { parent total }
+                0
+            }
+            echo: -> i64 {
+                let from: pid = receive
+                send { from 7 }
+                0
+            }
+            main: -> i64 {
+                let w: pid = spawn { worker self {} }
+                let e: pid = spawn { echo }
+                send { e self {} }
+                let i: i64 = 1
+                while i <= 100 {
+                    send { w i }
+                    i = i + 1
+                }
+                send { w 0 }
+                let total: i64 = receive
+                let seven: i64 = receive
+                total + seven
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), 5057);
+    }
+
+    #[test]
+    fn jit_processes_run_on_several_threads() {
+        let code = r#"
+            sum: from(i64) to(i64) parent(pid) -> i64 {
+                let total: i64 = 0
+                let i: i64 = from
+                while i < to {
+                    total = total + i
+                    i = i + 1
+                }
+                send { parent total }
+                0
+            }
+            main: -> i64 {
+                let i: i64 = 0
+                while i < 16 {
+                    spawn { sum i * 1000 i * 1000 + 1000 self {} }
+                    i = i + 1
+                }
+                let total: i64 = 0
+                while i > 0 {
+                    let part: i64 = receive
+                    total = total + part
+                    i = i - 1
+                }
+                total
+            }
+        "#;
+        for _ in 0..10 {
+            let result = JitCompiler::default().with_threads(4).run(code).unwrap();
+            assert_eq!(result, 127_992_000);
+        }
+    }
+
+    #[test]
+    fn jit_busy_process_is_preempted() {
+        let code = r#"
+            spin: -> i64 {
+                loop { }
+                0
+            }
+            main: -> i64 {
+                spawn { spin }
+                let i: i64 = 0
+                while i < 100 { i = i + 1 }
+                i
+            }
+        "#;
+        for reductions in [1, 3, 2000] {
+            let compiler = JitCompiler::default()
+                .with_threads(1)
+                .with_reductions(reductions);
+            assert_eq!(compiler.run(code).unwrap(), 100);
+        }
+    }
+
+    #[test]
+    fn jit_single_thread_runs_processes_in_order() {
+        let code = r#"
+            report: parent(pid) id(i64) -> i64 {
+                send { parent id }
+                0
+            }
+            main: -> i64 {
+                spawn { report self {} 1 }
+                spawn { report self {} 2 }
+                spawn { report self {} 3 }
+                let first: i64 = receive
+                let second: i64 = receive
+                let third: i64 = receive
+                first * 100 + second * 10 + third
+            }
+        "#;
+        let result = JitCompiler::default().with_threads(1).run(code).unwrap();
+        assert_eq!(result, 123);
+    }
+
+    #[test]
+    fn jit_links_report_failures() {
+        // Only the failure reaches the links, the normal exit does not.
+        let code = r#"
+            crash: d(i64) -> i64 { 1 / d }
+            main: -> i64 {
+                let ok: pid = spawn { crash 1 }
+                let bad: pid = spawn { crash 0 }
+                link { ok }
+                link { bad }
+                let e: exit = receive
+                exit_reason { e }
+            }
+        "#;
+        for threads in [1, 4] {
+            let result = JitCompiler::default().with_threads(threads).run(code);
+            assert_eq!(result.unwrap(), 1);
+        }
+    }
+
+    #[test]
+    fn jit_division_overflow_fails_process() {
+        let code = r#"
+            wide: -> i64 {
+                let n: i64 = -9223372036854775807 - 1
+                let m: i64 = -1
+                n / m
+            }
+            narrow: -> i64 {
+                let n: i8 = -127 - 1
+                let m: i8 = -1
+                let q: i8 = n / m
+                0
+            }
+            main: -> i64 {
+                let a: pid = spawn { wide }
+                monitor { a }
+                let e: exit = receive
+                let b: pid = spawn { narrow }
+                monitor { b }
+                let f: exit = receive
+                exit_reason { e } * 10 + exit_reason { f }
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), 55);
+    }
+
+    #[test]
+    fn jit_monitors_report_exits() {
+        let code = r#"
+            echo: -> i64 {
+                let from: pid = receive
+                send { from 7 }
+                0
+            }
+            main: -> i64 {
+                let child: pid = spawn { echo }
+                monitor { child }
+                send { child self {} }
+                let seven: i64 = receive
+                let e: exit = receive
+                let again: pid = restart { exit_pid { e } }
+                send { again self {} }
+                let other: i64 = receive
+                exit_reason { e } * 100 + seven + other
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), 14);
+    }
+
+    #[test]
+    fn jit_sleeping_processes_wake_by_deadline() {
+        let code = r#"
+            report: parent(pid) id(i64) ms(i64) -> i64 {
+                sleep { ms }
+                send { parent id }
+                0
+            }
+            main: -> i64 {
+                spawn { report self {} 3 30 }
+                spawn { report self {} 1 10 }
+                spawn { report self {} 2 20 }
+                let first: i64 = receive
+                let second: i64 = receive
+                let third: i64 = receive
+                now_ms {} * 1000 + first * 100 + second * 10 + third
+            }
+        "#;
+        for threads in [1, 4] {
+            let compiler = JitCompiler::default()
+                .with_threads(threads)
+                .with_virtual_clock();
+            assert_eq!(compiler.run(code).unwrap(), 30_123);
+        }
+    }
+
+    #[test]
+    fn jit_receive_after_times_out() {
+        // Nothing arrives within 50ms, the message of `late` only after the
+        // second timeout.
+        let code = r#"
+            later: parent(pid) ms(i64) value(i64) -> i64 {
+                sleep { ms }
+                send { parent value }
+                0
+            }
+            main: -> i64 {
+                let none: i64 = receive after 50 { -1 }
+                spawn { later self {} 10 7 }
+                let seven: i64 = receive after 50 { -1 }
+                spawn { later self {} 100 8 }
+                let late: i64 = receive after 20 { -1 }
+                now_ms {} * 100 + seven * 10 + none + late + 2
+            }
+        "#;
+        for threads in [1, 4] {
+            let compiler = JitCompiler::default()
+                .with_threads(threads)
+                .with_virtual_clock();
+            assert_eq!(compiler.run(code).unwrap(), 8070);
+        }
+    }
+
+    #[test]
+    fn jit_virtual_clock_fires_due_timers() {
+        let code = r#"
+            main: -> i64 {
+                sleep { 0 }
+                let none: i64 = receive after 0 { 7 }
+                sleep { 0 }
+                none
+            }
+        "#;
+        for threads in [1, 4] {
+            let compiler = JitCompiler::default()
+                .with_threads(threads)
+                .with_virtual_clock();
+            assert_eq!(compiler.run(code).unwrap(), 7);
+        }
+    }
+
+    #[test]
+    fn jit_sleep_waits_on_the_real_clock() {
+        let code = r#"
+            main: -> i64 {
+                let start: i64 = now_ms {}
+                sleep { 20 }
+                let slept: i64 = now_ms {} - start
+                if slept >= 20 { 1 } else { 0 }
+            }
+        "#;
+        assert_eq!(JitCompiler::default().run(code).unwrap(), 1);
+    }
+
+    #[test]
+    fn jit_processes_own_sockets() {
+        // Every client and every handler waits on its own socket.
+        let code = r#"
+            handle: conn(i64) -> i64 {
+                let line: str = read { conn }
+                write { conn line }
+                close { conn }
+                0
+            }
+            serve: listener(i64) n(i64) -> i64 {
+                while n > 0 {
+                    let conn: i64 = accept { listener }
+                    spawn { handle conn }
+                    n = n - 1
+                }
+                0
+            }
+            client: port(i64) parent(pid) -> i64 {
+                let conn: i64 = connect { port }
+                write { conn "hello" }
+                let reply: str = read { conn }
+                close { conn }
+                send { parent str_len { reply } }
+                0
+            }
+            main: -> i64 {
+                let listener: i64 = listen { 0 }
+                let port: i64 = local_port { listener }
+                spawn { serve listener 200 }
+                let i: i64 = 0
+                while i < 200 {
+                    spawn { client port self {} }
+                    i = i + 1
+                }
+                let total: i64 = 0
+                while i > 0 {
+                    let n: i64 = receive
+                    total = total + n
+                    i = i - 1
+                }
+                close { listener }
+                total
+            }
+        "#;
+        for threads in [1, 4] {
+            let result = JitCompiler::default().with_threads(threads).run(code);
+            assert_eq!(result.unwrap(), 1000);
+        }
+    }
+
+    #[test]
+    fn jit_reads_written_files() {
+        let path = std::env::temp_dir().join(format!("unicorn-file-{}", std::process::id()));
+        let code = format!(
+            r#"
+            main: -> i64 {{
+                let written: i64 = write_file {{ "{path}" "hello\n" }}
+                let text: str = read_file {{ "{path}" }}
+                let missing: str = read_file {{ "{path}.missing" }}
+                written * 100 + str_len {{ text }} * 10 + str_len {{ missing }}
+            }}
+        "#,
+            path = path.display()
+        );
+        let result = JitCompiler::default().run(&code);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap(), 660);
+    }
+
+    #[test]
+    fn jit_modules() {
+        let dir = std::env::temp_dir().join(format!("unicorn-jit-modules-{}", std::process::id()));
+        let lib = dir.join("lib");
+        std::fs::create_dir_all(&lib).unwrap();
+        std::fs::write(
+            dir.join("math.uniq"),
+            r#"
+            module math
+            let name: str = "math"
+            square: x(i64) -> i64 { x * x }
+            sum_squares: a(i64) b(i64) -> i64 { square { a } + square { b } }
+            "#,
+        )
+        .unwrap();
+        std::fs::write(
+            lib.join("shapes.uniq"),
+            r#"
+            module shapes
+            import math
+            let name: str = "shapes"
+            area: side(i64) -> i64 { math::square { side } }
+            report: parent(pid) side(i64) -> nil { send { parent area { side } } }
+            "#,
+        )
+        .unwrap();
+        let code = r#"
+            import math
+            import shapes
+            square: x(i64) -> i64 { x }
+            apply: f(x(i64) -> i64) v(i64) -> i64 { f { v } }
+            main: -> i64 {
+                spawn { shapes::report self {} 6 }
+                let reported: i64 = receive
+                let a: i64 = math::sum_squares { 1 2 }
+                let b: i64 = apply { math::square 3 }
+                reported * 1000 + a * 100 + b * 10 + square { 4 }
+            }
+        "#;
+        let modules = Resolver::new(vec![lib])
+            .resolve(dir.join("main.uniq"), code.to_owned())
+            .unwrap();
+        let result = JitCompiler::default().run_modules(LoweredModule::lower_all(modules).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(result.unwrap(), 36594);
+    }
+
+    #[test]
+    fn jit_supervisor_restarts_failed_child() {
+        let code = include_str!("../../../examples/supervisor.uniq");
+        for threads in [1, 4] {
+            let result = JitCompiler::default().with_threads(threads).run(code);
+            assert_eq!(result.unwrap(), 1000);
+        }
+    }
+
+    #[test]
+    fn jit_frees_failed_process_memory() {
+        let code = r#"
+            get: xs([i64]) n(i64) -> i64 { xs[n] }
+            fail: n(i64) -> i64 {
+                let xs: [i64] = [1 2 3]
+                get { xs n }
+            }
+            main: -> i64 {
+                let child: pid = spawn { fail 5 }
+                monitor { child }
+                let e: exit = receive
+                exit_reason { e }
+            }
+        "#;
+        assert_eq!(run_counting_allocations(code), (2, 0));
+    }
+
+    #[test]
+    fn jit_frees_pending_processes_and_messages() {
+        // Main finishes while a process still waits and another one never
+        // reads its mailbox.
+        let code = r#"
+            waiter: -> i64 { receive }
+            idle: n(i64) -> i64 { n }
+            main: -> i64 {
+                let w: pid = spawn { waiter }
+                let i: pid = spawn { idle 1 }
+                send { i 2 }
+                send { i 3 }
+                42
+            }
+        "#;
+        assert_eq!(run_counting_allocations(code), (42, 0));
+    }
+
+    #[test]
+    fn jit_rejects_sending_structs() {
+        let code = r#"
+            type Point { x: i64, y: i64 }
+            main: -> i64 {
+                send { self {} Point { x: 1, y: 2 } }
+                0
+            }
+        "#;
+        assert_eq!(
+            diagnostic(code).message,
+            "`Point` values cannot be passed to another process"
+        );
+    }
+
+    fn diagnostic(code: &str) -> Diagnostic {
+        let err = JitCompiler::default().run(code).unwrap_err();
+        err.downcast::<Diagnostic>().unwrap()
+    }
+
+    #[test]
+    fn jit_undefined_variable() {
+        let code = "main: -> i64 { let a: i64 = b\n a }";
+        let diagnostic = diagnostic(code);
+        assert_eq!(diagnostic.message, "Variable `b` is not defined");
+        assert_eq!(&code[diagnostic.span.start..diagnostic.span.end], "b");
+    }
+
+    #[test]
+    fn jit_unknown_function() {
+        let diagnostic = diagnostic("main: -> i64 { let a: i64 = foo { 1 }\n a }");
+        assert_eq!(diagnostic.message, "Function `foo` is not defined");
+    }
+
+    #[test]
+    fn jit_arity_mismatch() {
+        let diagnostic = diagnostic("main: -> i64 { let a: i64 = add { 1 }\n a }");
+        assert_eq!(
+            diagnostic.message,
+            "Function `add` takes 2 arguments but 1 were given"
+        );
+    }
+
+    #[test]
+    fn jit_parse_error() {
+        let diagnostic = diagnostic("main: -> i64 { 20 ");
+        assert_eq!(diagnostic.span.start, 18);
+    }
+}
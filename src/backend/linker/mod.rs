@@ -0,0 +1,97 @@
+use anyhow::{Context, Result, bail};
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// File name of the `unicorn-runtime` static library.
+pub const RUNTIME_LIB: &str = "libunicorn_runtime.a";
+
+/// Links compiled objects with the runtime through the system C compiler driver.
+pub struct Linker {
+    /// C compiler driver, `$CC` or `cc`.
+    pub driver: String,
+    /// Linker handed to the driver as `-fuse-ld=<linker>`, the driver's default when `None`.
+    pub fuse_ld: Option<String>,
+    /// Runtime library, found by [`locate_runtime`] when `None`.
+    pub runtime: Option<PathBuf>,
+}
+
+impl Default for Linker {
+    fn default() -> Self {
+        Self {
+            driver: env::var("CC").unwrap_or_else(|_| String::from("cc")),
+            fuse_ld: None,
+            runtime: None,
+        }
+    }
+}
+
+impl Linker {
+    pub fn link(&self, objs: &[impl AsRef<Path>], exe: &Path) -> Result<()> {
+        let runtime = match &self.runtime {
+            Some(runtime) => runtime.clone(),
+            None => locate_runtime()?,
+        };
+
+        let mut command = Command::new(&self.driver);
+        if let Some(fuse_ld) = &self.fuse_ld {
+            command.arg(format!("-fuse-ld={fuse_ld}"));
+        }
+        let status = command
+            .args(["-no-pie", "-Wl,-s"])
+            .args(objs.iter().map(AsRef::as_ref))
+            .arg(&runtime)
+            .args(["-lpthread", "-ldl", "-lm"])
+            .arg("-o")
+            .arg(exe)
+            .status()
+            .with_context(|| format!("Failed to start the linker `{}`", self.driver))?;
+        if !status.success() {
+            bail!("Linker failed with code: {:?}", status.code())
+        }
+        Ok(())
+    }
+}
+
+/// Finds the runtime library: `$UNICORN_RUNTIME` first, then next to the running
+/// executable (where cargo puts it for the workspace), then in `../lib` of it,
+/// and finally a hashed `libunicorn_runtime-*.a` that cargo leaves in `deps`.
+pub fn locate_runtime() -> Result<PathBuf> {
+    if let Some(path) = env::var_os("UNICORN_RUNTIME") {
+        return Ok(PathBuf::from(path));
+    }
+
+    let exe = env::current_exe()?;
+    let mut candidates = vec![];
+    for dir in exe.ancestors().skip(1).take(2) {
+        candidates.push(dir.join(RUNTIME_LIB));
+        candidates.push(dir.join("lib").join(RUNTIME_LIB));
+    }
+
+    if let Some(deps) = exe.parent().and_then(|dir| dir.read_dir().ok()) {
+        let mut hashed = deps
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| {
+                        name.starts_with("libunicorn_runtime-") && name.ends_with(".a")
+                    })
+            })
+            .collect::<Vec<_>>();
+        hashed.sort();
+        candidates.extend(hashed);
+    }
+
+    candidates
+        .into_iter()
+        .find(|path| path.is_file())
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Cannot find `{RUNTIME_LIB}`, build it with `cargo build -p unicorn-runtime` \
+                 or point `UNICORN_RUNTIME` at it"
+            )
+        })
+}
@@ -1,38 +1,111 @@
-use anyhow::{Result, bail};
+use anyhow::{Result, anyhow, bail};
 use base64ct::{Base64, Encoding};
 use cranelift::{
-    codegen::{Context, ir::BlockArg},
+    codegen::{
+        Context,
+        ir::{BlockArg, Signature},
+        isa,
+    },
     frontend::Switch,
-    module::{FuncId, Linkage, Module, default_libcall_names},
-    native::{self, builder},
+    module::{DataDescription, DataId, FuncId, Linkage, Module, default_libcall_names},
+    native,
     object::{ObjectBuilder, ObjectModule},
     prelude::{
-        AbiParam, Block, Configurable, EntityRef, FunctionBuilder, FunctionBuilderContext,
-        InstBuilder, IntCC, MemFlags, TrapCode, Value, Variable,
+        AbiParam, Block, Configurable, FloatCC, FunctionBuilder, FunctionBuilderContext,
+        InstBuilder, IntCC, MemFlags, Value, Variable,
         settings::{self},
+        types,
     },
 };
-use std::{cell::RefCell, collections::HashMap, fs::write, path::Path, rc::Rc, vec};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    env,
+    fmt::Write,
+    fs::{create_dir_all, remove_dir_all, remove_file, write},
+    path::{Path, PathBuf},
+    process,
+    rc::Rc,
+    sync::atomic::{AtomicUsize, Ordering},
+    vec,
+};
+use unicorn_runtime::scheduler::EXIT_REASON_BITS;
 use whirlpool::{Digest, Whirlpool};
 
 use crate::{
-    frontend::parser::{ast::expr::Expr, parser},
-    general_compiler::{call_free, call_malloc},
-    middleware::{Expression, Expressions},
+    backend::linker::Linker,
+    diagnostics::{Diagnostic, Span},
+    frontend::parser::ast::expr::{BinaryOp, UnaryOp},
+    frontend::{
+        parser::parse,
+        typeck::{Type, check},
+    },
+    general_compiler::{
+        REDUCTIONS_LIMIT, allocation_failed, call_free, call_malloc,
+        trap::CompilerTrapCode,
+        type_def::{Field, TypeDef},
+    },
+    middleware::{Expression, Expressions, LoweredModule},
 };
 
-const PROCESS_CTX_BUFFER_SIZE: i64 = 48;
+pub mod jit;
+pub mod linker;
+
+const PROCESS_CTX_BUFFER_SIZE: i64 = 72;
 const PROCESS_CTX_VARS: i32 = 0;
 const PROCESS_CTX_VARS_LEN: i32 = 8;
 const PROCESS_CTX_FUNC_ADDR: i32 = 16;
 const PROCESS_CTX_TEMP_VAL: i32 = 24;
-const PROCESS_CTX_DEPENDENCIES: i32 = 32;
+/// Id of the process, its entry in the process table.
+const PROCESS_CTX_PID: i32 = 32;
 const PROCESS_CTX_CALL_ARGS_TEMP: i32 = 40;
-
-const RUNTIME_BUFFER_SIZE: i64 = 40;
+/// Context of the calling function, null for the root of a process.
+const PROCESS_CTX_CALLER: i32 = 48;
+/// Block of the caller to resume once this function returns.
+const PROCESS_CTX_RETURN_BLOCK: i32 = 56;
+/// Arena of the process, shared by the contexts of all its calls.
+const PROCESS_CTX_ARENA: i32 = 64;
+
+/// Heap values of a process are linked into its arena, a circular list of
+/// `[prev, next]` headers in front of every allocation with the arena itself
+/// as the sentinel, and are all freed once the process finishes.
+const ARENA_HEADER_SIZE: i64 = 16;
+const ARENA_PREV: i32 = 0;
+const ARENA_NEXT: i32 = 8;
+
+/// Arrays are pointers to a header with their length, the capacity of their
+/// buffer and the buffer, whose elements are slot-sized values.
+const ARRAY_HEADER_SIZE: i64 = 24;
+const ARRAY_LEN: i32 = 0;
+const ARRAY_CAP: i32 = 8;
+const ARRAY_DATA: i32 = 16;
+
+// The runtime buffer is the state of the scheduler thread running the
+// process, see `unicorn_runtime::scheduler::Worker`.
+/// Context whose function the turn calls next, switched by calls and returns.
+const RUNTIME_CURRENT_CTX: i32 = 0;
+/// Value of the root function of a process once it finished.
+const RUNTIME_RESULT: i32 = 8;
+/// Set by a process waiting for a message, so that the scheduler switches to
+/// the next process without waiting for the end of the reduction budget.
+const RUNTIME_YIELDED: i32 = 16;
+/// Reason the running process failed with, see [`CompilerTrapCode`].
+const RUNTIME_FAULT: i32 = 24;
+
+/// Declared function with the shape of its signature.
+#[derive(Debug, Clone, Copy)]
+pub struct FunctionEntry {
+    pub id: FuncId,
+    pub params: usize,
+    pub returns: usize,
+    /// Number of arguments taken at the call site.
+    pub arity: usize,
+    /// User function run as blocks of a process instead of a native call.
+    pub resumable: bool,
+}
 
 thread_local! {
-    pub static FUNCTIONS: Rc<RefCell<HashMap<String, (FuncId, usize, usize)>>>
+    pub static FUNCTIONS: Rc<RefCell<HashMap<String, FunctionEntry>>>
     = Rc::new(RefCell::new(HashMap::new()));
 }
 
@@ -49,191 +122,420 @@ struct TranslationContext {
     var_counter: usize,
     block_counter: usize,
     tr_type: TranslationType,
+    /// `break` blocks of the enclosing loops, filled in once the loop end is numbered.
+    loops: Vec<Vec<Block>>,
+    /// Function being translated, nested functions are lifted under its name.
+    function: String,
+}
+
+fn encode_function_name(name: &str) -> String {
+    let mut wp = Whirlpool::new();
+    Digest::update(&mut wp, name);
+    Base64::encode_string(&wp.finalize())
 }
 
+/// Root context of a new process running the function at `func_addr`, the
+/// first `vars_len` slots of `vars_ptr` are its arguments.
 fn create_process(
-    module: &mut ObjectModule,
+    module: &mut dyn Module,
     builder: &mut FunctionBuilder,
-    func_name: &str,
+    func_addr: Value,
+    vars_ptr: Value,
+    vars_len: Value,
 ) -> Value {
     let target_type = module.target_config().pointer_type();
     let buff = builder.ins().iconst(target_type, PROCESS_CTX_BUFFER_SIZE);
     let after_call = builder.create_block();
     builder.append_block_param(after_call, target_type);
-    call_malloc(module, builder, buff, after_call, &[]);
+    call_malloc(module, builder, buff, after_call, &[], None);
     builder.switch_to_block(after_call);
     builder.seal_block(after_call);
     let ctx_ptr = *builder.block_params(after_call).first().unwrap();
 
-    let after_call = builder.create_block();
-    builder.append_block_param(after_call, target_type);
-    let buff = builder.ins().iconst(target_type, 0);
-    call_malloc(module, builder, buff, after_call, &[]);
-    builder.switch_to_block(after_call);
-    builder.seal_block(after_call);
-
-    let vars_ptr = *builder.block_params(after_call).first().unwrap();
-    let len = builder.ins().iconst(target_type, 0);
     let zero = builder.ins().iconst(target_type, 0);
-
-    builder
-        .ins()
-        .store(MemFlags::new(), vars_ptr, ctx_ptr, PROCESS_CTX_VARS);
-    builder
-        .ins()
-        .store(MemFlags::new(), len, ctx_ptr, PROCESS_CTX_VARS_LEN);
-    builder
-        .ins()
-        .store(MemFlags::new(), zero, ctx_ptr, PROCESS_CTX_TEMP_VAL);
-
-    let mut wp = Whirlpool::new();
-    Digest::update(&mut wp, func_name);
-    let ident = Base64::encode_string(&wp.finalize());
-
-    let (func_id, _, _) = FUNCTIONS.with(|map| *map.borrow().get(&ident).unwrap());
-
-    let callee = module.declare_func_in_func(func_id, builder.func);
-    let func_addr = builder.ins().func_addr(target_type, callee);
-
-    builder
-        .ins()
-        .store(MemFlags::new(), func_addr, ctx_ptr, PROCESS_CTX_FUNC_ADDR);
-
+    let arena = create_arena(module, builder);
+
+    for (val, offset) in [
+        (vars_ptr, PROCESS_CTX_VARS),
+        (vars_len, PROCESS_CTX_VARS_LEN),
+        (func_addr, PROCESS_CTX_FUNC_ADDR),
+        (zero, PROCESS_CTX_TEMP_VAL),
+        (zero, PROCESS_CTX_PID),
+        (zero, PROCESS_CTX_CALL_ARGS_TEMP),
+        (zero, PROCESS_CTX_CALLER),
+        (zero, PROCESS_CTX_RETURN_BLOCK),
+        (arena, PROCESS_CTX_ARENA),
+    ] {
+        builder.ins().store(MemFlags::new(), val, ctx_ptr, offset);
+    }
     ctx_ptr
 }
 
-pub struct Compiler {
-    module: ObjectModule,
+pub struct Compiler<M: Module = ObjectModule> {
+    module: M,
+    clif: Option<String>,
+    /// Top-level data by name, see [`Compiler::translate_data`].
+    data: HashMap<String, DataId>,
+    /// Scheduler threads of the program, `0` to decide when it starts.
+    threads: usize,
+    virtual_clock: bool,
+    /// Blocks a process runs per turn of the scheduler.
+    reductions: i64,
+    /// Module being translated, whose functions are named `module::function`.
+    module_name: Option<String>,
 }
 
-impl Default for Compiler {
+impl Default for Compiler<ObjectModule> {
     fn default() -> Self {
+        Self::with_target(None, "speed_and_size").unwrap()
+    }
+}
+
+impl Compiler<ObjectModule> {
+    /// Creates an object compiler for `target` (a target triple, the host when `None`)
+    /// with the given Cranelift `opt_level` (`none`, `speed` or `speed_and_size`).
+    pub fn with_target(target: Option<&str>, opt_level: &str) -> Result<Self> {
         let mut flag_builder = settings::builder();
-        flag_builder.set("opt_level", "speed_and_size").unwrap();
-        flag_builder.set("use_colocated_libcalls", "false").unwrap();
-        flag_builder.set("is_pic", "false").unwrap();
-        let isa_builder =
-            native::builder().unwrap_or_else(|msg| panic!("Host machine not supported: {msg}"));
-        let isa = isa_builder
-            .finish(settings::Flags::new(flag_builder))
-            .unwrap();
-        let builder = ObjectBuilder::new(isa, "module", default_libcall_names()).unwrap();
+        flag_builder.set("opt_level", opt_level)?;
+        flag_builder.set("use_colocated_libcalls", "false")?;
+        flag_builder.set("is_pic", "false")?;
+        let isa_builder = match target {
+            Some(target) => isa::lookup_by_name(target)?,
+            None => {
+                native::builder().map_err(|msg| anyhow!("Host machine not supported: {msg}"))?
+            }
+        };
+        let isa = isa_builder.finish(settings::Flags::new(flag_builder))?;
+        let builder = ObjectBuilder::new(isa, "module", default_libcall_names())?;
         let module = ObjectModule::new(builder);
 
-        Self { module }
+        Ok(Self::new(module))
     }
-}
 
-impl Compiler {
-    pub fn compile<P: AsRef<Path>>(mut self, input: &str, path: P) -> Result<()> {
-        let frontend_ast = parser::exprs(input)?;
+    /// Fresh compiler for the same target and settings, to compile another module.
+    fn sibling(&self) -> Result<Self> {
+        let isa = self.module.isa();
+        let mut isa_builder = isa::lookup(isa.triple().clone())?;
+        for flag in isa.isa_flags() {
+            isa_builder.set(flag.name, &flag.value_string())?;
+        }
+        let isa = isa_builder.finish(isa.flags().clone())?;
+        let builder = ObjectBuilder::new(isa, "module", default_libcall_names())?;
+        let mut compiler = Self::new(ObjectModule::new(builder))
+            .with_threads(self.threads)
+            .with_reductions(self.reductions);
+        compiler.virtual_clock = self.virtual_clock;
+        Ok(compiler)
+    }
+
+    /// Compiles `module` on its own into a relocatable object file written to
+    /// `path`, the functions it imports are left for the linker.
+    pub fn compile_module<P: AsRef<Path>>(mut self, module: LoweredModule, path: P) -> Result<()> {
+        self.translate_module(module)?;
+        let obj = self.module.finish();
+        write(path, obj.emit()?)?;
+        Ok(())
+    }
+
+    /// Compiles every module of a program into an object file, the program to
+    /// `path` and the module `name` next to it as `<path stem>.<name>.o`.
+    /// Returns the paths of the objects.
+    pub fn compile_modules<P: AsRef<Path>>(
+        self,
+        modules: Vec<LoweredModule>,
+        path: P,
+    ) -> Result<Vec<PathBuf>> {
+        let path = path.as_ref();
+        let mut objs = vec![];
+        for module in modules {
+            let obj = match &module.name {
+                Some(name) => path.with_extension(format!("{name}.o")),
+                None => path.to_owned(),
+            };
+            let compiled = self.sibling()?.compile_module(module, &obj);
+            objs.push(obj);
+            if let Err(err) = compiled {
+                objs.iter().for_each(|obj| _ = remove_file(obj));
+                return Err(err);
+            }
+        }
+        Ok(objs)
+    }
+
+    /// Compiles the modules of a program like [`Compiler::compile_modules`] and
+    /// links their objects with the runtime into an executable at `path`.
+    pub fn compile_modules_executable<P: AsRef<Path>>(
+        self,
+        modules: Vec<LoweredModule>,
+        path: P,
+        linker: &Linker,
+    ) -> Result<()> {
+        let exe = path.as_ref();
+        let dir = object_dir()?;
+        let linked = self
+            .compile_modules(modules, dir.join(object_name(exe)))
+            .and_then(|objs| linker.link(&objs, exe));
+        _ = remove_dir_all(&dir);
+        linked
+    }
 
-        let middleware_ast = Expressions::from(frontend_ast);
-        println!("{middleware_ast:#?}");
+    /// Compiles `input` into a relocatable object file written to `path`.
+    pub fn compile<P: AsRef<Path>>(mut self, input: &str, path: P) -> Result<()> {
+        let frontend_ast = parse(input)?;
+        let types = check(&frontend_ast)?;
+        let middleware_ast = Expressions::lower(frontend_ast, &types)?;
 
         self.translate(middleware_ast)?;
         let obj = self.module.finish();
         let obj_bytes = obj.emit()?;
 
-        write(path.as_ref().join("obj.o"), obj_bytes)?;
+        write(path, obj_bytes)?;
         Ok(())
     }
 
+    /// Compiles `input` and links it with the runtime into an executable at `path`.
+    pub fn compile_executable<P: AsRef<Path>>(
+        self,
+        input: &str,
+        path: P,
+        linker: &Linker,
+    ) -> Result<()> {
+        let exe = path.as_ref();
+        let dir = object_dir()?;
+        let obj = dir.join(object_name(exe));
+        let linked = self
+            .compile(input, &obj)
+            .and_then(|()| linker.link(&[&obj], exe));
+        _ = remove_dir_all(&dir);
+        linked
+    }
+}
+
+/// Creates a fresh directory for the objects an executable is linked from, so
+/// that they do not replace files next to the executable.
+fn object_dir() -> Result<PathBuf> {
+    static DIRS: AtomicUsize = AtomicUsize::new(0);
+    let dir = env::temp_dir().join(format!(
+        "unicorn-objects-{}-{}",
+        process::id(),
+        DIRS.fetch_add(1, Ordering::Relaxed)
+    ));
+    create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// Name of the object of the program linked into `exe`.
+fn object_name(exe: &Path) -> PathBuf {
+    Path::new(exe.file_name().unwrap_or("main".as_ref())).with_extension("o")
+}
+
+impl<M: Module> Compiler<M> {
+    pub fn new(module: M) -> Self {
+        Self {
+            module,
+            clif: None,
+            data: HashMap::new(),
+            threads: 0,
+            virtual_clock: false,
+            reductions: REDUCTIONS_LIMIT,
+            module_name: None,
+        }
+    }
+
+    /// Runs the processes of the program on `threads` scheduler threads instead
+    /// of `$UNICORN_THREADS` or one per CPU. A single thread runs them in a
+    /// fixed order.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Lets a process run `reductions` blocks per turn of the scheduler instead
+    /// of [`REDUCTIONS_LIMIT`], fewer make the processes take turns more often.
+    pub fn with_reductions(mut self, reductions: i64) -> Self {
+        self.reductions = reductions.max(1);
+        self
+    }
+
+    /// Runs the program on a clock which only moves once every process waits,
+    /// right to the next timer, so that timers fire in the same order each run.
+    pub fn with_virtual_clock(mut self) -> Self {
+        self.virtual_clock = true;
+        self
+    }
+
+    /// Keeps the Cranelift IR of every translated function, see [`Compiler::clif`].
+    pub fn with_clif(mut self) -> Self {
+        self.clif = Some(String::new());
+        self
+    }
+
+    /// Cranelift IR collected so far when created [`Compiler::with_clif`].
+    pub fn clif(&self) -> Option<&str> {
+        self.clif.as_deref()
+    }
+
+    pub fn module(&self) -> &M {
+        &self.module
+    }
+
+    pub fn into_module(self) -> M {
+        self.module
+    }
+
+    /// `(block_index, ctx_ptr, runtime_ptr) -> next_block`, shared by every user function.
+    fn process_signature(&self) -> Signature {
+        let target_type = self.module.target_config().pointer_type();
+        let mut sig = self.module.make_signature();
+        sig.params.push(AbiParam::new(target_type));
+        sig.params.push(AbiParam::new(target_type));
+        sig.params.push(AbiParam::new(target_type));
+        sig.returns.push(AbiParam::new(target_type));
+        sig
+    }
+
     fn declare_runtime_funcitons(&mut self) -> Result<()> {
         let target_type = self.module.target_config().pointer_type();
-        let mut stdprint_sig = self.module.make_signature();
-        stdprint_sig.params.push(AbiParam::new(target_type));
-        let callee_stdprint =
-            self.module
-                .declare_function("stdprint", Linkage::Import, &stdprint_sig)?;
-        let mut wp = Whirlpool::new();
-        Digest::update(&mut wp, "stdprint");
-        let name = Base64::encode_string(&wp.finalize());
-        FUNCTIONS.with(|map| {
-            map.borrow_mut().insert(
-                name,
-                (
-                    callee_stdprint,
-                    stdprint_sig.params.len(),
-                    stdprint_sig.returns.len(),
-                ),
-            )
-        });
+        for function in unicorn_runtime::FUNCTIONS {
+            let mut sig = self.module.make_signature();
+            let params = function.params.len();
+            let returns = usize::from(function.returns.is_some());
+            for _ in 0..params {
+                sig.params.push(AbiParam::new(target_type));
+            }
+            for _ in 0..returns {
+                sig.returns.push(AbiParam::new(target_type));
+            }
+            let callee = self
+                .module
+                .declare_function(function.name, Linkage::Import, &sig)?;
+
+            let entry = FunctionEntry {
+                id: callee,
+                params,
+                returns,
+                arity: params,
+                resumable: false,
+            };
+            FUNCTIONS.with(|map| {
+                map.borrow_mut()
+                    .insert(encode_function_name(function.name), entry)
+            });
+        }
+        Ok(())
+    }
 
-        let mut add_sig = self.module.make_signature();
-        add_sig.params.push(AbiParam::new(target_type));
-        add_sig.params.push(AbiParam::new(target_type));
-        add_sig.returns.push(AbiParam::new(target_type));
-        let callee_add = self
-            .module
-            .declare_function("add", Linkage::Import, &add_sig)?;
-        let mut wp = Whirlpool::new();
-        Digest::update(&mut wp, "add");
-        let name = Base64::encode_string(&wp.finalize());
+    /// `name` qualified with the module being translated.
+    fn qualify(&self, name: &str) -> String {
+        match &self.module_name {
+            Some(module) => format!("{module}::{name}"),
+            None => name.to_owned(),
+        }
+    }
+
+    /// Function `name` of the module being translated, or of the runtime or an
+    /// imported module.
+    fn function(&self, name: &str) -> Option<FunctionEntry> {
         FUNCTIONS.with(|map| {
-            map.borrow_mut().insert(
-                name,
-                (callee_add, add_sig.params.len(), add_sig.returns.len()),
-            )
-        });
+            let map = map.borrow();
+            map.get(&encode_function_name(&self.qualify(name)))
+                .or_else(|| map.get(&encode_function_name(name)))
+                .copied()
+        })
+    }
 
-        let mut now_sig = self.module.make_signature();
-        now_sig.returns.push(AbiParam::new(target_type));
-        let callee_now = self
+    /// Declares `name` as a process function taking `arity` arguments, so that
+    /// calls can be translated before it is defined.
+    fn declare_process_function(
+        &mut self,
+        name: &str,
+        arity: usize,
+        linkage: Linkage,
+    ) -> Result<FuncId> {
+        let sig = self.process_signature();
+        let encoded_function_name = encode_function_name(name);
+        let id = self
             .module
-            .declare_function("now", Linkage::Import, &now_sig)?;
-        let mut wp = Whirlpool::new();
-        Digest::update(&mut wp, "now");
-        let name = Base64::encode_string(&wp.finalize());
-        FUNCTIONS.with(|map| {
-            map.borrow_mut().insert(
-                name,
-                (callee_now, now_sig.params.len(), now_sig.returns.len()),
-            )
-        });
+            .declare_function(&encoded_function_name, linkage, &sig)?;
+        let entry = FunctionEntry {
+            id,
+            params: sig.params.len(),
+            returns: sig.returns.len(),
+            arity,
+            resumable: true,
+        };
+        FUNCTIONS.with(|map| map.borrow_mut().insert(encoded_function_name, entry));
+        Ok(id)
+    }
 
-        let mut elapsed_sig = self.module.make_signature();
-        elapsed_sig.params.push(AbiParam::new(target_type));
-        elapsed_sig.returns.push(AbiParam::new(target_type));
-        let callee_elapsed =
-            self.module
-                .declare_function("elapsed", Linkage::Import, &elapsed_sig)?;
-        let mut wp = Whirlpool::new();
-        Digest::update(&mut wp, "elapsed");
-        let name = Base64::encode_string(&wp.finalize());
-        FUNCTIONS.with(|map| {
-            map.borrow_mut().insert(
-                name,
-                (
-                    callee_elapsed,
-                    elapsed_sig.params.len(),
-                    elapsed_sig.returns.len(),
-                ),
-            )
-        });
-        Ok(())
+    /// Translates a program, see [`Compiler::translate_module`].
+    pub fn translate(&mut self, expressions: Expressions) -> Result<FuncId> {
+        let module = LoweredModule {
+            name: None,
+            expressions,
+            imports: vec![],
+        };
+        Ok(self.translate_module(module)?.unwrap())
     }
 
-    pub fn translate(&mut self, expressions: Expressions) -> Result<()> {
+    /// Translates the functions of `module`, and the `main` entry of the
+    /// executable when it is the program rather than a named module.
+    pub fn translate_module(&mut self, module: LoweredModule) -> Result<Option<FuncId>> {
         let target_type = self.module.target_config().pointer_type();
         let mut builder_ctx = FunctionBuilderContext::new();
         let mut ctx = self.module.make_context();
 
+        FUNCTIONS.with(|map| map.borrow_mut().clear());
+        self.data.clear();
+        self.module_name = module.name;
         self.declare_runtime_funcitons()?;
-
-        for expression in expressions.0 {
+        // Imported functions are defined by the objects of their own modules.
+        for (name, arity) in &module.imports {
+            self.declare_process_function(name, *arity, Linkage::Import)?;
+        }
+        let expressions = module.expressions;
+
+        // Data comes first, so that every function can take its address.
+        let (data, functions): (Vec<_>, Vec<_>) = expressions
+            .0
+            .into_iter()
+            .partition(|expression| matches!(expression, Expression::Assign(..)));
+        for expression in data {
+            self.translate_data(expression)?;
+        }
+        // Every function is declared before any body is translated, so that calls
+        // do not depend on the order of the definitions.
+        for expression in &functions {
+            if let Expression::Function {
+                name, function_ty, ..
+            } = expression
+                && let Expression::Ident(name, _) = &**name
+            {
+                let arity = match &**function_ty {
+                    Expression::FunctionType { params, .. } => params.len(),
+                    _ => 0,
+                };
+                self.declare_process_function(&self.qualify(name), arity, Linkage::Export)?;
+            }
+        }
+        for expression in functions {
             self.translate_function(expression, &mut builder_ctx, &mut ctx)?;
         }
+        if self.module_name.is_some() {
+            return Ok(None);
+        }
 
-        let mut builder = FunctionBuilder::new(&mut ctx.func, &mut builder_ctx);
-
-        let mut sig = self.module.make_signature();
-        sig.params.push(AbiParam::new(target_type));
-        sig.params.push(AbiParam::new(target_type));
-        sig.returns.push(AbiParam::new(target_type));
-
-        let sig_ref = builder.import_signature(sig);
+        // The main process is the first one, its value is the one of the program.
+        let Some(main) =
+            FUNCTIONS.with(|map| map.borrow().get(&encode_function_name("main")).copied())
+        else {
+            bail!("Function `main` is not defined")
+        };
+        let create_process = self.define_create_process(&mut builder_ctx, &mut ctx)?;
+        let run_slice = self.define_run_slice(&mut builder_ctx, &mut ctx)?;
+        let free_process = self.define_free_process(&mut builder_ctx, &mut ctx)?;
 
+        let mut builder = FunctionBuilder::new(&mut ctx.func, &mut builder_ctx);
         builder
             .func
             .signature
@@ -243,132 +545,445 @@ impl Compiler {
         builder.switch_to_block(entry_block);
         builder.seal_block(entry_block);
 
-        let runtime_process_array_size = builder.ins().iconst(target_type, RUNTIME_BUFFER_SIZE);
-        let runtime_process_array_len = builder.ins().iconst(target_type, 5);
-        let runtime_process_len_var = builder.declare_var(target_type);
-        builder.def_var(runtime_process_len_var, runtime_process_array_len);
+        let mut args = vec![];
+        for function in [main.id, create_process, run_slice, free_process] {
+            let function = self.module.declare_func_in_func(function, builder.func);
+            args.push(builder.ins().func_addr(target_type, function));
+        }
+        args.push(builder.ins().iconst(target_type, self.threads as i64));
+        args.push(builder.ins().iconst(target_type, self.virtual_clock as i64));
+        let result =
+            call_scheduler(&mut self.module, &mut builder, "unicorn_start", &args, true).unwrap();
+        builder.ins().return_(&[result]);
+        builder.seal_all_blocks();
+
+        let sig = builder.func.signature.clone();
+        builder.finalize();
+
+        let id = self
+            .module
+            .declare_function("main", Linkage::Export, &sig)?;
+        self.module.define_function(id, &mut ctx)?;
+
+        if let Some(clif) = &mut self.clif {
+            writeln!(clif, "{}", ctx.func)?;
+        }
+
+        self.module.clear_context(&mut ctx);
+        Ok(Some(id))
+    }
+
+    /// `(function, args_ptr, args_len, pid) -> ctx_ptr` creating the root context
+    /// of a process for the scheduler, see [`create_process`]. The arguments are
+    /// copied into the variables of the process.
+    fn define_create_process(
+        &mut self,
+        builder_ctx: &mut FunctionBuilderContext,
+        ctx: &mut Context,
+    ) -> Result<FuncId> {
+        let target_type = self.module.target_config().pointer_type();
+        let mut builder = FunctionBuilder::new(&mut ctx.func, builder_ctx);
+        for _ in 0..4 {
+            builder
+                .func
+                .signature
+                .params
+                .push(AbiParam::new(target_type));
+        }
+        builder
+            .func
+            .signature
+            .returns
+            .push(AbiParam::new(target_type));
+
+        let entry_block = builder.create_block();
+        let copy_block = builder.create_block();
+        let store_block = builder.create_block();
+        let create_block = builder.create_block();
+        builder.append_block_params_for_function_params(entry_block);
+        builder.append_block_param(copy_block, target_type);
 
+        builder.switch_to_block(entry_block);
+        let &[function, args_ptr, args_len, pid] = builder.block_params(entry_block) else {
+            unreachable!()
+        };
+        // Calls take the same room behind the arguments, see `BeforeCall`.
+        let size = builder.ins().iadd_imm(args_len, 2);
+        let size = builder.ins().imul_imm(size, 8);
         let after_call = builder.create_block();
         builder.append_block_param(after_call, target_type);
-        call_malloc(
-            &mut self.module,
-            &mut builder,
-            runtime_process_array_size,
-            after_call,
-            &[],
-        );
+        call_malloc(&mut self.module, &mut builder, size, after_call, &[], None);
         builder.switch_to_block(after_call);
-        builder.seal_block(after_call);
+        let vars_ptr = *builder.block_params(after_call).first().unwrap();
+        let zero = builder.ins().iconst(target_type, 0);
+        builder.ins().jump(copy_block, &[BlockArg::Value(zero)]);
 
-        let runtime_ptr = *builder.block_params(after_call).first().unwrap();
-        let runtime_var = builder.declare_var(target_type);
-        builder.def_var(runtime_var, runtime_ptr);
+        builder.switch_to_block(copy_block);
+        let index = *builder.block_params(copy_block).first().unwrap();
+        let more = builder.ins().icmp(IntCC::SignedLessThan, index, args_len);
+        builder
+            .ins()
+            .brif(more, store_block, &[], create_block, &[]);
+
+        builder.switch_to_block(store_block);
+        let offset = builder.ins().ishl_imm(index, 3);
+        let from = builder.ins().iadd(args_ptr, offset);
+        let to = builder.ins().iadd(vars_ptr, offset);
+        let val = builder.ins().load(target_type, MemFlags::new(), from, 0);
+        builder.ins().store(MemFlags::new(), val, to, 0);
+        let index = builder.ins().iadd_imm(index, 1);
+        builder.ins().jump(copy_block, &[BlockArg::Value(index)]);
+
+        builder.switch_to_block(create_block);
+        let ctx_ptr = create_process(&mut self.module, &mut builder, function, vars_ptr, args_len);
+        builder
+            .ins()
+            .store(MemFlags::new(), pid, ctx_ptr, PROCESS_CTX_PID);
+        builder.ins().return_(&[ctx_ptr]);
+        builder.seal_all_blocks();
+
+        let sig = builder.func.signature.clone();
+        builder.finalize();
+        let id = self
+            .module
+            .declare_function("unicorn_create_process", Linkage::Local, &sig)?;
+        self.module.define_function(id, ctx)?;
+        if let Some(clif) = &mut self.clif {
+            writeln!(clif, "{}", ctx.func)?;
+        }
+        self.module.clear_context(ctx);
+        Ok(id)
+    }
 
-        let main_process_ctx = create_process(&mut self.module, &mut builder, "main");
+    /// `(ctx_ptr, next_block, runtime_ptr) -> next_block` running a process for
+    /// one turn of the scheduler: at most [`Compiler::with_reductions`] blocks, fewer when
+    /// it finishes or waits for a message. Calls and returns switch the context
+    /// the turn ends in, which is left in the runtime buffer.
+    fn define_run_slice(
+        &mut self,
+        builder_ctx: &mut FunctionBuilderContext,
+        ctx: &mut Context,
+    ) -> Result<FuncId> {
+        let target_type = self.module.target_config().pointer_type();
+        let mut builder = FunctionBuilder::new(&mut ctx.func, builder_ctx);
+        let sig = self.process_signature();
+        let sig_ref = builder.import_signature(sig);
+        for _ in 0..3 {
+            builder
+                .func
+                .signature
+                .params
+                .push(AbiParam::new(target_type));
+        }
+        builder
+            .func
+            .signature
+            .returns
+            .push(AbiParam::new(target_type));
 
-        let while_block_entry = builder.create_block();
-        let condition_block = builder.create_block();
+        let entry_block = builder.create_block();
         let action_block = builder.create_block();
-        let exit_block = builder.create_block();
+        let budget_block = builder.create_block();
+        let continue_block = builder.create_block();
+        let return_block = builder.create_block();
+        builder.append_block_params_for_function_params(entry_block);
+        builder.append_block_param(return_block, target_type);
 
+        builder.switch_to_block(entry_block);
+        let &[ctx_ptr, next_block, runtime_ptr] = builder.block_params(entry_block) else {
+            unreachable!()
+        };
         let ctx_ptr_var = builder.declare_var(target_type);
-        builder.def_var(ctx_ptr_var, main_process_ctx);
-        builder.ins().jump(while_block_entry, &[]);
-
-        builder.switch_to_block(while_block_entry);
-        builder.seal_block(while_block_entry);
-
         let next_block_var = builder.declare_var(target_type);
-        let zero = builder.ins().iconst(target_type, 0);
-        builder.def_var(next_block_var, zero);
-
-        builder.ins().jump(condition_block, &[]);
-
-        builder.switch_to_block(condition_block);
-        let next_block = builder.use_var(next_block_var);
-        let cond = builder.ins().icmp_imm(IntCC::NotEqual, next_block, -1);
-        builder.ins().brif(cond, action_block, &[], exit_block, &[]);
+        let reductions_var = builder.declare_var(target_type);
+        builder
+            .ins()
+            .store(MemFlags::new(), ctx_ptr, runtime_ptr, RUNTIME_CURRENT_CTX);
+        let reductions = builder.ins().iconst(target_type, self.reductions);
+        builder.def_var(ctx_ptr_var, ctx_ptr);
+        builder.def_var(next_block_var, next_block);
+        builder.def_var(reductions_var, reductions);
+        builder.ins().jump(action_block, &[]);
 
+        // Every resumable block is a reduction.
         builder.switch_to_block(action_block);
-        builder.seal_block(action_block);
-
         let ctx_ptr = builder.use_var(ctx_ptr_var);
-
         let callee =
             builder
                 .ins()
                 .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_FUNC_ADDR);
-
         let next_block = builder.use_var(next_block_var);
-
-        let call = builder
-            .ins()
-            .call_indirect(sig_ref, callee, &[next_block, ctx_ptr]);
-
+        let call =
+            builder
+                .ins()
+                .call_indirect(sig_ref, callee, &[next_block, ctx_ptr, runtime_ptr]);
         let next_block = *builder.inst_results(call).first().unwrap();
-
+        let ctx_ptr = builder.ins().load(
+            target_type,
+            MemFlags::new(),
+            runtime_ptr,
+            RUNTIME_CURRENT_CTX,
+        );
+        let reductions = builder.use_var(reductions_var);
+        let reductions = builder.ins().iadd_imm(reductions, -1);
+        builder.def_var(ctx_ptr_var, ctx_ptr);
         builder.def_var(next_block_var, next_block);
-        builder.ins().jump(condition_block, &[]);
-        builder.seal_block(condition_block);
+        builder.def_var(reductions_var, reductions);
+        let finished = builder.ins().icmp_imm(IntCC::Equal, next_block, -1);
+        builder.ins().brif(
+            finished,
+            return_block,
+            &[BlockArg::Value(next_block)],
+            budget_block,
+            &[],
+        );
 
-        builder.switch_to_block(exit_block);
-        builder.seal_block(exit_block);
+        builder.switch_to_block(budget_block);
+        let yielded =
+            builder
+                .ins()
+                .load(target_type, MemFlags::new(), runtime_ptr, RUNTIME_YIELDED);
+        builder.ins().brif(
+            yielded,
+            return_block,
+            &[BlockArg::Value(next_block)],
+            continue_block,
+            &[],
+        );
 
-        let ret = builder
-            .ins()
-            .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_TEMP_VAL);
+        builder.switch_to_block(continue_block);
+        builder.ins().brif(
+            reductions,
+            action_block,
+            &[],
+            return_block,
+            &[BlockArg::Value(next_block)],
+        );
 
-        builder.ins().return_(&[ret]);
+        builder.switch_to_block(return_block);
+        let next_block = *builder.block_params(return_block).first().unwrap();
+        builder.ins().return_(&[next_block]);
+        builder.seal_all_blocks();
 
         let sig = builder.func.signature.clone();
         builder.finalize();
-
         let id = self
             .module
-            .declare_function("main", Linkage::Export, &sig)?;
-        self.module.define_function(id, &mut ctx)?;
-
-        println!("{}", ctx.func);
-
-        self.module.clear_context(&mut ctx);
-        Ok(())
+            .declare_function("unicorn_run_slice", Linkage::Local, &sig)?;
+        self.module.define_function(id, ctx)?;
+        if let Some(clif) = &mut self.clif {
+            writeln!(clif, "{}", ctx.func)?;
+        }
+        self.module.clear_context(ctx);
+        Ok(id)
     }
 
-    pub fn translate_function(
+    /// `(ctx_ptr)` freeing a process which did not finish when the program
+    /// ends: the contexts of its pending calls with their variables and its arena.
+    fn define_free_process(
         &mut self,
-        expression: Expression,
         builder_ctx: &mut FunctionBuilderContext,
         ctx: &mut Context,
     ) -> Result<FuncId> {
         let target_type = self.module.target_config().pointer_type();
         let mut builder = FunctionBuilder::new(&mut ctx.func, builder_ctx);
-        let mut translation_ctx = TranslationContext::default();
-
-        let Expression::Function { name, body, .. } = expression else {
-            bail!("Not a function!")
-        };
-
-        builder
-            .func
-            .signature
-            .params
-            .push(AbiParam::new(target_type));
-        builder
-            .func
-            .signature
-            .params
-            .push(AbiParam::new(target_type));
         builder
             .func
             .signature
             .params
             .push(AbiParam::new(target_type));
 
-        builder
-            .func
-            .signature
-            .returns
-            .push(AbiParam::new(target_type));
+        let entry_block = builder.create_block();
+        let frame_block = builder.create_block();
+        let arena_block = builder.create_block();
+        builder.append_block_params_for_function_params(entry_block);
+        builder.append_block_param(frame_block, target_type);
+        builder.append_block_param(arena_block, target_type);
+
+        builder.switch_to_block(entry_block);
+        let ctx_ptr = *builder.block_params(entry_block).first().unwrap();
+        builder.ins().jump(frame_block, &[BlockArg::Value(ctx_ptr)]);
+
+        // Every call of the process shares the arena of its root context.
+        builder.switch_to_block(frame_block);
+        let ctx_ptr = *builder.block_params(frame_block).first().unwrap();
+        let arena = builder
+            .ins()
+            .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_ARENA);
+        let caller_ptr =
+            builder
+                .ins()
+                .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_CALLER);
+        let vars_ptr = builder
+            .ins()
+            .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_VARS);
+        call_free(&mut self.module, &mut builder, vars_ptr);
+        call_free(&mut self.module, &mut builder, ctx_ptr);
+        builder.ins().brif(
+            caller_ptr,
+            frame_block,
+            &[BlockArg::Value(caller_ptr)],
+            arena_block,
+            &[BlockArg::Value(arena)],
+        );
+
+        builder.switch_to_block(arena_block);
+        let arena = *builder.block_params(arena_block).first().unwrap();
+        free_arena(&mut self.module, &mut builder, arena);
+        builder.ins().return_(&[]);
+        builder.seal_all_blocks();
+
+        let sig = builder.func.signature.clone();
+        builder.finalize();
+        let id = self
+            .module
+            .declare_function("unicorn_free_process", Linkage::Local, &sig)?;
+        self.module.define_function(id, ctx)?;
+        if let Some(clif) = &mut self.clif {
+            writeln!(clif, "{}", ctx.func)?;
+        }
+        self.module.clear_context(ctx);
+        Ok(id)
+    }
+
+    /// Defines a top-level `let name: str = ".."` as read-only data.
+    pub fn translate_data(&mut self, expression: Expression) -> Result<DataId> {
+        let span = expression.span();
+        let Expression::Assign((name, _), value, _) = expression else {
+            return Err(Diagnostic::error(span, "Expected a data definition").into());
+        };
+        let Expression::Ident(name, _) = *name else {
+            return Err(Diagnostic::error(name.span(), "Expected a variable name").into());
+        };
+        let Expression::Str(value, _) = *value else {
+            return Err(
+                Diagnostic::error(value.span(), "Top-level data must be a string literal").into(),
+            );
+        };
+
+        let id = self.define_str(Some(&name), value)?;
+        self.data.insert(name, id);
+        Ok(id)
+    }
+
+    /// Emits the NUL-terminated bytes of a string, anonymous unless it is top-level data.
+    fn define_str(&mut self, name: Option<&str>, value: String) -> Result<DataId> {
+        let id = match name {
+            Some(name) => {
+                let name = format!("data::{}", self.qualify(name));
+                self.module
+                    .declare_data(&name, Linkage::Local, false, false)?
+            }
+            None => self.module.declare_anonymous_data(false, false)?,
+        };
+        let mut bytes = value.into_bytes();
+        bytes.push(0);
+        let mut description = DataDescription::new();
+        description.define(bytes.into_boxed_slice());
+        self.module.define_data(id, &description)?;
+        Ok(id)
+    }
+
+    pub fn translate_function(
+        &mut self,
+        expression: Expression,
+        builder_ctx: &mut FunctionBuilderContext,
+        ctx: &mut Context,
+    ) -> Result<FuncId> {
+        let span = expression.span();
+        let Expression::Function {
+            name,
+            function_ty,
+            body,
+            ..
+        } = expression
+        else {
+            return Err(Diagnostic::error(
+                span,
+                "Only functions and data are allowed at the top level",
+            )
+            .into());
+        };
+        let Expression::Ident(name, _) = *name else {
+            return Err(
+                Diagnostic::error(name.span(), "Function name must be an identifier").into(),
+            );
+        };
+
+        let name = self.qualify(&name);
+        self.define_function(&name, *function_ty, body, None, builder_ctx, ctx)
+    }
+
+    /// Defines `name` as a process function. A lifted nested function gets its own
+    /// name and the variables it captures in `closure`: it is only called through
+    /// its closure record, which the caller passes right after the arguments.
+    fn define_function(
+        &mut self,
+        name: &str,
+        function_ty: Expression,
+        body: Expressions,
+        closure: Option<(&str, &[String])>,
+        builder_ctx: &mut FunctionBuilderContext,
+        ctx: &mut Context,
+    ) -> Result<FuncId> {
+        let target_type = self.module.target_config().pointer_type();
+        let mut translation_ctx = TranslationContext {
+            function: name.to_owned(),
+            ..TranslationContext::default()
+        };
+
+        // Parameters are the first variables, the caller hands its arguments buffer
+        // over as the variables buffer of the new context.
+        let params = match function_ty {
+            Expression::FunctionType { params, .. } => params,
+            _ => vec![],
+        };
+        let mut param_names = Vec::with_capacity(params.len());
+        for (param, _) in &params {
+            let Expression::Ident(param, _) = param else {
+                return Err(Diagnostic::error(
+                    param.span(),
+                    "Parameter name must be an identifier",
+                )
+                .into());
+            };
+            param_names.push(param.clone());
+        }
+        translation_ctx.var_counter = params.len();
+
+        // The closure record comes after the arguments and its captured values are
+        // copied right behind it, the function itself is reachable through the record.
+        let captures = closure.map_or(0, |(_, captures)| captures.len());
+        if let Some((own_name, captured)) = closure {
+            for (i, name) in captured.iter().enumerate() {
+                translation_ctx
+                    .variables
+                    .insert(name.clone(), params.len() + 1 + i);
+            }
+            translation_ctx
+                .variables
+                .insert(own_name.to_owned(), params.len());
+            translation_ctx.var_counter += 1 + captures;
+        }
+        for (i, param) in param_names.into_iter().enumerate() {
+            translation_ctx.variables.insert(param, i);
+        }
+
+        // Top-level functions are declared by `translate_module` before any body is
+        // translated, a lifted function is declared here so that it can call itself.
+        let declared = FUNCTIONS.with(|map| {
+            map.borrow()
+                .get(&encode_function_name(name))
+                .map(|entry| entry.id)
+        });
+        let id = match (closure, declared) {
+            (None, Some(id)) => id,
+            _ => self.declare_process_function(name, params.len(), Linkage::Export)?,
+        };
+        let sig = self.process_signature();
+
+        let mut builder = FunctionBuilder::new(&mut ctx.func, builder_ctx);
+        builder.func.signature = sig;
 
         let block0 = builder.create_block();
         let switch_block = builder.create_block();
@@ -393,7 +1008,19 @@ impl Compiler {
 
         let mut switch = Switch::new();
 
-        let mut last_block_i = 0;
+        if closure.is_some() {
+            let prologue = self.closure_prologue(
+                &mut builder,
+                ctx_ptr_var,
+                runtime_var,
+                params.len(),
+                captures,
+                &mut translation_ctx,
+            );
+            switch.set_entry(0, prologue);
+        }
+
+        let mut last_block_i = translation_ctx.block_counter;
         for expression in body.0 {
             let (indecies, last_block, blocks) = self.translate_expression(
                 expression,
@@ -411,46 +1038,91 @@ impl Compiler {
         }
 
         let final_block = builder.create_block();
+        let return_block = builder.create_block();
+        let root_block = builder.create_block();
         builder.switch_to_block(final_block);
+        let ctx_ptr = builder.use_var(ctx_ptr_var);
+        let caller_ptr =
+            builder
+                .ins()
+                .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_CALLER);
+        builder
+            .ins()
+            .brif(caller_ptr, return_block, &[], root_block, &[]);
+        switch.set_entry(last_block_i as u128, final_block);
+
+        // Hands the result to the caller, switches the driver back to it and
+        // resumes it after the call.
+        builder.switch_to_block(return_block);
+        let val = builder
+            .ins()
+            .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_TEMP_VAL);
+        builder
+            .ins()
+            .store(MemFlags::new(), val, caller_ptr, PROCESS_CTX_TEMP_VAL);
+        let runtime_ptr = builder.use_var(runtime_var);
+        builder.ins().store(
+            MemFlags::new(),
+            caller_ptr,
+            runtime_ptr,
+            RUNTIME_CURRENT_CTX,
+        );
+        let next_block = builder.ins().load(
+            target_type,
+            MemFlags::new(),
+            ctx_ptr,
+            PROCESS_CTX_RETURN_BLOCK,
+        );
+        let vars_ptr = builder
+            .ins()
+            .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_VARS);
+        call_free(&mut self.module, &mut builder, vars_ptr);
+        call_free(&mut self.module, &mut builder, ctx_ptr);
+        builder.ins().return_(&[next_block]);
+
+        // The process finished, its value outlives it in the runtime and
+        // everything it owned is freed.
+        builder.switch_to_block(root_block);
+        let val = builder
+            .ins()
+            .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_TEMP_VAL);
+        let runtime_ptr = builder.use_var(runtime_var);
+        builder
+            .ins()
+            .store(MemFlags::new(), val, runtime_ptr, RUNTIME_RESULT);
+        let vars_ptr = builder
+            .ins()
+            .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_VARS);
+        call_free(&mut self.module, &mut builder, vars_ptr);
+        let arena = builder
+            .ins()
+            .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_ARENA);
+        free_arena(&mut self.module, &mut builder, arena);
+        call_free(&mut self.module, &mut builder, ctx_ptr);
         let neg = builder.ins().iconst(target_type, -1);
         builder.ins().return_(&[neg]);
-        switch.set_entry(last_block_i as u128, final_block);
 
         builder.switch_to_block(switch_block);
 
         switch.emit(&mut builder, block_index, trap_block);
 
         builder.switch_to_block(trap_block);
-        builder.ins().trap(TrapCode::user(25).unwrap());
+        let runtime_ptr = builder.use_var(runtime_var);
+        fault(
+            &mut builder,
+            target_type,
+            runtime_ptr,
+            CompilerTrapCode::EndOfBlocks,
+        );
         builder.seal_all_blocks();
-
-        let sig = builder.func.signature.clone();
-
         builder.finalize();
 
-        let mut wp = Whirlpool::default();
-
-        let Expression::Ident(name) = *name else {
-            bail!("Not a ident")
-        };
-
-        Digest::update(&mut wp, name.as_bytes());
-        let encoded_function_name = Base64::encode_string(&wp.finalize());
-
-        let id = self
-            .module
-            .declare_function(&encoded_function_name, Linkage::Export, &sig)?;
         self.module.define_function(id, ctx)?;
 
-        FUNCTIONS.with(|map| {
-            map.borrow_mut().insert(
-                encoded_function_name.clone(),
-                (id, sig.params.len(), sig.returns.len()),
-            );
-        });
+        if let Some(clif) = &mut self.clif {
+            writeln!(clif, "{}", ctx.func)?;
+        }
 
-        println!("{}", ctx.func);
-        println!("{:?}", translation_ctx.variables);
         self.module.clear_context(ctx);
         Ok(id)
     }
@@ -465,7 +1137,7 @@ impl Compiler {
     ) -> Result<(Vec<usize>, usize, Vec<Block>)> {
         let target_type = self.module.target_config().pointer_type();
         match expression {
-            Expression::Lit(lit) => {
+            Expression::Lit(lit, _) => {
                 let b = builder.create_block();
                 builder.switch_to_block(b);
                 let ctx_ptr: Value = builder.use_var(ctx_ptr_var);
@@ -501,11 +1173,31 @@ impl Compiler {
 
                 Ok((vec![block_count], translation_ctx.block_counter, vec![b]))
             }
-            Expression::Ident(name) => {
+            Expression::Ident(name, span) => {
+                let Some(&val_index) = translation_ctx.variables.get(&name) else {
+                    // A user function used as a value is a closure without captures.
+                    if let Some(function) = self.function(&name)
+                        && function.resumable
+                    {
+                        let (index, b) = self.translate_closure(
+                            function.id,
+                            &[],
+                            builder,
+                            ctx_ptr_var,
+                            runtime_var,
+                            translation_ctx,
+                        );
+                        return Ok((vec![index], translation_ctx.block_counter, vec![b]));
+                    }
+                    return Err(Diagnostic::error(
+                        span,
+                        format!("Variable `{name}` is not defined"),
+                    )
+                    .into());
+                };
                 let b = builder.create_block();
                 builder.switch_to_block(b);
                 let ctx_ptr: Value = builder.use_var(ctx_ptr_var);
-                let val_index = *translation_ctx.variables.get(&name).unwrap();
 
                 let vars_ptr =
                     builder
@@ -549,17 +1241,42 @@ impl Compiler {
 
                 Ok((vec![block_count], translation_ctx.block_counter, vec![b]))
             }
-            Expression::BeforeCall(args_len) => {
+            Expression::BeforeCall(args_len, _) => {
                 let b = builder.create_block();
                 builder.switch_to_block(b);
                 let after_call = builder.create_block();
                 builder.append_block_param(after_call, target_type);
-                let buffer_size = builder.ins().iconst(target_type, (args_len * 8) as i64);
-                call_malloc(&mut self.module, builder, buffer_size, after_call, &[]);
+                // Past the arguments there is a slot for the closure record of the
+                // callee and one keeping the buffer of an enclosing call, which the
+                // call restores once it has consumed its own arguments.
+                let buffer_size = builder
+                    .ins()
+                    .iconst(target_type, ((args_len + 2) * 8) as i64);
+                let runtime_ptr = builder.use_var(runtime_var);
+                call_malloc(
+                    &mut self.module,
+                    builder,
+                    buffer_size,
+                    after_call,
+                    &[],
+                    Some(runtime_ptr),
+                );
                 builder.switch_to_block(after_call);
                 let args_ptr = *builder.block_params(after_call).first().unwrap();
 
                 let ctx_ptr = builder.use_var(ctx_ptr_var);
+                let outer_args_ptr = builder.ins().load(
+                    target_type,
+                    MemFlags::new(),
+                    ctx_ptr,
+                    PROCESS_CTX_CALL_ARGS_TEMP,
+                );
+                builder.ins().store(
+                    MemFlags::new(),
+                    outer_args_ptr,
+                    args_ptr,
+                    ((args_len + 1) * 8) as i32,
+                );
                 builder.ins().store(
                     MemFlags::new(),
                     args_ptr,
@@ -576,13 +1293,108 @@ impl Compiler {
 
                 Ok((vec![block_count], translation_ctx.block_counter, vec![b]))
             }
-            Expression::Call { ident, args } => {
+            Expression::Call { ident, args, span } => self.translate_call(
+                *ident,
+                args,
+                span,
+                false,
+                builder,
+                ctx_ptr_var,
+                runtime_var,
+                translation_ctx,
+            ),
+            Expression::Function {
+                name,
+                function_ty,
+                body,
+                span,
+            } => {
+                let Expression::Ident(name, _) = *name else {
+                    return Err(Diagnostic::error(
+                        name.span(),
+                        "Function name must be an identifier",
+                    )
+                    .into());
+                };
+
+                // Lambda lifting: every visible variable the body refers to is
+                // copied into the closure record when the definition is reached.
+                let mut referenced = HashSet::new();
+                body.idents(&mut referenced);
+                let mut captures = translation_ctx
+                    .variables
+                    .iter()
+                    .filter(|(name, _)| referenced.contains(*name))
+                    .map(|(name, slot)| (name.clone(), *slot))
+                    .collect::<Vec<_>>();
+                captures.sort_by_key(|(_, slot)| *slot);
+                let (captured_names, captured_slots): (Vec<_>, Vec<_>) =
+                    captures.into_iter().unzip();
+
+                let lifted_name = format!("{}::{name}@{}", translation_ctx.function, span.start);
+                let mut lifted_builder_ctx = FunctionBuilderContext::new();
+                let mut lifted_ctx = self.module.make_context();
+                let id = self.define_function(
+                    &lifted_name,
+                    *function_ty,
+                    body,
+                    Some((&name, &captured_names)),
+                    &mut lifted_builder_ctx,
+                    &mut lifted_ctx,
+                )?;
+
+                let tr_type = translation_ctx.tr_type;
+                translation_ctx.tr_type = TranslationType::Default;
+                let (closure_index, closure_block) = self.translate_closure(
+                    id,
+                    &captured_slots,
+                    builder,
+                    ctx_ptr_var,
+                    runtime_var,
+                    translation_ctx,
+                );
+                translation_ctx.tr_type = tr_type;
+                let (bind_index, bind_block) =
+                    self.bind_variable(name, builder, ctx_ptr_var, runtime_var, translation_ctx);
+
+                Ok((
+                    vec![closure_index, bind_index],
+                    translation_ctx.block_counter,
+                    vec![closure_block, bind_block],
+                ))
+            }
+            Expression::FunctionType { span, .. } => {
+                Err(Diagnostic::error(span, "A function type is not a value").into())
+            }
+            Expression::Assign((name, _), expr, _) => {
+                let Expression::Ident(name, _) = *name else {
+                    return Err(Diagnostic::error(name.span(), "Expected a variable name").into());
+                };
+                let tr_type = translation_ctx.tr_type;
+                translation_ctx.tr_type = TranslationType::Default;
+                let (indecies, _, blocks) = self.translate_expression(
+                    *expr,
+                    builder,
+                    ctx_ptr_var,
+                    runtime_var,
+                    translation_ctx,
+                )?;
+                translation_ctx.tr_type = tr_type;
+
+                let (block_index, b) =
+                    self.bind_variable(name, builder, ctx_ptr_var, runtime_var, translation_ctx);
+
+                Ok((
+                    [indecies, vec![block_index]].concat(),
+                    translation_ctx.block_counter,
+                    [blocks, vec![b]].concat(),
+                ))
+            }
+            Expression::Block(body, _) => {
                 let mut indecies = vec![];
                 let mut blocks = vec![];
-                let args_len = args.0.len();
-                for (i, expression) in args.0.into_iter().enumerate() {
-                    translation_ctx.tr_type = TranslationType::Call(i);
-                    let (indecies_, last_block_, blocks_) = self.translate_expression(
+                for expression in body.0 {
+                    let (indecies_, _, blocks_) = self.translate_expression(
                         expression,
                         builder,
                         ctx_ptr_var,
@@ -592,75 +1404,32 @@ impl Compiler {
                     indecies = [indecies, indecies_].concat();
                     blocks = [blocks, blocks_].concat();
                 }
-                translation_ctx.tr_type = TranslationType::Default;
                 let b = builder.create_block();
                 builder.switch_to_block(b);
 
                 let ctx_ptr = builder.use_var(ctx_ptr_var);
-                let args_ptr = builder.ins().load(
-                    target_type,
-                    MemFlags::new(),
-                    ctx_ptr,
-                    PROCESS_CTX_CALL_ARGS_TEMP,
-                );
-
-                let mut wp = Whirlpool::new();
-
-                let Expression::Ident(name) = *ident else {
-                    bail!("Not a ident")
-                };
-
-                Digest::update(&mut wp, name.as_bytes());
-                let encoded_function_name = Base64::encode_string(&wp.finalize());
-
-                let mut sig = self.module.make_signature();
-                let (func_id, params, returns) =
-                    FUNCTIONS.with(|map| *map.borrow().get(&encoded_function_name).unwrap());
-                for _ in 0..params {
-                    sig.params.push(AbiParam::new(target_type));
-                }
-                for _ in 0..returns {
-                    sig.returns.push(AbiParam::new(target_type));
-                }
-
-                let callee = self.module.declare_func_in_func(func_id, builder.func);
-                let callee = builder.ins().func_addr(target_type, callee);
-                let sig_ref = builder.import_signature(sig);
 
-                let mut args_vals = Vec::with_capacity(args_len);
-
-                for i in 0..args_len {
-                    args_vals.push(builder.ins().load(
+                if let TranslationType::Call(arg_i) = translation_ctx.tr_type {
+                    let res_of_block = builder.ins().load(
+                        target_type,
+                        MemFlags::new(),
+                        ctx_ptr,
+                        PROCESS_CTX_TEMP_VAL,
+                    );
+                    let args_ptr = builder.ins().load(
                         target_type,
                         MemFlags::new(),
+                        ctx_ptr,
+                        PROCESS_CTX_CALL_ARGS_TEMP,
+                    );
+                    builder.ins().store(
+                        MemFlags::new(),
+                        res_of_block,
                         args_ptr,
-                        (i * 8) as i32,
-                    ));
-                }
-
-                let call = builder.ins().call_indirect(sig_ref, callee, &args_vals);
-
-                call_free(&mut self.module, builder, args_ptr);
-                let zero = builder.ins().iconst(target_type, 0);
-                builder
-                    .ins()
-                    .store(MemFlags::new(), zero, ctx_ptr, PROCESS_CTX_CALL_ARGS_TEMP);
-
-                let res: Option<&Value> = builder.inst_results(call).first();
-                if let Some(res) = res {
-                    let res = *res;
-                    builder
-                        .ins()
-                        .store(MemFlags::new(), res, ctx_ptr, PROCESS_CTX_TEMP_VAL);
+                        (arg_i * 8) as i32,
+                    );
                 }
 
-                /*let deps_ptr = builder.ins().load(
-                target_type,
-                MemFlags::new(),
-                ctx_ptr,
-                PROCESS_CTX_DEPENDENCIES,
-                );*/
-
                 let block_count = translation_ctx.block_counter;
 
                 let block_count_val = builder.ins().iconst(target_type, (block_count + 1) as i64);
@@ -674,169 +1443,1888 @@ impl Compiler {
                     [blocks, vec![b]].concat(),
                 ))
             }
-            Expression::Function {
-                name,
-                function_ty,
-                body,
-            } => todo!(),
-            Expression::FunctionType { params, ret_ty } => todo!(),
-            Expression::Assign((name, _), expr) => {
+            Expression::If {
+                cond,
+                then,
+                otherwise,
+                ..
+            } => {
                 let tr_type = translation_ctx.tr_type;
                 translation_ctx.tr_type = TranslationType::Default;
-                let (indecies, block_index, blocks) = self.translate_expression(
-                    *expr,
+                let (mut indecies, _, mut blocks) = self.translate_expression(
+                    *cond,
+                    builder,
+                    ctx_ptr_var,
+                    runtime_var,
+                    translation_ctx,
+                )?;
+
+                // Jump targets are only known once both branches are numbered,
+                // so the branch and the exit of `then` are filled in afterwards.
+                let branch_index = translation_ctx.block_counter;
+                translation_ctx.block_counter += 1;
+
+                let (then_indecies, then_blocks) = self.translate_branch(
+                    then,
+                    builder,
+                    ctx_ptr_var,
+                    runtime_var,
+                    translation_ctx,
+                )?;
+                let then_exit_index = translation_ctx.block_counter;
+                translation_ctx.block_counter += 1;
+
+                let else_index = translation_ctx.block_counter;
+                let (else_indecies, else_blocks) = self.translate_branch(
+                    otherwise,
                     builder,
                     ctx_ptr_var,
                     runtime_var,
                     translation_ctx,
                 )?;
+                let end_index = translation_ctx.block_counter;
                 translation_ctx.tr_type = tr_type;
-                let b = builder.create_block();
-                builder.switch_to_block(b);
 
+                let branch = builder.create_block();
+                builder.switch_to_block(branch);
                 let ctx_ptr = builder.use_var(ctx_ptr_var);
-                let old_vars_ptr =
-                    builder
-                        .ins()
-                        .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_VARS);
-                let old_vars_ptr_len =
+                let cond =
                     builder
                         .ins()
-                        .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_VARS_LEN);
+                        .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_TEMP_VAL);
+                let then_index = builder.ins().iconst(target_type, (branch_index + 1) as i64);
+                let else_index = builder.ins().iconst(target_type, else_index as i64);
+                let next_block = builder.ins().select(cond, then_index, else_index);
+                builder.ins().return_(&[next_block]);
 
-                let new_len = builder.ins().iadd_imm(old_vars_ptr_len, 1);
-                let new_buffer_size = builder.ins().imul_imm(new_len, 8);
+                let then_exit = builder.create_block();
+                builder.switch_to_block(then_exit);
+                let next_block = builder.ins().iconst(target_type, end_index as i64);
+                builder.ins().return_(&[next_block]);
 
-                let new_len_var = builder.declare_var(target_type);
-                builder.def_var(new_len_var, new_len);
+                let b = builder.create_block();
+                builder.switch_to_block(b);
+                let ctx_ptr = builder.use_var(ctx_ptr_var);
+                let val =
+                    builder
+                        .ins()
+                        .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_TEMP_VAL);
+                self.store_value(builder, ctx_ptr, val, translation_ctx);
+                let next_block = builder.ins().iconst(target_type, (end_index + 1) as i64);
+                builder.ins().return_(&[next_block]);
+                translation_ctx.block_counter += 1;
 
-                let after_realloc = builder.create_block();
-                builder.append_block_param(after_realloc, target_type);
-                call_realloc(
-                    &mut self.module,
+                indecies.push(branch_index);
+                indecies.extend(then_indecies);
+                indecies.push(then_exit_index);
+                indecies.extend(else_indecies);
+                indecies.push(end_index);
+                blocks.push(branch);
+                blocks.extend(then_blocks);
+                blocks.push(then_exit);
+                blocks.extend(else_blocks);
+                blocks.push(b);
+
+                Ok((indecies, translation_ctx.block_counter, blocks))
+            }
+            Expression::ReturnCall { ident, args, span } => self.translate_call(
+                *ident,
+                args,
+                span,
+                true,
+                builder,
+                ctx_ptr_var,
+                runtime_var,
+                translation_ctx,
+            ),
+            Expression::Loop(body, _) => {
+                let tr_type = translation_ctx.tr_type;
+                let start_index = translation_ctx.block_counter;
+                translation_ctx.loops.push(vec![]);
+                let (mut indecies, mut blocks) = self.translate_branch(
+                    body,
                     builder,
-                    old_vars_ptr,
-                    new_buffer_size,
-                    after_realloc,
-                    &[],
+                    ctx_ptr_var,
+                    runtime_var,
+                    translation_ctx,
+                )?;
+                translation_ctx.tr_type = tr_type;
+
+                let back_edge = builder.create_block();
+                builder.switch_to_block(back_edge);
+                let next_block = builder.ins().iconst(target_type, start_index as i64);
+                builder.ins().return_(&[next_block]);
+                indecies.push(translation_ctx.block_counter);
+                blocks.push(back_edge);
+                translation_ctx.block_counter += 1;
+
+                let end_index = translation_ctx.block_counter;
+                for break_block in translation_ctx.loops.pop().unwrap_or_default() {
+                    builder.switch_to_block(break_block);
+                    let next_block = builder.ins().iconst(target_type, end_index as i64);
+                    builder.ins().return_(&[next_block]);
+                }
+
+                let b = builder.create_block();
+                builder.switch_to_block(b);
+                let ctx_ptr = builder.use_var(ctx_ptr_var);
+                let zero = builder.ins().iconst(target_type, 0);
+                self.store_value(builder, ctx_ptr, zero, translation_ctx);
+                let next_block = builder.ins().iconst(target_type, (end_index + 1) as i64);
+                builder.ins().return_(&[next_block]);
+                indecies.push(end_index);
+                blocks.push(b);
+                translation_ctx.block_counter += 1;
+
+                Ok((indecies, translation_ctx.block_counter, blocks))
+            }
+            Expression::Break(span) => {
+                let b = builder.create_block();
+                let Some(breaks) = translation_ctx.loops.last_mut() else {
+                    return Err(Diagnostic::error(span, "`break` outside of a loop").into());
+                };
+                breaks.push(b);
+
+                let block_count = translation_ctx.block_counter;
+                translation_ctx.block_counter += 1;
+
+                Ok((vec![block_count], translation_ctx.block_counter, vec![b]))
+            }
+            Expression::Set(name, expr, _) => {
+                let Expression::Ident(name, span) = *name else {
+                    return Err(Diagnostic::error(name.span(), "Expected a variable name").into());
+                };
+                let Some(&slot) = translation_ctx.variables.get(&name) else {
+                    return Err(Diagnostic::error(
+                        span,
+                        format!("Variable `{name}` is not defined"),
+                    )
+                    .into());
+                };
+
+                let tr_type = translation_ctx.tr_type;
+                translation_ctx.tr_type = TranslationType::Default;
+                let (indecies, _, blocks) = self.translate_expression(
+                    *expr,
+                    builder,
+                    ctx_ptr_var,
+                    runtime_var,
+                    translation_ctx,
+                )?;
+                translation_ctx.tr_type = tr_type;
+
+                let b = builder.create_block();
+                builder.switch_to_block(b);
+                let ctx_ptr = builder.use_var(ctx_ptr_var);
+                let val =
+                    builder
+                        .ins()
+                        .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_TEMP_VAL);
+                let vars_ptr =
+                    builder
+                        .ins()
+                        .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_VARS);
+                builder
+                    .ins()
+                    .store(MemFlags::new(), val, vars_ptr, (slot * 8) as i32);
+                self.store_value(builder, ctx_ptr, val, translation_ctx);
+
+                let block_count = translation_ctx.block_counter;
+                let next_block = builder.ins().iconst(target_type, (block_count + 1) as i64);
+                builder.ins().return_(&[next_block]);
+                translation_ctx.block_counter += 1;
+
+                Ok((
+                    [indecies, vec![block_count]].concat(),
+                    translation_ctx.block_counter,
+                    [blocks, vec![b]].concat(),
+                ))
+            }
+            Expression::FFICall { span, .. } => {
+                Err(Diagnostic::error(span, "FFI calls are not supported yet").into())
+            }
+            Expression::Struct { ty, fields, span } => self.translate_struct(
+                type_def(&ty),
+                fields,
+                span,
+                builder,
+                ctx_ptr_var,
+                runtime_var,
+                translation_ctx,
+            ),
+            Expression::Field {
+                ty,
+                expr,
+                field,
+                span,
+            } => {
+                let b = builder.create_block();
+                builder.switch_to_block(b);
+                let ctx_ptr = builder.use_var(ctx_ptr_var);
+
+                let ptr = self.operand_value(*expr, builder, ctx_ptr, translation_ctx)?;
+                let type_def = type_def(&ty);
+                let Type::Struct { fields, .. } = &ty else {
+                    unreachable!("field accesses are lowered with their struct type")
+                };
+                let (Some(layout), Some((_, field_ty))) = (
+                    type_def.field(&field),
+                    fields.iter().find(|(name, _)| *name == field),
+                ) else {
+                    return Err(Diagnostic::error(
+                        span,
+                        format!("Struct `{ty}` has no field `{field}`"),
+                    )
+                    .into());
+                };
+                let val = load_field(builder, ptr, layout, field_ty, target_type);
+                self.store_value(builder, ctx_ptr, val, translation_ctx);
+
+                let block_count = translation_ctx.block_counter;
+
+                let block_count_val = builder.ins().iconst(target_type, (block_count + 1) as i64);
+                builder.ins().return_(&[block_count_val]);
+
+                translation_ctx.block_counter += 1;
+
+                Ok((vec![block_count], translation_ctx.block_counter, vec![b]))
+            }
+            Expression::Variant {
+                ty,
+                variant,
+                mut fields,
+                span,
+            } => {
+                // The tag is stored like the first field of the variant.
+                fields.0.insert(0, Expression::Lit(variant as i64, span));
+                self.translate_struct(
+                    variant_def(&ty, variant),
+                    fields,
+                    span,
+                    builder,
+                    ctx_ptr_var,
+                    runtime_var,
+                    translation_ctx,
+                )
+            }
+            Expression::IsVariant { expr, variant, .. } => {
+                let b = builder.create_block();
+                builder.switch_to_block(b);
+                let ctx_ptr = builder.use_var(ctx_ptr_var);
+
+                let ptr = self.operand_value(*expr, builder, ctx_ptr, translation_ctx)?;
+                let tag = builder.ins().load(types::I64, MemFlags::new(), ptr, 0);
+                let cmp = builder.ins().icmp_imm(IntCC::Equal, tag, variant as i64);
+                let val = builder.ins().uextend(target_type, cmp);
+                self.store_value(builder, ctx_ptr, val, translation_ctx);
+
+                let block_count = translation_ctx.block_counter;
+
+                let block_count_val = builder.ins().iconst(target_type, (block_count + 1) as i64);
+                builder.ins().return_(&[block_count_val]);
+
+                translation_ctx.block_counter += 1;
+
+                Ok((vec![block_count], translation_ctx.block_counter, vec![b]))
+            }
+            Expression::Payload {
+                ty,
+                expr,
+                variant,
+                index,
+                ..
+            } => {
+                let b = builder.create_block();
+                builder.switch_to_block(b);
+                let ctx_ptr = builder.use_var(ctx_ptr_var);
+
+                let ptr = self.operand_value(*expr, builder, ctx_ptr, translation_ctx)?;
+                let Type::Enum { variants, .. } = &ty else {
+                    unreachable!("payloads are lowered with their enum type")
+                };
+                let layout = variant_def(&ty, variant);
+                let val = load_field(
+                    builder,
+                    ptr,
+                    &layout.fields()[index + 1],
+                    &variants[variant].1[index],
+                    target_type,
+                );
+                self.store_value(builder, ctx_ptr, val, translation_ctx);
+
+                let block_count = translation_ctx.block_counter;
+
+                let block_count_val = builder.ins().iconst(target_type, (block_count + 1) as i64);
+                builder.ins().return_(&[block_count_val]);
+
+                translation_ctx.block_counter += 1;
+
+                Ok((vec![block_count], translation_ctx.block_counter, vec![b]))
+            }
+            Expression::Array(elems, span) => self.translate_array(
+                elems,
+                span,
+                builder,
+                ctx_ptr_var,
+                runtime_var,
+                translation_ctx,
+            ),
+            Expression::Index { expr, index, .. } => {
+                let b = builder.create_block();
+                builder.switch_to_block(b);
+                let ctx_ptr = builder.use_var(ctx_ptr_var);
+
+                let array_ptr = self.operand_value(*expr, builder, ctx_ptr, translation_ctx)?;
+                let index = self.operand_value(*index, builder, ctx_ptr, translation_ctx)?;
+                let len = builder
+                    .ins()
+                    .load(target_type, MemFlags::new(), array_ptr, ARRAY_LEN);
+                // Negative indices are out of bounds as large unsigned ones.
+                let out_of_bounds =
+                    builder
+                        .ins()
+                        .icmp(IntCC::UnsignedGreaterThanOrEqual, index, len);
+                let runtime_ptr = builder.use_var(runtime_var);
+                fault_if(
+                    builder,
+                    target_type,
+                    runtime_ptr,
+                    out_of_bounds,
+                    CompilerTrapCode::IndexOutOfBounds,
                 );
-                builder.switch_to_block(after_realloc);
+                let data_ptr =
+                    builder
+                        .ins()
+                        .load(target_type, MemFlags::new(), array_ptr, ARRAY_DATA);
+                let offset = builder.ins().ishl_imm(index, 3);
+                let elem_ptr = builder.ins().iadd(data_ptr, offset);
+                let val = builder
+                    .ins()
+                    .load(target_type, MemFlags::new(), elem_ptr, 0);
+                self.store_value(builder, ctx_ptr, val, translation_ctx);
+
+                let block_count = translation_ctx.block_counter;
+
+                let block_count_val = builder.ins().iconst(target_type, (block_count + 1) as i64);
+                builder.ins().return_(&[block_count_val]);
+
+                translation_ctx.block_counter += 1;
+
+                Ok((vec![block_count], translation_ctx.block_counter, vec![b]))
+            }
+            Expression::ArrayLen(expr, _) => {
+                let b = builder.create_block();
+                builder.switch_to_block(b);
+                let ctx_ptr = builder.use_var(ctx_ptr_var);
+
+                let array_ptr = self.operand_value(*expr, builder, ctx_ptr, translation_ctx)?;
+                let len = builder
+                    .ins()
+                    .load(target_type, MemFlags::new(), array_ptr, ARRAY_LEN);
+                self.store_value(builder, ctx_ptr, len, translation_ctx);
+
+                let block_count = translation_ctx.block_counter;
+
+                let block_count_val = builder.ins().iconst(target_type, (block_count + 1) as i64);
+                builder.ins().return_(&[block_count_val]);
+
+                translation_ctx.block_counter += 1;
 
-                let new_ptr = *builder.block_params(after_realloc).first().unwrap();
+                Ok((vec![block_count], translation_ctx.block_counter, vec![b]))
+            }
+            Expression::ArrayPush { array, value, .. } => {
+                let b = builder.create_block();
+                builder.switch_to_block(b);
                 let ctx_ptr = builder.use_var(ctx_ptr_var);
-                let new_len = builder.use_var(new_len_var);
 
+                let array_ptr = self.operand_value(*array, builder, ctx_ptr, translation_ctx)?;
+                let val = self.operand_value(*value, builder, ctx_ptr, translation_ctx)?;
+                let len = builder
+                    .ins()
+                    .load(target_type, MemFlags::new(), array_ptr, ARRAY_LEN);
+                let cap = builder
+                    .ins()
+                    .load(target_type, MemFlags::new(), array_ptr, ARRAY_CAP);
+                let data_ptr =
+                    builder
+                        .ins()
+                        .load(target_type, MemFlags::new(), array_ptr, ARRAY_DATA);
+
+                let grow_block = builder.create_block();
+                let push_block = builder.create_block();
+                builder.append_block_param(push_block, target_type);
+                let full = builder.ins().icmp(IntCC::Equal, len, cap);
+                builder.ins().brif(
+                    full,
+                    grow_block,
+                    &[],
+                    push_block,
+                    &[BlockArg::Value(data_ptr)],
+                );
+
+                // A full buffer doubles.
+                builder.switch_to_block(grow_block);
+                let cap = builder.ins().ishl_imm(cap, 1);
+                builder
+                    .ins()
+                    .store(MemFlags::new(), cap, array_ptr, ARRAY_CAP);
+                let buffer_size = builder.ins().ishl_imm(cap, 3);
+                let runtime_ptr = builder.use_var(runtime_var);
+                let data_ptr = arena_realloc(
+                    &mut self.module,
+                    builder,
+                    data_ptr,
+                    buffer_size,
+                    runtime_ptr,
+                );
+                builder.ins().jump(push_block, &[BlockArg::Value(data_ptr)]);
+
+                builder.switch_to_block(push_block);
+                let data_ptr = *builder.block_params(push_block).first().unwrap();
                 builder
                     .ins()
-                    .store(MemFlags::new(), new_ptr, ctx_ptr, PROCESS_CTX_VARS);
+                    .store(MemFlags::new(), data_ptr, array_ptr, ARRAY_DATA);
+                let offset = builder.ins().ishl_imm(len, 3);
+                let elem_ptr = builder.ins().iadd(data_ptr, offset);
+                builder.ins().store(MemFlags::new(), val, elem_ptr, 0);
+                let len = builder.ins().iadd_imm(len, 1);
                 builder
                     .ins()
-                    .store(MemFlags::new(), new_len, ctx_ptr, PROCESS_CTX_VARS_LEN);
+                    .store(MemFlags::new(), len, array_ptr, ARRAY_LEN);
+                let nil = builder.ins().iconst(target_type, 0);
+                let ctx_ptr = builder.use_var(ctx_ptr_var);
+                self.store_value(builder, ctx_ptr, nil, translation_ctx);
+
+                let block_count = translation_ctx.block_counter;
+
+                let block_count_val = builder.ins().iconst(target_type, (block_count + 1) as i64);
+                builder.ins().return_(&[block_count_val]);
+
+                translation_ctx.block_counter += 1;
+
+                Ok((vec![block_count], translation_ctx.block_counter, vec![b]))
+            }
+            Expression::Spawn {
+                function,
+                args,
+                span,
+            } => self.translate_spawn(
+                *function,
+                args,
+                span,
+                builder,
+                ctx_ptr_var,
+                runtime_var,
+                translation_ctx,
+            ),
+            Expression::Send { pid, message, .. } => {
+                let b = builder.create_block();
+                builder.switch_to_block(b);
+                let ctx_ptr = builder.use_var(ctx_ptr_var);
+
+                let pid = self.operand_value(*pid, builder, ctx_ptr, translation_ctx)?;
+                let val = self.operand_value(*message, builder, ctx_ptr, translation_ctx)?;
+                let runtime_ptr = builder.use_var(runtime_var);
+                call_scheduler(
+                    &mut self.module,
+                    builder,
+                    "unicorn_send",
+                    &[runtime_ptr, pid, val],
+                    false,
+                );
+                let nil = builder.ins().iconst(target_type, 0);
+                self.store_value(builder, ctx_ptr, nil, translation_ctx);
+
+                let block_count = translation_ctx.block_counter;
+
+                let block_count_val = builder.ins().iconst(target_type, (block_count + 1) as i64);
+                builder.ins().return_(&[block_count_val]);
+
+                translation_ctx.block_counter += 1;
+
+                Ok((vec![block_count], translation_ctx.block_counter, vec![b]))
+            }
+            Expression::Link(pid, _) => self.translate_process_call(
+                "unicorn_link",
+                vec![*pid],
+                false,
+                builder,
+                ctx_ptr_var,
+                runtime_var,
+                translation_ctx,
+            ),
+            Expression::Monitor(pid, _) => self.translate_process_call(
+                "unicorn_monitor",
+                vec![*pid],
+                false,
+                builder,
+                ctx_ptr_var,
+                runtime_var,
+                translation_ctx,
+            ),
+            Expression::Restart(pid, _) => self.translate_process_call(
+                "unicorn_restart",
+                vec![*pid],
+                true,
+                builder,
+                ctx_ptr_var,
+                runtime_var,
+                translation_ctx,
+            ),
+            Expression::Sleep(ms, _) => self.translate_process_call(
+                "unicorn_sleep",
+                vec![*ms],
+                false,
+                builder,
+                ctx_ptr_var,
+                runtime_var,
+                translation_ctx,
+            ),
+            Expression::NowMs(_) => self.translate_process_call(
+                "unicorn_now_ms",
+                vec![],
+                true,
+                builder,
+                ctx_ptr_var,
+                runtime_var,
+                translation_ctx,
+            ),
+            Expression::ExitPid(exit, _) => {
+                self.translate_exit_field(*exit, false, builder, ctx_ptr_var, translation_ctx)
+            }
+            Expression::ExitReason(exit, _) => {
+                self.translate_exit_field(*exit, true, builder, ctx_ptr_var, translation_ctx)
+            }
+            Expression::SelfPid(_) => {
+                let b = builder.create_block();
+                builder.switch_to_block(b);
+                let ctx_ptr = builder.use_var(ctx_ptr_var);
+
+                let pid =
+                    builder
+                        .ins()
+                        .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_PID);
+                self.store_value(builder, ctx_ptr, pid, translation_ctx);
+
+                let block_count = translation_ctx.block_counter;
+
+                let block_count_val = builder.ins().iconst(target_type, (block_count + 1) as i64);
+                builder.ins().return_(&[block_count_val]);
+
+                translation_ctx.block_counter += 1;
+
+                Ok((vec![block_count], translation_ctx.block_counter, vec![b]))
+            }
+            Expression::Receive(_) => self.translate_wait(
+                "unicorn_receive",
+                vec![],
+                builder,
+                ctx_ptr_var,
+                runtime_var,
+                translation_ctx,
+            ),
+            Expression::Io { function, args, .. } => self.translate_wait(
+                &format!("unicorn_{function}"),
+                args.0,
+                builder,
+                ctx_ptr_var,
+                runtime_var,
+                translation_ctx,
+            ),
+            Expression::AwaitMessage(timeout, _) => self.translate_wait(
+                "unicorn_await",
+                vec![*timeout],
+                builder,
+                ctx_ptr_var,
+                runtime_var,
+                translation_ctx,
+            ),
+            Expression::Str(value, _) => {
+                let id = self.define_str(None, value)?;
+                self.translate_data_addr(id, builder, ctx_ptr_var, translation_ctx)
+            }
+            Expression::GlobalDataAddr(name, span) => {
+                let Expression::Ident(name, _) = *name else {
+                    return Err(Diagnostic::error(span, "Expected a data name").into());
+                };
+                let Some(&id) = self.data.get(&name) else {
+                    return Err(
+                        Diagnostic::error(span, format!("Data `{name}` is not defined")).into(),
+                    );
+                };
+                self.translate_data_addr(id, builder, ctx_ptr_var, translation_ctx)
+            }
+            Expression::Binary {
+                op, ty, lhs, rhs, ..
+            } => {
+                let b = builder.create_block();
+                builder.switch_to_block(b);
+                let ctx_ptr = builder.use_var(ctx_ptr_var);
+
+                let lhs = self.operand_value(*lhs, builder, ctx_ptr, translation_ctx)?;
+                let rhs = self.operand_value(*rhs, builder, ctx_ptr, translation_ctx)?;
+                let lhs = narrow(builder, &ty, lhs);
+                let rhs = narrow(builder, &ty, rhs);
+                let signed = ty.is_signed();
+
+                let val = match op {
+                    _ if ty.is_float() => float_binary(builder, op, lhs, rhs, target_type),
+                    BinaryOp::Add => builder.ins().iadd(lhs, rhs),
+                    BinaryOp::Sub => builder.ins().isub(lhs, rhs),
+                    BinaryOp::Mul => builder.ins().imul(lhs, rhs),
+                    BinaryOp::Div | BinaryOp::Rem => {
+                        let runtime_ptr = builder.use_var(runtime_var);
+                        let zero = builder.ins().icmp_imm(IntCC::Equal, rhs, 0);
+                        fault_if(
+                            builder,
+                            target_type,
+                            runtime_ptr,
+                            zero,
+                            CompilerTrapCode::DivisionByZero,
+                        );
+                        // The quotient of the smallest value by -1 does not fit its type.
+                        if let (BinaryOp::Div, Some((min, _))) = (op, ty.range())
+                            && signed
+                        {
+                            let is_min = builder.ins().icmp_imm(IntCC::Equal, lhs, min as i64);
+                            let minus_one = builder.ins().icmp_imm(IntCC::Equal, rhs, -1);
+                            let overflow = builder.ins().band(is_min, minus_one);
+                            fault_if(
+                                builder,
+                                target_type,
+                                runtime_ptr,
+                                overflow,
+                                CompilerTrapCode::Overflow,
+                            );
+                        }
+                        match (op == BinaryOp::Div, signed) {
+                            (true, true) => builder.ins().sdiv(lhs, rhs),
+                            (true, false) => builder.ins().udiv(lhs, rhs),
+                            (false, true) => builder.ins().srem(lhs, rhs),
+                            (false, false) => builder.ins().urem(lhs, rhs),
+                        }
+                    }
+                    BinaryOp::Eq
+                    | BinaryOp::Ne
+                    | BinaryOp::Lt
+                    | BinaryOp::Le
+                    | BinaryOp::Gt
+                    | BinaryOp::Ge => {
+                        let cc = match (op, signed) {
+                            (BinaryOp::Eq, _) => IntCC::Equal,
+                            (BinaryOp::Ne, _) => IntCC::NotEqual,
+                            (BinaryOp::Lt, true) => IntCC::SignedLessThan,
+                            (BinaryOp::Le, true) => IntCC::SignedLessThanOrEqual,
+                            (BinaryOp::Gt, true) => IntCC::SignedGreaterThan,
+                            (_, true) => IntCC::SignedGreaterThanOrEqual,
+                            (BinaryOp::Lt, false) => IntCC::UnsignedLessThan,
+                            (BinaryOp::Le, false) => IntCC::UnsignedLessThanOrEqual,
+                            (BinaryOp::Gt, false) => IntCC::UnsignedGreaterThan,
+                            (_, false) => IntCC::UnsignedGreaterThanOrEqual,
+                        };
+                        let cmp = builder.ins().icmp(cc, lhs, rhs);
+                        builder.ins().uextend(target_type, cmp)
+                    }
+                    BinaryOp::And | BinaryOp::Or => {
+                        unreachable!("lowered to `if` by the middleware")
+                    }
+                };
+                let val = match op {
+                    BinaryOp::Add
+                    | BinaryOp::Sub
+                    | BinaryOp::Mul
+                    | BinaryOp::Div
+                    | BinaryOp::Rem => widen(builder, &ty, target_type, val),
+                    _ => val,
+                };
+
+                self.store_value(builder, ctx_ptr, val, translation_ctx);
+
+                let block_count = translation_ctx.block_counter;
+
+                let block_count_val = builder.ins().iconst(target_type, (block_count + 1) as i64);
+                builder.ins().return_(&[block_count_val]);
+
+                translation_ctx.block_counter += 1;
+
+                Ok((vec![block_count], translation_ctx.block_counter, vec![b]))
+            }
+            Expression::Unary { op, ty, expr, .. } => {
+                let b = builder.create_block();
+                builder.switch_to_block(b);
+                let ctx_ptr = builder.use_var(ctx_ptr_var);
+
+                let val = self.operand_value(*expr, builder, ctx_ptr, translation_ctx)?;
+                let val = narrow(builder, &ty, val);
+                let val = match op {
+                    UnaryOp::Not => {
+                        let cmp = builder.ins().icmp_imm(IntCC::Equal, val, 0);
+                        builder.ins().uextend(target_type, cmp)
+                    }
+                    UnaryOp::Neg if ty.is_float() => {
+                        let val = builder.ins().fneg(val);
+                        widen(builder, &ty, target_type, val)
+                    }
+                    UnaryOp::Neg => {
+                        let val = builder.ins().ineg(val);
+                        widen(builder, &ty, target_type, val)
+                    }
+                };
+
+                self.store_value(builder, ctx_ptr, val, translation_ctx);
+
+                let block_count = translation_ctx.block_counter;
+
+                let block_count_val = builder.ins().iconst(target_type, (block_count + 1) as i64);
+                builder.ins().return_(&[block_count_val]);
+
+                translation_ctx.block_counter += 1;
+
+                Ok((vec![block_count], translation_ctx.block_counter, vec![b]))
+            }
+        }
+    }
+
+    /// Evaluates the fields like call arguments, then allocates the struct in the
+    /// arena and stores every field at its offset, nested structs are copied inline.
+    #[allow(clippy::too_many_arguments)]
+    fn translate_struct(
+        &mut self,
+        type_def: TypeDef,
+        fields: Expressions,
+        span: Span,
+        builder: &mut FunctionBuilder,
+        ctx_ptr_var: Variable,
+        runtime_var: Variable,
+        translation_ctx: &mut TranslationContext,
+    ) -> Result<(Vec<usize>, usize, Vec<Block>)> {
+        let target_type = self.module.target_config().pointer_type();
+        let fields_len = fields.0.len();
+        let (mut indecies, _, mut blocks) = self.translate_expression(
+            Expression::BeforeCall(fields_len, span),
+            builder,
+            ctx_ptr_var,
+            runtime_var,
+            translation_ctx,
+        )?;
+        let tr_type = translation_ctx.tr_type;
+        for (i, expression) in fields.0.into_iter().enumerate() {
+            translation_ctx.tr_type = TranslationType::Call(i);
+            let (indecies_, _, blocks_) = self.translate_expression(
+                expression,
+                builder,
+                ctx_ptr_var,
+                runtime_var,
+                translation_ctx,
+            )?;
+            indecies = [indecies, indecies_].concat();
+            blocks = [blocks, blocks_].concat();
+        }
+        translation_ctx.tr_type = tr_type;
+
+        let b = builder.create_block();
+        builder.switch_to_block(b);
+        let ctx_ptr = builder.use_var(ctx_ptr_var);
+        let args_ptr = builder.ins().load(
+            target_type,
+            MemFlags::new(),
+            ctx_ptr,
+            PROCESS_CTX_CALL_ARGS_TEMP,
+        );
+        let outer_args_ptr = builder.ins().load(
+            target_type,
+            MemFlags::new(),
+            args_ptr,
+            ((fields_len + 1) * 8) as i32,
+        );
+        builder.ins().store(
+            MemFlags::new(),
+            outer_args_ptr,
+            ctx_ptr,
+            PROCESS_CTX_CALL_ARGS_TEMP,
+        );
+
+        let arena = builder
+            .ins()
+            .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_ARENA);
+        let size = builder.ins().iconst(target_type, type_def.size() as i64);
+        let runtime_ptr = builder.use_var(runtime_var);
+        let struct_ptr = arena_malloc(&mut self.module, builder, arena, size, runtime_ptr);
+
+        for (i, field) in type_def.fields().iter().enumerate() {
+            let val = builder
+                .ins()
+                .load(target_type, MemFlags::new(), args_ptr, (i * 8) as i32);
+            match field.ty() {
+                Some(field_ty) => {
+                    let field_ty = field_ty.as_int();
+                    let val = if field_ty == target_type {
+                        val
+                    } else {
+                        builder.ins().ireduce(field_ty, val)
+                    };
+                    builder
+                        .ins()
+                        .store(MemFlags::new(), val, struct_ptr, field.offset() as i32);
+                }
+                None => {
+                    let dest = builder.ins().iadd_imm(struct_ptr, field.offset() as i64);
+                    builder.emit_small_memory_copy(
+                        self.module.target_config(),
+                        dest,
+                        val,
+                        field.size() as u64,
+                        field.align() as u8,
+                        field.align() as u8,
+                        true,
+                        MemFlags::new(),
+                    );
+                }
+            }
+        }
+        call_free(&mut self.module, builder, args_ptr);
+
+        let ctx_ptr = builder.use_var(ctx_ptr_var);
+        self.store_value(builder, ctx_ptr, struct_ptr, translation_ctx);
+
+        let block_count = translation_ctx.block_counter;
+
+        let block_count_val = builder.ins().iconst(target_type, (block_count + 1) as i64);
+        builder.ins().return_(&[block_count_val]);
+
+        translation_ctx.block_counter += 1;
+
+        Ok((
+            [indecies, vec![block_count]].concat(),
+            translation_ctx.block_counter,
+            [blocks, vec![b]].concat(),
+        ))
+    }
+
+    /// Evaluates the elements like call arguments, then copies them into a buffer
+    /// of the arena behind the array header.
+    fn translate_array(
+        &mut self,
+        elems: Expressions,
+        span: Span,
+        builder: &mut FunctionBuilder,
+        ctx_ptr_var: Variable,
+        runtime_var: Variable,
+        translation_ctx: &mut TranslationContext,
+    ) -> Result<(Vec<usize>, usize, Vec<Block>)> {
+        let target_type = self.module.target_config().pointer_type();
+        let elems_len = elems.0.len();
+        let (mut indecies, _, mut blocks) = self.translate_expression(
+            Expression::BeforeCall(elems_len, span),
+            builder,
+            ctx_ptr_var,
+            runtime_var,
+            translation_ctx,
+        )?;
+        let tr_type = translation_ctx.tr_type;
+        for (i, expression) in elems.0.into_iter().enumerate() {
+            translation_ctx.tr_type = TranslationType::Call(i);
+            let (indecies_, _, blocks_) = self.translate_expression(
+                expression,
+                builder,
+                ctx_ptr_var,
+                runtime_var,
+                translation_ctx,
+            )?;
+            indecies = [indecies, indecies_].concat();
+            blocks = [blocks, blocks_].concat();
+        }
+        translation_ctx.tr_type = tr_type;
+
+        let b = builder.create_block();
+        builder.switch_to_block(b);
+        let ctx_ptr = builder.use_var(ctx_ptr_var);
+        let args_ptr = builder.ins().load(
+            target_type,
+            MemFlags::new(),
+            ctx_ptr,
+            PROCESS_CTX_CALL_ARGS_TEMP,
+        );
+        let outer_args_ptr = builder.ins().load(
+            target_type,
+            MemFlags::new(),
+            args_ptr,
+            ((elems_len + 1) * 8) as i32,
+        );
+        builder.ins().store(
+            MemFlags::new(),
+            outer_args_ptr,
+            ctx_ptr,
+            PROCESS_CTX_CALL_ARGS_TEMP,
+        );
+
+        let arena = builder
+            .ins()
+            .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_ARENA);
+        let size = builder.ins().iconst(target_type, ARRAY_HEADER_SIZE);
+        let runtime_ptr = builder.use_var(runtime_var);
+        let array_ptr = arena_malloc(&mut self.module, builder, arena, size, runtime_ptr);
+        // The capacity is never 0, so that doubling it always makes room.
+        let cap = elems_len.max(1);
+        let size = builder.ins().iconst(target_type, (cap * 8) as i64);
+        let data_ptr = arena_malloc(&mut self.module, builder, arena, size, runtime_ptr);
+        for i in 0..elems_len {
+            let val = builder
+                .ins()
+                .load(target_type, MemFlags::new(), args_ptr, (i * 8) as i32);
+            builder
+                .ins()
+                .store(MemFlags::new(), val, data_ptr, (i * 8) as i32);
+        }
+        call_free(&mut self.module, builder, args_ptr);
+
+        let len = builder.ins().iconst(target_type, elems_len as i64);
+        builder
+            .ins()
+            .store(MemFlags::new(), len, array_ptr, ARRAY_LEN);
+        let cap = builder.ins().iconst(target_type, cap as i64);
+        builder
+            .ins()
+            .store(MemFlags::new(), cap, array_ptr, ARRAY_CAP);
+        builder
+            .ins()
+            .store(MemFlags::new(), data_ptr, array_ptr, ARRAY_DATA);
+
+        let ctx_ptr = builder.use_var(ctx_ptr_var);
+        self.store_value(builder, ctx_ptr, array_ptr, translation_ctx);
+
+        let block_count = translation_ctx.block_counter;
+
+        let block_count_val = builder.ins().iconst(target_type, (block_count + 1) as i64);
+        builder.ins().return_(&[block_count_val]);
+
+        translation_ctx.block_counter += 1;
+
+        Ok((
+            [indecies, vec![block_count]].concat(),
+            translation_ctx.block_counter,
+            [blocks, vec![b]].concat(),
+        ))
+    }
+
+    /// Calls the scheduler function `name` with the values of `operands`, the
+    /// value is the one the function `returns` or nil.
+    #[allow(clippy::too_many_arguments)]
+    fn translate_process_call(
+        &mut self,
+        name: &str,
+        operands: Vec<Expression>,
+        returns: bool,
+        builder: &mut FunctionBuilder,
+        ctx_ptr_var: Variable,
+        runtime_var: Variable,
+        translation_ctx: &mut TranslationContext,
+    ) -> Result<(Vec<usize>, usize, Vec<Block>)> {
+        let target_type = self.module.target_config().pointer_type();
+        let b = builder.create_block();
+        builder.switch_to_block(b);
+        let ctx_ptr = builder.use_var(ctx_ptr_var);
+
+        let mut args = vec![builder.use_var(runtime_var)];
+        for operand in operands {
+            args.push(self.operand_value(operand, builder, ctx_ptr, translation_ctx)?);
+        }
+        let val = match call_scheduler(&mut self.module, builder, name, &args, returns) {
+            Some(val) => val,
+            None => builder.ins().iconst(target_type, 0),
+        };
+        self.store_value(builder, ctx_ptr, val, translation_ctx);
+
+        let block_count = translation_ctx.block_counter;
+
+        let block_count_val = builder.ins().iconst(target_type, (block_count + 1) as i64);
+        builder.ins().return_(&[block_count_val]);
+
+        translation_ctx.block_counter += 1;
+
+        Ok((vec![block_count], translation_ctx.block_counter, vec![b]))
+    }
+
+    /// Calls the scheduler function `name` with the values of `operands` until
+    /// it does not yield, the process waits in between, and yields its value.
+    fn translate_wait(
+        &mut self,
+        name: &str,
+        operands: Vec<Expression>,
+        builder: &mut FunctionBuilder,
+        ctx_ptr_var: Variable,
+        runtime_var: Variable,
+        translation_ctx: &mut TranslationContext,
+    ) -> Result<(Vec<usize>, usize, Vec<Block>)> {
+        let target_type = self.module.target_config().pointer_type();
+        let b = builder.create_block();
+        builder.switch_to_block(b);
+        let ctx_ptr = builder.use_var(ctx_ptr_var);
+        let block_count = translation_ctx.block_counter;
+
+        let runtime_ptr = builder.use_var(runtime_var);
+        let mut args = vec![runtime_ptr];
+        for operand in operands {
+            args.push(self.operand_value(operand, builder, ctx_ptr, translation_ctx)?);
+        }
+        let val = call_scheduler(&mut self.module, builder, name, &args, true).unwrap();
+        let yielded =
+            builder
+                .ins()
+                .load(target_type, MemFlags::new(), runtime_ptr, RUNTIME_YIELDED);
+        let wait_block = builder.create_block();
+        let take_block = builder.create_block();
+        builder
+            .ins()
+            .brif(yielded, wait_block, &[], take_block, &[]);
+
+        // Resumes at this block once the process is woken.
+        builder.switch_to_block(wait_block);
+        let this_block = builder.ins().iconst(target_type, block_count as i64);
+        builder.ins().return_(&[this_block]);
+
+        builder.switch_to_block(take_block);
+        self.store_value(builder, ctx_ptr, val, translation_ctx);
+
+        let block_count_val = builder.ins().iconst(target_type, (block_count + 1) as i64);
+        builder.ins().return_(&[block_count_val]);
+
+        translation_ctx.block_counter += 1;
+
+        Ok((vec![block_count], translation_ctx.block_counter, vec![b]))
+    }
+
+    /// Id of the process of an exit message in an identifier, or its `reason`.
+    fn translate_exit_field(
+        &mut self,
+        exit: Expression,
+        reason: bool,
+        builder: &mut FunctionBuilder,
+        ctx_ptr_var: Variable,
+        translation_ctx: &mut TranslationContext,
+    ) -> Result<(Vec<usize>, usize, Vec<Block>)> {
+        let target_type = self.module.target_config().pointer_type();
+        let b = builder.create_block();
+        builder.switch_to_block(b);
+        let ctx_ptr = builder.use_var(ctx_ptr_var);
+
+        let exit = self.operand_value(exit, builder, ctx_ptr, translation_ctx)?;
+        let val = if reason {
+            builder.ins().band_imm(exit, (1 << EXIT_REASON_BITS) - 1)
+        } else {
+            builder.ins().sshr_imm(exit, EXIT_REASON_BITS)
+        };
+        self.store_value(builder, ctx_ptr, val, translation_ctx);
+
+        let block_count = translation_ctx.block_counter;
+
+        let block_count_val = builder.ins().iconst(target_type, (block_count + 1) as i64);
+        builder.ins().return_(&[block_count_val]);
+
+        translation_ctx.block_counter += 1;
+
+        Ok((vec![block_count], translation_ctx.block_counter, vec![b]))
+    }
+
+    /// Evaluates the arguments like the ones of a call, then starts a process
+    /// whose variables are the arguments buffer and yields its id.
+    #[allow(clippy::too_many_arguments)]
+    fn translate_spawn(
+        &mut self,
+        function: Expression,
+        args: Expressions,
+        span: Span,
+        builder: &mut FunctionBuilder,
+        ctx_ptr_var: Variable,
+        runtime_var: Variable,
+        translation_ctx: &mut TranslationContext,
+    ) -> Result<(Vec<usize>, usize, Vec<Block>)> {
+        let target_type = self.module.target_config().pointer_type();
+        let Expression::Ident(name, _) = function else {
+            return Err(Diagnostic::error(span, "Expected the name of a function").into());
+        };
+        // A closure record lives in the arena of the spawning process.
+        let Some(function) = self.function(&name).filter(|function| {
+            function.resumable && !translation_ctx.variables.contains_key(&name)
+        }) else {
+            return Err(
+                Diagnostic::error(span, format!("`{name}` is not a top-level function")).into(),
+            );
+        };
+        let args_len = args.0.len();
+        if function.arity != args_len {
+            return Err(Diagnostic::error(
+                span,
+                format!(
+                    "Function `{name}` takes {} arguments but {args_len} were given",
+                    function.arity
+                ),
+            )
+            .into());
+        }
+
+        let (mut indecies, _, mut blocks) = self.translate_expression(
+            Expression::BeforeCall(args_len, span),
+            builder,
+            ctx_ptr_var,
+            runtime_var,
+            translation_ctx,
+        )?;
+        let tr_type = translation_ctx.tr_type;
+        for (i, expression) in args.0.into_iter().enumerate() {
+            translation_ctx.tr_type = TranslationType::Call(i);
+            let (indecies_, _, blocks_) = self.translate_expression(
+                expression,
+                builder,
+                ctx_ptr_var,
+                runtime_var,
+                translation_ctx,
+            )?;
+            indecies = [indecies, indecies_].concat();
+            blocks = [blocks, blocks_].concat();
+        }
+        translation_ctx.tr_type = tr_type;
+
+        let b = builder.create_block();
+        builder.switch_to_block(b);
+        let ctx_ptr = builder.use_var(ctx_ptr_var);
+        let args_ptr = builder.ins().load(
+            target_type,
+            MemFlags::new(),
+            ctx_ptr,
+            PROCESS_CTX_CALL_ARGS_TEMP,
+        );
+        let outer_args_ptr = builder.ins().load(
+            target_type,
+            MemFlags::new(),
+            args_ptr,
+            ((args_len + 1) * 8) as i32,
+        );
+        builder.ins().store(
+            MemFlags::new(),
+            outer_args_ptr,
+            ctx_ptr,
+            PROCESS_CTX_CALL_ARGS_TEMP,
+        );
+
+        // The scheduler keeps a copy of the arguments to restart the process.
+        let callee = self.module.declare_func_in_func(function.id, builder.func);
+        let func_addr = builder.ins().func_addr(target_type, callee);
+        let args_len_val = builder.ins().iconst(target_type, args_len as i64);
+        let runtime_ptr = builder.use_var(runtime_var);
+        let pid = call_scheduler(
+            &mut self.module,
+            builder,
+            "unicorn_spawn",
+            &[runtime_ptr, func_addr, args_ptr, args_len_val],
+            true,
+        )
+        .unwrap();
+        call_free(&mut self.module, builder, args_ptr);
+
+        let ctx_ptr = builder.use_var(ctx_ptr_var);
+        self.store_value(builder, ctx_ptr, pid, translation_ctx);
+
+        let block_count = translation_ctx.block_counter;
+
+        let block_count_val = builder.ins().iconst(target_type, (block_count + 1) as i64);
+        builder.ins().return_(&[block_count_val]);
+
+        translation_ctx.block_counter += 1;
+
+        Ok((
+            [indecies, vec![block_count]].concat(),
+            translation_ctx.block_counter,
+            [blocks, vec![b]].concat(),
+        ))
+    }
+
+    /// Stores the address of a data object as the value of the expression.
+    fn translate_data_addr(
+        &mut self,
+        id: DataId,
+        builder: &mut FunctionBuilder,
+        ctx_ptr_var: Variable,
+        translation_ctx: &mut TranslationContext,
+    ) -> Result<(Vec<usize>, usize, Vec<Block>)> {
+        let target_type = self.module.target_config().pointer_type();
+        let b = builder.create_block();
+        builder.switch_to_block(b);
+        let ctx_ptr = builder.use_var(ctx_ptr_var);
+
+        let data = self.module.declare_data_in_func(id, builder.func);
+        let addr = builder.ins().symbol_value(target_type, data);
+        self.store_value(builder, ctx_ptr, addr, translation_ctx);
+
+        let block_count = translation_ctx.block_counter;
+
+        let block_count_val = builder.ins().iconst(target_type, (block_count + 1) as i64);
+        builder.ins().return_(&[block_count_val]);
+
+        translation_ctx.block_counter += 1;
+
+        Ok((vec![block_count], translation_ctx.block_counter, vec![b]))
+    }
+
+    /// Calls a runtime function natively, or enters a user function by pushing a new
+    /// context for it (replacing the current one for a tail call) and handing the
+    /// driver over to its first block.
+    #[allow(clippy::too_many_arguments)]
+    fn translate_call(
+        &mut self,
+        ident: Expression,
+        args: Expressions,
+        span: Span,
+        tail: bool,
+        builder: &mut FunctionBuilder,
+        ctx_ptr_var: Variable,
+        runtime_var: Variable,
+        translation_ctx: &mut TranslationContext,
+    ) -> Result<(Vec<usize>, usize, Vec<Block>)> {
+        let target_type = self.module.target_config().pointer_type();
+        let Expression::Ident(name, _) = ident else {
+            return Err(Diagnostic::error(ident.span(), "Callee must be an identifier").into());
+        };
+        // A variable holds a closure record, whose function takes it after the arguments.
+        let closure_slot = translation_ctx.variables.get(&name).copied();
+        let function = match closure_slot {
+            Some(_) => None,
+            None => {
+                let Some(function) = self.function(&name) else {
+                    return Err(Diagnostic::error(
+                        span,
+                        format!("Function `{name}` is not defined"),
+                    )
+                    .into());
+                };
+                Some(function)
+            }
+        };
+        if let Some(function) = function
+            && function.arity != args.0.len()
+        {
+            return Err(Diagnostic::error(
+                span,
+                format!(
+                    "Function `{name}` takes {} arguments but {} were given",
+                    function.arity,
+                    args.0.len()
+                ),
+            )
+            .into());
+        }
+
+        let mut indecies = vec![];
+        let mut blocks = vec![];
+        let args_len = args.0.len();
+        let tr_type = translation_ctx.tr_type;
+        for (i, expression) in args.0.into_iter().enumerate() {
+            translation_ctx.tr_type = TranslationType::Call(i);
+            let (indecies_, _, blocks_) = self.translate_expression(
+                expression,
+                builder,
+                ctx_ptr_var,
+                runtime_var,
+                translation_ctx,
+            )?;
+            indecies = [indecies, indecies_].concat();
+            blocks = [blocks, blocks_].concat();
+        }
+        translation_ctx.tr_type = tr_type;
+        let b = builder.create_block();
+        builder.switch_to_block(b);
+
+        let ctx_ptr = builder.use_var(ctx_ptr_var);
+        let args_ptr = builder.ins().load(
+            target_type,
+            MemFlags::new(),
+            ctx_ptr,
+            PROCESS_CTX_CALL_ARGS_TEMP,
+        );
+        let outer_args_ptr = builder.ins().load(
+            target_type,
+            MemFlags::new(),
+            args_ptr,
+            ((args_len + 1) * 8) as i32,
+        );
+        builder.ins().store(
+            MemFlags::new(),
+            outer_args_ptr,
+            ctx_ptr,
+            PROCESS_CTX_CALL_ARGS_TEMP,
+        );
+
+        let block_count = translation_ctx.block_counter;
+        let (callee, vars_len) = match (closure_slot, function) {
+            (Some(slot), _) => {
+                let vars_ptr =
+                    builder
+                        .ins()
+                        .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_VARS);
+                let closure_ptr =
+                    builder
+                        .ins()
+                        .load(target_type, MemFlags::new(), vars_ptr, (slot * 8) as i32);
+                builder.ins().store(
+                    MemFlags::new(),
+                    closure_ptr,
+                    args_ptr,
+                    (args_len * 8) as i32,
+                );
+                let callee = builder
+                    .ins()
+                    .load(target_type, MemFlags::new(), closure_ptr, 0);
+                (callee, args_len + 1)
+            }
+            (None, Some(function)) => {
+                let callee = self.module.declare_func_in_func(function.id, builder.func);
+                (builder.ins().func_addr(target_type, callee), args_len)
+            }
+            (None, None) => unreachable!("the callee was resolved above"),
+        };
+        let resumable = function.is_none_or(|function| function.resumable);
+        let args_len_val = builder.ins().iconst(target_type, vars_len as i64);
+
+        if resumable && tail {
+            let vars_ptr =
+                builder
+                    .ins()
+                    .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_VARS);
+            call_free(&mut self.module, builder, vars_ptr);
+            builder
+                .ins()
+                .store(MemFlags::new(), args_ptr, ctx_ptr, PROCESS_CTX_VARS);
+            builder
+                .ins()
+                .store(MemFlags::new(), args_len_val, ctx_ptr, PROCESS_CTX_VARS_LEN);
+            builder
+                .ins()
+                .store(MemFlags::new(), callee, ctx_ptr, PROCESS_CTX_FUNC_ADDR);
+
+            let first_block = builder.ins().iconst(target_type, 0);
+            builder.ins().return_(&[first_block]);
+        } else if resumable {
+            let after_call = builder.create_block();
+            builder.append_block_param(after_call, target_type);
+            let buffer_size = builder.ins().iconst(target_type, PROCESS_CTX_BUFFER_SIZE);
+            let runtime_ptr = builder.use_var(runtime_var);
+            call_malloc(
+                &mut self.module,
+                builder,
+                buffer_size,
+                after_call,
+                &[],
+                Some(runtime_ptr),
+            );
+            builder.switch_to_block(after_call);
+            let callee_ctx = *builder.block_params(after_call).first().unwrap();
+
+            let ctx_ptr = builder.use_var(ctx_ptr_var);
+            let zero = builder.ins().iconst(target_type, 0);
+            let return_block = builder.ins().iconst(target_type, (block_count + 1) as i64);
+            let arena =
+                builder
+                    .ins()
+                    .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_ARENA);
+            let pid = builder
+                .ins()
+                .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_PID);
+            for (val, offset) in [
+                (args_ptr, PROCESS_CTX_VARS),
+                (args_len_val, PROCESS_CTX_VARS_LEN),
+                (callee, PROCESS_CTX_FUNC_ADDR),
+                (zero, PROCESS_CTX_TEMP_VAL),
+                (pid, PROCESS_CTX_PID),
+                (zero, PROCESS_CTX_CALL_ARGS_TEMP),
+                (ctx_ptr, PROCESS_CTX_CALLER),
+                (return_block, PROCESS_CTX_RETURN_BLOCK),
+                (arena, PROCESS_CTX_ARENA),
+            ] {
+                builder
+                    .ins()
+                    .store(MemFlags::new(), val, callee_ctx, offset);
+            }
+            let runtime_ptr = builder.use_var(runtime_var);
+            builder.ins().store(
+                MemFlags::new(),
+                callee_ctx,
+                runtime_ptr,
+                RUNTIME_CURRENT_CTX,
+            );
+
+            let first_block = builder.ins().iconst(target_type, 0);
+            builder.ins().return_(&[first_block]);
+        } else if let Some(function) = function {
+            let mut sig = self.module.make_signature();
+            for _ in 0..function.params {
+                sig.params.push(AbiParam::new(target_type));
+            }
+            for _ in 0..function.returns {
+                sig.returns.push(AbiParam::new(target_type));
+            }
+            let sig_ref = builder.import_signature(sig);
+
+            let mut args_vals = Vec::with_capacity(args_len);
+            for i in 0..args_len {
+                args_vals.push(builder.ins().load(
+                    target_type,
+                    MemFlags::new(),
+                    args_ptr,
+                    (i * 8) as i32,
+                ));
+            }
+
+            let call = builder.ins().call_indirect(sig_ref, callee, &args_vals);
+            if let Some(&res) = builder.inst_results(call).first() {
+                builder
+                    .ins()
+                    .store(MemFlags::new(), res, ctx_ptr, PROCESS_CTX_TEMP_VAL);
+            }
+            call_free(&mut self.module, builder, args_ptr);
+
+            let next_block = builder.ins().iconst(target_type, (block_count + 1) as i64);
+            builder.ins().return_(&[next_block]);
+        }
+
+        translation_ctx.block_counter += 1;
+
+        Ok((
+            [indecies, vec![block_count]].concat(),
+            translation_ctx.block_counter,
+            [blocks, vec![b]].concat(),
+        ))
+    }
+
+    /// Block 0 of a lifted function: grows the variables buffer past the closure
+    /// record and copies the captured values into it.
+    fn closure_prologue(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        ctx_ptr_var: Variable,
+        runtime_var: Variable,
+        params: usize,
+        captures: usize,
+        translation_ctx: &mut TranslationContext,
+    ) -> Block {
+        let target_type = self.module.target_config().pointer_type();
+        let b = builder.create_block();
+        builder.switch_to_block(b);
+
+        let ctx_ptr = builder.use_var(ctx_ptr_var);
+        let old_vars_ptr =
+            builder
+                .ins()
+                .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_VARS);
+        let vars_len = params + 1 + captures;
+        let buffer_size = builder.ins().iconst(target_type, (vars_len * 8) as i64);
+        let after_realloc = builder.create_block();
+        builder.append_block_param(after_realloc, target_type);
+        let runtime_ptr = builder.use_var(runtime_var);
+        call_realloc(
+            &mut self.module,
+            builder,
+            old_vars_ptr,
+            buffer_size,
+            after_realloc,
+            &[],
+            Some(runtime_ptr),
+        );
+        builder.switch_to_block(after_realloc);
+
+        let vars_ptr = *builder.block_params(after_realloc).first().unwrap();
+        let ctx_ptr = builder.use_var(ctx_ptr_var);
+        let vars_len = builder.ins().iconst(target_type, vars_len as i64);
+        builder
+            .ins()
+            .store(MemFlags::new(), vars_ptr, ctx_ptr, PROCESS_CTX_VARS);
+        builder
+            .ins()
+            .store(MemFlags::new(), vars_len, ctx_ptr, PROCESS_CTX_VARS_LEN);
+
+        let closure_ptr =
+            builder
+                .ins()
+                .load(target_type, MemFlags::new(), vars_ptr, (params * 8) as i32);
+        for i in 0..captures {
+            let val = builder.ins().load(
+                target_type,
+                MemFlags::new(),
+                closure_ptr,
+                ((1 + i) * 8) as i32,
+            );
+            builder.ins().store(
+                MemFlags::new(),
+                val,
+                vars_ptr,
+                ((params + 1 + i) * 8) as i32,
+            );
+        }
+
+        let next_block = builder.ins().iconst(target_type, 1);
+        builder.ins().return_(&[next_block]);
+        translation_ctx.block_counter += 1;
+        b
+    }
+
+    /// Allocates a closure record `[function address, captured values..]` in the arena and yields
+    /// its address as the value of the block.
+    fn translate_closure(
+        &mut self,
+        id: FuncId,
+        captures: &[usize],
+        builder: &mut FunctionBuilder,
+        ctx_ptr_var: Variable,
+        runtime_var: Variable,
+        translation_ctx: &mut TranslationContext,
+    ) -> (usize, Block) {
+        let target_type = self.module.target_config().pointer_type();
+        let b = builder.create_block();
+        builder.switch_to_block(b);
+
+        let ctx_ptr = builder.use_var(ctx_ptr_var);
+        let arena = builder
+            .ins()
+            .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_ARENA);
+        let buffer_size = builder
+            .ins()
+            .iconst(target_type, ((1 + captures.len()) * 8) as i64);
+        let runtime_ptr = builder.use_var(runtime_var);
+        let closure_ptr = arena_malloc(&mut self.module, builder, arena, buffer_size, runtime_ptr);
+
+        let callee = self.module.declare_func_in_func(id, builder.func);
+        let callee = builder.ins().func_addr(target_type, callee);
+        builder.ins().store(MemFlags::new(), callee, closure_ptr, 0);
+
+        let ctx_ptr = builder.use_var(ctx_ptr_var);
+        let vars_ptr = builder
+            .ins()
+            .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_VARS);
+        for (i, slot) in captures.iter().enumerate() {
+            let val = builder
+                .ins()
+                .load(target_type, MemFlags::new(), vars_ptr, (slot * 8) as i32);
+            builder
+                .ins()
+                .store(MemFlags::new(), val, closure_ptr, ((1 + i) * 8) as i32);
+        }
+        self.store_value(builder, ctx_ptr, closure_ptr, translation_ctx);
+
+        let block_count = translation_ctx.block_counter;
+        let next_block = builder.ins().iconst(target_type, (block_count + 1) as i64);
+        builder.ins().return_(&[next_block]);
+        translation_ctx.block_counter += 1;
+
+        (block_count, b)
+    }
+
+    /// Binds the value of the previous block to a new variable `name`.
+    fn bind_variable(
+        &mut self,
+        name: String,
+        builder: &mut FunctionBuilder,
+        ctx_ptr_var: Variable,
+        runtime_var: Variable,
+        translation_ctx: &mut TranslationContext,
+    ) -> (usize, Block) {
+        let target_type = self.module.target_config().pointer_type();
+        let b = builder.create_block();
+        builder.switch_to_block(b);
+
+        let ctx_ptr = builder.use_var(ctx_ptr_var);
+        let old_vars_ptr =
+            builder
+                .ins()
+                .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_VARS);
+        let old_vars_ptr_len =
+            builder
+                .ins()
+                .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_VARS_LEN);
+
+        // Variables live in the slot numbered at compile time, which only
+        // matches the order they are reached in when no branch is skipped.
+        let slot = translation_ctx.var_counter;
+        let slot_len = builder.ins().iconst(target_type, (slot + 1) as i64);
+        let new_len = builder.ins().umax(old_vars_ptr_len, slot_len);
+        let new_buffer_size = builder.ins().imul_imm(new_len, 8);
+
+        let new_len_var = builder.declare_var(target_type);
+        builder.def_var(new_len_var, new_len);
+
+        let after_realloc = builder.create_block();
+        builder.append_block_param(after_realloc, target_type);
+        let runtime_ptr = builder.use_var(runtime_var);
+        call_realloc(
+            &mut self.module,
+            builder,
+            old_vars_ptr,
+            new_buffer_size,
+            after_realloc,
+            &[],
+            Some(runtime_ptr),
+        );
+        builder.switch_to_block(after_realloc);
 
-                let last_i = builder.ins().iadd_imm(new_len, -1);
-                let offset = builder.ins().imul_imm(last_i, 8);
-                let ptr_with_offset = builder.ins().iadd(new_ptr, offset);
+        let new_ptr = *builder.block_params(after_realloc).first().unwrap();
+        let ctx_ptr = builder.use_var(ctx_ptr_var);
+        let new_len = builder.use_var(new_len_var);
 
-                let val =
-                    builder
-                        .ins()
-                        .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_TEMP_VAL);
+        builder
+            .ins()
+            .store(MemFlags::new(), new_ptr, ctx_ptr, PROCESS_CTX_VARS);
+        builder
+            .ins()
+            .store(MemFlags::new(), new_len, ctx_ptr, PROCESS_CTX_VARS_LEN);
 
-                builder
-                    .ins()
-                    .store(MemFlags::new(), val, ptr_with_offset, 0);
+        let val = builder
+            .ins()
+            .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_TEMP_VAL);
 
-                if let TranslationType::Call(arg_i) = translation_ctx.tr_type {
-                    let args_ptr = builder.ins().load(
-                        target_type,
-                        MemFlags::new(),
-                        ctx_ptr,
-                        PROCESS_CTX_CALL_ARGS_TEMP,
-                    );
-                    builder
-                        .ins()
-                        .store(MemFlags::new(), val, args_ptr, (arg_i * 8) as i32);
-                }
+        builder
+            .ins()
+            .store(MemFlags::new(), val, new_ptr, (slot * 8) as i32);
+        self.store_value(builder, ctx_ptr, val, translation_ctx);
 
-                let next_block = builder
-                    .ins()
-                    .iconst(target_type, (translation_ctx.block_counter + 1) as i64);
-                builder.ins().return_(&[next_block]);
+        let block_count = translation_ctx.block_counter;
+        let next_block = builder.ins().iconst(target_type, (block_count + 1) as i64);
+        builder.ins().return_(&[next_block]);
 
-                let Expression::Ident(name) = *name else {
-                    bail!("Not a ident")
-                };
+        translation_ctx.variables.insert(name, slot);
+        translation_ctx.var_counter += 1;
+        translation_ctx.block_counter += 1;
 
-                let val = translation_ctx.var_counter;
-                translation_ctx.variables.insert(name, val);
-                translation_ctx.var_counter += 1;
+        (block_count, b)
+    }
 
-                translation_ctx.block_counter += 1;
+    /// Translates the body of an `if` branch, whose variables go out of scope after it.
+    fn translate_branch(
+        &mut self,
+        body: Expressions,
+        builder: &mut FunctionBuilder,
+        ctx_ptr_var: Variable,
+        runtime_var: Variable,
+        translation_ctx: &mut TranslationContext,
+    ) -> Result<(Vec<usize>, Vec<Block>)> {
+        let variables = translation_ctx.variables.clone();
+        let mut indecies = vec![];
+        let mut blocks = vec![];
+        for expression in body.0 {
+            translation_ctx.tr_type = TranslationType::Default;
+            let (indecies_, _, blocks_) = self.translate_expression(
+                expression,
+                builder,
+                ctx_ptr_var,
+                runtime_var,
+                translation_ctx,
+            )?;
+            indecies = [indecies, indecies_].concat();
+            blocks = [blocks, blocks_].concat();
+        }
+        translation_ctx.variables = variables;
+        Ok((indecies, blocks))
+    }
 
-                Ok((
-                    [indecies, vec![block_index]].concat(),
-                    translation_ctx.block_counter,
-                    [blocks, vec![b]].concat(),
+    /// Reads an operator operand, which the middleware reduces to an identifier or a literal.
+    fn operand_value(
+        &mut self,
+        expression: Expression,
+        builder: &mut FunctionBuilder,
+        ctx_ptr: Value,
+        translation_ctx: &TranslationContext,
+    ) -> Result<Value> {
+        let target_type = self.module.target_config().pointer_type();
+        match expression {
+            Expression::Lit(lit, _) => Ok(builder.ins().iconst(target_type, lit)),
+            Expression::Ident(name, span) => {
+                let Some(&val_index) = translation_ctx.variables.get(&name) else {
+                    return Err(Diagnostic::error(
+                        span,
+                        format!("Variable `{name}` is not defined"),
+                    )
+                    .into());
+                };
+                let vars_ptr =
+                    builder
+                        .ins()
+                        .load(target_type, MemFlags::new(), ctx_ptr, PROCESS_CTX_VARS);
+                Ok(builder.ins().load(
+                    target_type,
+                    MemFlags::new(),
+                    vars_ptr,
+                    (val_index * 8) as i32,
                 ))
             }
-            Expression::Block(body) => {
-                let mut indecies = vec![];
-                let mut blocks = vec![];
-                for expression in body.0 {
-                    let (indecies_, last_block_, blocks_) = self.translate_expression(
-                        expression,
-                        builder,
-                        ctx_ptr_var,
-                        runtime_var,
-                        translation_ctx,
-                    )?;
-                    indecies = [indecies, indecies_].concat();
-                    blocks = [blocks, blocks_].concat();
-                }
-                let b = builder.create_block();
-                builder.switch_to_block(b);
+            expression => {
+                Err(Diagnostic::error(expression.span(), "Expected a variable or a literal").into())
+            }
+        }
+    }
 
-                let ctx_ptr = builder.use_var(ctx_ptr_var);
+    /// Stores the value of an expression into the temp slot and, inside a call, into its argument.
+    fn store_value(
+        &mut self,
+        builder: &mut FunctionBuilder,
+        ctx_ptr: Value,
+        val: Value,
+        translation_ctx: &TranslationContext,
+    ) {
+        let target_type = self.module.target_config().pointer_type();
+        builder
+            .ins()
+            .store(MemFlags::new(), val, ctx_ptr, PROCESS_CTX_TEMP_VAL);
+        if let TranslationType::Call(arg_i) = translation_ctx.tr_type {
+            let args_ptr = builder.ins().load(
+                target_type,
+                MemFlags::new(),
+                ctx_ptr,
+                PROCESS_CTX_CALL_ARGS_TEMP,
+            );
+            builder
+                .ins()
+                .store(MemFlags::new(), val, args_ptr, (arg_i * 8) as i32);
+        }
+    }
+}
 
-                if let TranslationType::Call(arg_i) = translation_ctx.tr_type {
-                    let res_of_block = builder.ins().load(
-                        target_type,
-                        MemFlags::new(),
-                        ctx_ptr,
-                        PROCESS_CTX_TEMP_VAL,
-                    );
-                    let args_ptr = builder.ins().load(
-                        target_type,
-                        MemFlags::new(),
-                        ctx_ptr,
-                        PROCESS_CTX_CALL_ARGS_TEMP,
-                    );
-                    builder.ins().store(
-                        MemFlags::new(),
-                        res_of_block,
-                        args_ptr,
-                        (arg_i * 8) as i32,
-                    );
-                }
+/// Cranelift type of a scalar type, values of other types are pointer-sized.
+fn cranelift_type(ty: &Type) -> types::Type {
+    match ty {
+        Type::F32 => types::F32,
+        Type::F64 => types::F64,
+        Type::Char => types::I32,
+        Type::Bool => types::I8,
+        ty => match ty.bits() {
+            Some(8) => types::I8,
+            Some(16) => types::I16,
+            Some(32) => types::I32,
+            _ => types::I64,
+        },
+    }
+}
 
-                let block_count = translation_ctx.block_counter;
+/// Layout of a struct type.
+fn type_def(ty: &Type) -> TypeDef {
+    let Type::Struct { name, fields } = ty else {
+        unreachable!("only structs have a layout")
+    };
+    let fields = fields
+        .iter()
+        .map(|(name, ty)| field_def(name, ty))
+        .collect();
+    TypeDef::new(name, fields)
+}
 
-                let block_count_val = builder.ins().iconst(target_type, (block_count + 1) as i64);
-                builder.ins().return_(&[block_count_val]);
+/// Layout of a variant of an enum type: its tag, then its values like the fields
+/// of a struct. A value only takes the size of its own variant.
+fn variant_def(ty: &Type, variant: usize) -> TypeDef {
+    let Type::Enum { name, variants } = ty else {
+        unreachable!("only enums have variants")
+    };
+    let (variant_name, payload) = &variants[variant];
+    let fields = [Field::new("tag", types::I64)]
+        .into_iter()
+        .chain(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(i, ty)| field_def(&i.to_string(), ty)),
+        )
+        .collect();
+    TypeDef::new(&format!("{name}::{variant_name}"), fields)
+}
 
-                translation_ctx.block_counter += 1;
+/// Nested structs are stored inline, enums and other values as pointer-sized slots.
+fn field_def(name: &str, ty: &Type) -> Field {
+    match ty {
+        Type::Struct { .. } => Field::nested(name, &type_def(ty)),
+        ty => Field::new(name, cranelift_type(ty)),
+    }
+}
 
-                Ok((
-                    [indecies, vec![block_count]].concat(),
-                    translation_ctx.block_counter,
-                    [blocks, vec![b]].concat(),
-                ))
-            }
-            _ => unimplemented!(),
+/// Reads a field of type `ty` extended to the slot width, a nested struct is addressed in place.
+fn load_field(
+    builder: &mut FunctionBuilder,
+    ptr: Value,
+    field: &Field,
+    ty: &Type,
+    slot_type: types::Type,
+) -> Value {
+    match field.ty() {
+        Some(field_ty) => {
+            let val = builder.ins().load(
+                field_ty.as_int(),
+                MemFlags::new(),
+                ptr,
+                field.offset() as i32,
+            );
+            widen(builder, ty, slot_type, val)
         }
+        None => builder.ins().iadd_imm(ptr, field.offset() as i64),
+    }
+}
+
+/// Scalars are kept as 64-bit patterns in variables and arguments, operators
+/// work on the declared type and integers wrap around on overflow.
+fn narrow(builder: &mut FunctionBuilder, ty: &Type, val: Value) -> Value {
+    let narrow_type = cranelift_type(ty);
+    let int_type = narrow_type.as_int();
+    let val = if int_type == builder.func.dfg.value_type(val) {
+        val
+    } else {
+        builder.ins().ireduce(int_type, val)
+    };
+    if narrow_type.is_float() {
+        builder.ins().bitcast(narrow_type, MemFlags::new(), val)
+    } else {
+        val
     }
 }
 
+/// Extends a result of [`narrow`] operands back to the slot width.
+fn widen(builder: &mut FunctionBuilder, ty: &Type, slot_type: types::Type, val: Value) -> Value {
+    let val_type = builder.func.dfg.value_type(val);
+    let val = if val_type.is_float() {
+        builder
+            .ins()
+            .bitcast(val_type.as_int(), MemFlags::new(), val)
+    } else {
+        val
+    };
+    if builder.func.dfg.value_type(val) == slot_type {
+        return val;
+    }
+    if ty.is_signed() {
+        builder.ins().sextend(slot_type, val)
+    } else {
+        builder.ins().uextend(slot_type, val)
+    }
+}
+
+/// Float operators, the checker rejects `%`, `&&` and `||` on floats.
+fn float_binary(
+    builder: &mut FunctionBuilder,
+    op: BinaryOp,
+    lhs: Value,
+    rhs: Value,
+    target_type: types::Type,
+) -> Value {
+    let cc = match op {
+        BinaryOp::Add => return builder.ins().fadd(lhs, rhs),
+        BinaryOp::Sub => return builder.ins().fsub(lhs, rhs),
+        BinaryOp::Mul => return builder.ins().fmul(lhs, rhs),
+        BinaryOp::Div => return builder.ins().fdiv(lhs, rhs),
+        BinaryOp::Eq => FloatCC::Equal,
+        BinaryOp::Ne => FloatCC::NotEqual,
+        BinaryOp::Lt => FloatCC::LessThan,
+        BinaryOp::Le => FloatCC::LessThanOrEqual,
+        BinaryOp::Gt => FloatCC::GreaterThan,
+        BinaryOp::Ge => FloatCC::GreaterThanOrEqual,
+        BinaryOp::Rem => unreachable!("rejected by the checker"),
+        BinaryOp::And | BinaryOp::Or => unreachable!("lowered to `if` by the middleware"),
+    };
+    let cmp = builder.ins().fcmp(cc, lhs, rhs);
+    builder.ins().uextend(target_type, cmp)
+}
+
 pub fn call_realloc(
     module: &mut dyn Module,
     builder: &mut FunctionBuilder,
@@ -844,6 +3332,7 @@ pub fn call_realloc(
     buffer_size: Value,
     block_after_call: Block,
     block_args: &[BlockArg],
+    runtime_ptr: Option<Value>,
 ) {
     let ty = module.target_config().pointer_type();
     let mut realloc_sig = module.make_signature();
@@ -859,7 +3348,7 @@ pub fn call_realloc(
     let call = builder
         .ins()
         .call(local_callee_realloc, &[old_ptr, buffer_size]);
-    let ptr: Value = *builder.inst_results(call).get(0).unwrap();
+    let ptr: Value = *builder.inst_results(call).first().unwrap();
 
     let cond_block = builder.create_block();
     for _ in block_args {
@@ -878,7 +3367,7 @@ pub fn call_realloc(
 
     let len = builder.block_params(cond_block).len();
     let ptr = *builder.block_params(cond_block).last().unwrap();
-    let block_args: Vec<BlockArg> = (&builder.block_params(cond_block)[..len - 1])
+    let block_args: Vec<BlockArg> = builder.block_params(cond_block)[..len - 1]
         .iter()
         .map(|x| BlockArg::Value(*x))
         .collect();
@@ -894,5 +3383,346 @@ pub fn call_realloc(
 
     builder.switch_to_block(trap_block);
     builder.seal_block(trap_block);
-    builder.ins().trap(TrapCode::HEAP_OUT_OF_BOUNDS);
+    allocation_failed(builder, ty, runtime_ptr);
+}
+
+/// Stops the running process, which fails with the reason of `code`.
+pub(crate) fn fault(
+    builder: &mut FunctionBuilder,
+    target_type: types::Type,
+    runtime_ptr: Value,
+    code: CompilerTrapCode,
+) {
+    let reason = builder.ins().iconst(target_type, code.reason());
+    builder
+        .ins()
+        .store(MemFlags::new(), reason, runtime_ptr, RUNTIME_FAULT);
+    let finished = builder.ins().iconst(target_type, -1);
+    builder.ins().return_(&[finished]);
+}
+
+/// Stops the running process when `condition` holds, the code after it runs otherwise.
+fn fault_if(
+    builder: &mut FunctionBuilder,
+    target_type: types::Type,
+    runtime_ptr: Value,
+    condition: Value,
+    code: CompilerTrapCode,
+) {
+    let fault_block = builder.create_block();
+    let continue_block = builder.create_block();
+    builder
+        .ins()
+        .brif(condition, fault_block, &[], continue_block, &[]);
+    builder.switch_to_block(fault_block);
+    fault(builder, target_type, runtime_ptr, code);
+    builder.switch_to_block(continue_block);
+}
+
+/// Calls a function of the scheduler of the runtime, all its params and its
+/// return are pointer-sized.
+fn call_scheduler(
+    module: &mut dyn Module,
+    builder: &mut FunctionBuilder,
+    name: &str,
+    args: &[Value],
+    returns: bool,
+) -> Option<Value> {
+    let target_type = module.target_config().pointer_type();
+    let mut sig = module.make_signature();
+    for _ in args {
+        sig.params.push(AbiParam::new(target_type));
+    }
+    if returns {
+        sig.returns.push(AbiParam::new(target_type));
+    }
+    let callee = module
+        .declare_function(name, Linkage::Import, &sig)
+        .unwrap();
+    let local_callee = module.declare_func_in_func(callee, builder.func);
+    let call = builder.ins().call(local_callee, args);
+    builder.inst_results(call).first().copied()
+}
+
+/// Empty arena, its own `prev` and `next`.
+fn create_arena(module: &mut dyn Module, builder: &mut FunctionBuilder) -> Value {
+    let target_type = module.target_config().pointer_type();
+    let size = builder.ins().iconst(target_type, ARENA_HEADER_SIZE);
+    let after_call = builder.create_block();
+    builder.append_block_param(after_call, target_type);
+    call_malloc(module, builder, size, after_call, &[], None);
+    builder.switch_to_block(after_call);
+    builder.seal_block(after_call);
+    let arena = *builder.block_params(after_call).first().unwrap();
+    builder
+        .ins()
+        .store(MemFlags::new(), arena, arena, ARENA_PREV);
+    builder
+        .ins()
+        .store(MemFlags::new(), arena, arena, ARENA_NEXT);
+    arena
+}
+
+/// Allocates `size` bytes owned by `arena`, linked in right after the sentinel.
+fn arena_malloc(
+    module: &mut dyn Module,
+    builder: &mut FunctionBuilder,
+    arena: Value,
+    size: Value,
+    runtime_ptr: Value,
+) -> Value {
+    let target_type = module.target_config().pointer_type();
+    let size = builder.ins().iadd_imm(size, ARENA_HEADER_SIZE);
+    let after_call = builder.create_block();
+    builder.append_block_param(after_call, target_type);
+    call_malloc(module, builder, size, after_call, &[], Some(runtime_ptr));
+    builder.switch_to_block(after_call);
+    builder.seal_block(after_call);
+    let node = *builder.block_params(after_call).first().unwrap();
+
+    let next = builder
+        .ins()
+        .load(target_type, MemFlags::new(), arena, ARENA_NEXT);
+    builder
+        .ins()
+        .store(MemFlags::new(), arena, node, ARENA_PREV);
+    builder.ins().store(MemFlags::new(), next, node, ARENA_NEXT);
+    builder.ins().store(MemFlags::new(), node, next, ARENA_PREV);
+    builder
+        .ins()
+        .store(MemFlags::new(), node, arena, ARENA_NEXT);
+    builder.ins().iadd_imm(node, ARENA_HEADER_SIZE)
+}
+
+/// Resizes an allocation of [`arena_malloc`], its neighbours are pointed at
+/// wherever it moved.
+fn arena_realloc(
+    module: &mut dyn Module,
+    builder: &mut FunctionBuilder,
+    ptr: Value,
+    size: Value,
+    runtime_ptr: Value,
+) -> Value {
+    let target_type = module.target_config().pointer_type();
+    let node = builder.ins().iadd_imm(ptr, -ARENA_HEADER_SIZE);
+    let size = builder.ins().iadd_imm(size, ARENA_HEADER_SIZE);
+    let after_call = builder.create_block();
+    builder.append_block_param(after_call, target_type);
+    call_realloc(
+        module,
+        builder,
+        node,
+        size,
+        after_call,
+        &[],
+        Some(runtime_ptr),
+    );
+    builder.switch_to_block(after_call);
+    builder.seal_block(after_call);
+    let node = *builder.block_params(after_call).first().unwrap();
+
+    let prev = builder
+        .ins()
+        .load(target_type, MemFlags::new(), node, ARENA_PREV);
+    let next = builder
+        .ins()
+        .load(target_type, MemFlags::new(), node, ARENA_NEXT);
+    builder.ins().store(MemFlags::new(), node, prev, ARENA_NEXT);
+    builder.ins().store(MemFlags::new(), node, next, ARENA_PREV);
+    builder.ins().iadd_imm(node, ARENA_HEADER_SIZE)
+}
+
+/// Frees every allocation of `arena`, then the arena itself.
+fn free_arena(module: &mut dyn Module, builder: &mut FunctionBuilder, arena: Value) {
+    let target_type = module.target_config().pointer_type();
+    let loop_block = builder.create_block();
+    let free_block = builder.create_block();
+    let exit_block = builder.create_block();
+    builder.append_block_param(loop_block, target_type);
+
+    let first = builder
+        .ins()
+        .load(target_type, MemFlags::new(), arena, ARENA_NEXT);
+    builder.ins().jump(loop_block, &[BlockArg::Value(first)]);
+
+    builder.switch_to_block(loop_block);
+    let node = *builder.block_params(loop_block).first().unwrap();
+    let done = builder.ins().icmp(IntCC::Equal, node, arena);
+    builder.ins().brif(done, exit_block, &[], free_block, &[]);
+
+    builder.switch_to_block(free_block);
+    builder.seal_block(free_block);
+    let next = builder
+        .ins()
+        .load(target_type, MemFlags::new(), node, ARENA_NEXT);
+    call_free(module, builder, node);
+    builder.ins().jump(loop_block, &[BlockArg::Value(next)]);
+    builder.seal_block(loop_block);
+
+    builder.switch_to_block(exit_block);
+    builder.seal_block(exit_block);
+    call_free(module, builder, arena);
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        backend::{Compiler, linker::Linker},
+        frontend::{module::Resolver, parser::parser, typeck::check},
+        middleware::{Expressions, LoweredModule},
+    };
+    use cranelift::object::ObjectModule;
+
+    #[test]
+    fn object_module_exports_main() {
+        let mut compiler = Compiler::<ObjectModule>::default();
+        let exprs = parser::exprs("main: -> i64 { 20 }").unwrap();
+        let types = check(&exprs).unwrap();
+        compiler
+            .translate(Expressions::lower(exprs, &types).unwrap())
+            .unwrap();
+
+        let obj_bytes = compiler.into_module().finish().emit().unwrap();
+        assert!(obj_bytes.windows(5).any(|w| w == b"main\0"));
+    }
+
+    fn run_executable(name: &str, code: &str) -> std::process::ExitStatus {
+        let dir = std::env::temp_dir().join(format!("unicorn-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let exe = dir.join(name);
+
+        Compiler::<ObjectModule>::default()
+            .compile_executable(code, &exe, &Linker::default())
+            .unwrap();
+        let status = std::process::Command::new(&exe).status().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        status
+    }
+
+    #[test]
+    fn modules_link_from_separate_objects() {
+        let dir = std::env::temp_dir().join(format!("unicorn-modules-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("math.uniq"),
+            "module math\nlet name: str = \"math\"\nsquare: x(i64) -> i64 { x * x }",
+        )
+        .unwrap();
+        let code = "import math\nlet name: str = \"app\"\nmain: -> i64 { math::square { 5 } + 1 }";
+        let modules = Resolver::default()
+            .resolve(dir.join("app.uniq"), code.to_owned())
+            .unwrap();
+        let exe = dir.join("app");
+        std::fs::write(dir.join("app.o"), "not an object").unwrap();
+
+        Compiler::<ObjectModule>::default()
+            .compile_modules_executable(
+                LoweredModule::lower_all(modules).unwrap(),
+                &exe,
+                &Linker::default(),
+            )
+            .unwrap();
+        let status = std::process::Command::new(&exe).status().unwrap();
+        let leftover = std::fs::read_dir(&dir)
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("o".as_ref()))
+            .count();
+        let kept = std::fs::read_to_string(dir.join("app.o")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(status.code(), Some(26));
+        assert_eq!(leftover, 1);
+        assert_eq!(kept, "not an object");
+    }
+
+    #[test]
+    fn compile_executable_reports_linker_errors() {
+        let dir = std::env::temp_dir().join(format!("unicorn-link-error-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let linker = Linker {
+            driver: "unicorn-missing-linker".into(),
+            ..Linker::default()
+        };
+        let err = Compiler::<ObjectModule>::default()
+            .compile_executable("main: -> i64 { 20 }", dir.join("app"), &linker)
+            .unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(err.to_string().contains("unicorn-missing-linker"));
+    }
+
+    #[test]
+    fn compile_executable_links_runtime() {
+        let status = run_executable("basic_return", "main: -> i64 { 20 }");
+        assert_eq!(status.code(), Some(20));
+    }
+
+    #[test]
+    fn division_by_zero_traps() {
+        let status = run_executable(
+            "division_by_zero",
+            "main: -> i64 { let z: i64 = 0\n 1 / z }",
+        );
+        assert!(!status.success());
+        assert_eq!(status.code(), None);
+    }
+
+    #[test]
+    fn signed_division_overflow_traps() {
+        let status = run_executable(
+            "division_overflow",
+            "main: -> i64 { let n: i64 = -9223372036854775807 - 1\n let m: i64 = -1\n n / m }",
+        );
+        assert!(!status.success());
+        assert_eq!(status.code(), None);
+    }
+
+    #[test]
+    fn index_out_of_bounds_traps() {
+        let status = run_executable(
+            "index_out_of_bounds",
+            "main: -> i64 { let xs: [i64] = [1 2]\n push { xs 3 }\n xs[3] }",
+        );
+        assert!(!status.success());
+        assert_eq!(status.code(), None);
+    }
+
+    #[test]
+    fn read_line_reads_stdin() {
+        let code = r#"
+            main: -> i64 {
+                let total: i64 = 0
+                loop {
+                    let line: str = read_line {}
+                    let n: i64 = str_len { line }
+                    if n == 0 { break }
+                    total = total * 10 + n
+                }
+                total
+            }
+        "#;
+        let dir = std::env::temp_dir().join(format!("unicorn-read_line-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let exe = dir.join("read_line");
+        Compiler::<ObjectModule>::default()
+            .compile_executable(code, &exe, &Linker::default())
+            .unwrap();
+        let mut child = std::process::Command::new(&exe)
+            .stdin(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        std::io::Write::write_all(&mut stdin, b"a\nbb\nccc").unwrap();
+        drop(stdin);
+        let status = child.wait().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(status.code(), Some(233));
+    }
+
+    #[test]
+    fn deadlock_traps() {
+        let status = run_executable("deadlock", "main: -> i64 { receive }");
+        assert!(!status.success());
+        assert_eq!(status.code(), None);
+    }
 }
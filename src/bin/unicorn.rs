@@ -0,0 +1,303 @@
+use anyhow::*;
+use std::{env, fs, path::PathBuf, process::exit};
+
+use unicorn::{
+    backend::{Compiler, jit::JitCompiler, linker::Linker},
+    diagnostics::{Diagnostic, FileDiagnostic},
+    frontend::{module::Resolver, parser::parse},
+    general_compiler::REDUCTIONS_LIMIT,
+    middleware::LoweredModule,
+};
+
+const USAGE: &str = "\
+Usage: unicorn <command> <input> [options]
+
+Commands:
+    build   Compile <input> into an executable
+    run     Compile <input> in memory and run it, exiting with the `main` result
+    check   Parse and translate <input> without writing anything
+    emit    Write the stage selected by --emit
+
+Options:
+    -o <path>               Output path (stdout for textual stages when omitted)
+    --emit=<stage>          ast | middleware | clif | obj | exe
+    --linker=<linker>       cc (default) or a linker passed to `cc -fuse-ld=`, e.g. mold, lld
+    --runtime=<path>        Runtime library to link with (default: libunicorn_runtime.a
+                            from $UNICORN_RUNTIME or next to this executable)
+    --target=<triple>       Target triple to compile for (default: host), not for `run`
+    --opt-level=<level>     none | speed | speed_and_size (default)
+    --threads=<n>           Scheduler threads of the program (default: $UNICORN_THREADS
+                            when it runs, or one per CPU)
+    --reductions=<n>        Blocks a process runs before the next one gets its turn
+                            (default: 2000)
+    --virtual-clock         Only let time pass once every process waits, right to
+                            the next timer, so that timers fire the same way each run
+    --module-path=<dir>     Directory to look for imported modules in after the one
+                            of the importing file, can be repeated
+    -h, --help              Print this message
+";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Ast,
+    Middleware,
+    Clif,
+    Obj,
+    Exe,
+}
+
+impl Stage {
+    fn parse(stage: &str) -> Result<Self> {
+        Ok(match stage {
+            "ast" => Stage::Ast,
+            "middleware" => Stage::Middleware,
+            "clif" => Stage::Clif,
+            "obj" => Stage::Obj,
+            "exe" => Stage::Exe,
+            _ => bail!("Unknown stage `{stage}`, expected ast|middleware|clif|obj|exe"),
+        })
+    }
+}
+
+enum Subcommand {
+    Build,
+    Run,
+    Check,
+    Emit,
+}
+
+struct Args {
+    subcommand: Subcommand,
+    input: PathBuf,
+    output: Option<PathBuf>,
+    emit: Option<Stage>,
+    linker: String,
+    runtime: Option<PathBuf>,
+    target: Option<String>,
+    opt_level: String,
+    threads: usize,
+    reductions: i64,
+    virtual_clock: bool,
+    module_path: Vec<PathBuf>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let subcommand = match args.next().as_deref() {
+            Some("build") => Subcommand::Build,
+            Some("run") => Subcommand::Run,
+            Some("check") => Subcommand::Check,
+            Some("emit") => Subcommand::Emit,
+            Some("-h" | "--help") => {
+                print!("{USAGE}");
+                exit(0)
+            }
+            Some(other) => bail!("Unknown command `{other}`\n\n{USAGE}"),
+            None => bail!("Missing command\n\n{USAGE}"),
+        };
+
+        let mut input = None;
+        let mut output = None;
+        let mut emit = None;
+        let mut linker = String::from("cc");
+        let mut runtime = None;
+        let mut target = None;
+        let mut opt_level = String::from("speed_and_size");
+        let mut threads = 0;
+        let mut reductions = REDUCTIONS_LIMIT;
+        let mut virtual_clock = false;
+        let mut module_path = vec![];
+
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with('-') => (flag.to_owned(), Some(value)),
+                _ => (arg.clone(), None),
+            };
+            let mut value = || match inline_value {
+                Some(value) => Ok(value.to_owned()),
+                None => args
+                    .next()
+                    .ok_or_else(|| anyhow!("Missing value for `{flag}`")),
+            };
+
+            match flag.as_str() {
+                "-o" | "--output" => output = Some(PathBuf::from(value()?)),
+                "--emit" => emit = Some(Stage::parse(&value()?)?),
+                "--linker" => linker = value()?,
+                "--runtime" => runtime = Some(PathBuf::from(value()?)),
+                "--target" => target = Some(value()?),
+                "--opt-level" => opt_level = value()?,
+                "--threads" => {
+                    let value = value()?;
+                    threads = value
+                        .parse()
+                        .ok()
+                        .filter(|threads| *threads > 0)
+                        .ok_or_else(|| anyhow!("Invalid thread count `{value}`"))?
+                }
+                "--reductions" => {
+                    let value = value()?;
+                    reductions = value
+                        .parse()
+                        .ok()
+                        .filter(|reductions| *reductions > 0)
+                        .ok_or_else(|| anyhow!("Invalid reduction count `{value}`"))?
+                }
+                "--virtual-clock" => virtual_clock = true,
+                "--module-path" => module_path.push(PathBuf::from(value()?)),
+                "-h" | "--help" => {
+                    print!("{USAGE}");
+                    exit(0)
+                }
+                _ if arg.starts_with('-') => bail!("Unknown option `{arg}`\n\n{USAGE}"),
+                _ if input.is_none() => input = Some(PathBuf::from(arg)),
+                _ => bail!("Unexpected argument `{arg}`"),
+            }
+        }
+
+        Ok(Self {
+            subcommand,
+            input: input.ok_or_else(|| anyhow!("Missing input file\n\n{USAGE}"))?,
+            output,
+            emit,
+            linker,
+            runtime,
+            target,
+            opt_level,
+            threads,
+            reductions,
+            virtual_clock,
+            module_path,
+        })
+    }
+
+    fn compiler(&self) -> Result<Compiler> {
+        let compiler = Compiler::with_target(self.target.as_deref(), &self.opt_level)?
+            .with_threads(self.threads)
+            .with_reductions(self.reductions);
+        Ok(match self.virtual_clock {
+            true => compiler.with_virtual_clock(),
+            false => compiler,
+        })
+    }
+
+    fn linker(&self) -> Linker {
+        Linker {
+            fuse_ld: (self.linker != "cc").then(|| self.linker.clone()),
+            runtime: self.runtime.clone(),
+            ..Linker::default()
+        }
+    }
+
+    /// Loads the modules `input` imports and lowers them with it, the program last.
+    fn modules(&self, input: &str) -> Result<Vec<LoweredModule>> {
+        let modules =
+            Resolver::new(self.module_path.clone()).resolve(&self.input, input.to_owned())?;
+        Ok(LoweredModule::lower_all(modules)?)
+    }
+
+    fn output_or(&self, extension: &str) -> PathBuf {
+        self.output
+            .clone()
+            .unwrap_or_else(|| self.input.with_extension(extension))
+    }
+}
+
+fn main() {
+    let args = match Args::parse(env::args().skip(1)) {
+        Result::Ok(args) => args,
+        Err(err) => {
+            eprintln!("error: {err:#}");
+            exit(1)
+        }
+    };
+    let input = match fs::read_to_string(&args.input) {
+        Result::Ok(input) => input,
+        Err(err) => {
+            eprintln!("error: Failed to read `{}`: {err}", args.input.display());
+            exit(1)
+        }
+    };
+
+    if let Err(err) = drive(&args, &input) {
+        if let Some(diagnostic) = err.downcast_ref::<FileDiagnostic>() {
+            eprint!("{}", diagnostic.render())
+        } else if let Some(diagnostic) = err.downcast_ref::<Diagnostic>() {
+            eprint!(
+                "{}",
+                diagnostic.render(&args.input.display().to_string(), &input)
+            )
+        } else {
+            eprintln!("error: {err:#}")
+        }
+        exit(1)
+    }
+}
+
+fn drive(args: &Args, input: &str) -> Result<()> {
+    match args.subcommand {
+        Subcommand::Build => emit(args, input, args.emit.unwrap_or(Stage::Exe)),
+        Subcommand::Emit => {
+            let stage = args
+                .emit
+                .ok_or_else(|| anyhow!("`emit` requires --emit=<stage>"))?;
+            emit(args, input, stage)
+        }
+        Subcommand::Check => {
+            let mut compiler = args.compiler()?;
+            for module in args.modules(input)? {
+                compiler.translate_module(module)?;
+            }
+            Ok(())
+        }
+        Subcommand::Run => {
+            if args.target.is_some() {
+                bail!("`run` executes on this machine, --target is only for `build` and `emit`");
+            }
+            let compiler = JitCompiler::with_opt_level(&args.opt_level)?
+                .with_threads(args.threads)
+                .with_reductions(args.reductions);
+            let compiler = match args.virtual_clock {
+                true => compiler.with_virtual_clock(),
+                false => compiler,
+            };
+            let result = compiler.run_modules(args.modules(input)?)?;
+            exit(result as i32)
+        }
+    }
+}
+
+fn emit(args: &Args, input: &str, stage: Stage) -> Result<()> {
+    match stage {
+        Stage::Ast => write_text(args, format!("{:#?}\n", parse(input)?)),
+        Stage::Middleware => {
+            let program = args.modules(input)?.pop().unwrap();
+            write_text(args, format!("{:#?}\n", program.expressions))
+        }
+        Stage::Clif => {
+            let mut compiler = args.compiler()?.with_clif();
+            for module in args.modules(input)? {
+                compiler.translate_module(module)?;
+            }
+            write_text(args, compiler.clif().unwrap_or_default().to_owned())
+        }
+        Stage::Obj => {
+            args.compiler()?
+                .compile_modules(args.modules(input)?, args.output_or("o"))?;
+            Ok(())
+        }
+        Stage::Exe => args.compiler()?.compile_modules_executable(
+            args.modules(input)?,
+            args.output_or(""),
+            &args.linker(),
+        ),
+    }
+}
+
+fn write_text(args: &Args, text: String) -> Result<()> {
+    match &args.output {
+        Some(path) => fs::write(path, text)?,
+        None => print!("{text}"),
+    }
+    Ok(())
+}
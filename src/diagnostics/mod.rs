@@ -0,0 +1,147 @@
+use peg::{error::ParseError, str::LineCol};
+use std::{
+    fmt::{self, Display, Write},
+    path::PathBuf,
+};
+
+/// Byte range of a node in the source it was parsed from.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// Smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+/// Error pointing at a place in the source, see [`Diagnostic::render`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn error(span: Span, message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            span,
+        }
+    }
+
+    /// Renders the error with `file_name:line:column`, the source line and a caret underline.
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        let start = self.span.start.min(source.len());
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |i| start + i);
+        let line = &source[line_start..line_end];
+        let line_number = source[..line_start].matches('\n').count() + 1;
+        let column = source[line_start..start].chars().count() + 1;
+        let underline = self.span.end.clamp(start + 1, line_end.max(start + 1)) - start;
+
+        let gutter = " ".repeat(line_number.to_string().len());
+        let mut out = String::new();
+        let _ = writeln!(out, "error: {}", self.message);
+        let _ = writeln!(out, "{gutter}--> {file_name}:{line_number}:{column}");
+        let _ = writeln!(out, "{gutter} |");
+        let _ = writeln!(out, "{line_number} | {line}");
+        let _ = writeln!(
+            out,
+            "{gutter} | {}{}",
+            " ".repeat(column - 1),
+            "^".repeat(underline)
+        );
+        out
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// [`Diagnostic`] in one of the files of a program, see
+/// [`Resolver`](crate::frontend::module::Resolver).
+#[derive(Debug, Clone)]
+pub struct FileDiagnostic {
+    pub file: PathBuf,
+    pub source: String,
+    pub diagnostic: Diagnostic,
+}
+
+impl FileDiagnostic {
+    pub fn new(
+        file: impl Into<PathBuf>,
+        source: impl Into<String>,
+        diagnostic: Diagnostic,
+    ) -> Self {
+        Self {
+            file: file.into(),
+            source: source.into(),
+            diagnostic,
+        }
+    }
+
+    /// Renders the diagnostic against its own file, see [`Diagnostic::render`].
+    pub fn render(&self) -> String {
+        self.diagnostic
+            .render(&self.file.display().to_string(), &self.source)
+    }
+}
+
+impl Display for FileDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.file.display(), self.diagnostic)
+    }
+}
+
+impl std::error::Error for FileDiagnostic {}
+
+impl From<ParseError<LineCol>> for Diagnostic {
+    fn from(value: ParseError<LineCol>) -> Self {
+        let offset = value.location.offset;
+        Diagnostic::error(
+            Span::new(offset, offset + 1),
+            format!("Expected {}", value.expected),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::diagnostics::{Diagnostic, Span};
+
+    #[test]
+    fn render_points_at_span() {
+        let source = "main: -> i64 {\n  let a: i64 = foo\n}\n";
+        let start = source.find("foo").unwrap();
+        let diagnostic =
+            Diagnostic::error(Span::new(start, start + 3), "Variable `foo` is not defined");
+
+        assert_eq!(
+            diagnostic.render("main.uniq", source),
+            [
+                "error: Variable `foo` is not defined",
+                " --> main.uniq:2:16",
+                "  |",
+                "2 |   let a: i64 = foo",
+                "  |                ^^^",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+}
@@ -1 +1,3 @@
+pub mod module;
 pub mod parser;
+pub mod typeck;
@@ -0,0 +1,241 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    diagnostics::{Diagnostic, FileDiagnostic, Span},
+    frontend::parser::{ast::expr::Expr, parse},
+};
+
+/// Extension of the files of a program, `import name` loads `name.uniq`.
+pub const EXTENSION: &str = "uniq";
+
+/// Parsed source file of a program. A file starting with `module name` is the
+/// module `name`, which other files `import` and call as `name::function`.
+#[derive(Debug)]
+pub struct Module {
+    /// `None` for a program, whose `main` the executable runs.
+    pub name: Option<String>,
+    pub path: PathBuf,
+    pub source: String,
+    pub exprs: Vec<Expr>,
+    /// Modules imported by the file with the span of their `import`.
+    pub imports: Vec<(String, Span)>,
+}
+
+impl Module {
+    pub fn parse(path: impl Into<PathBuf>, source: String) -> Result<Self, FileDiagnostic> {
+        let path = path.into();
+        let error = |diagnostic| FileDiagnostic::new(&path, &source, diagnostic);
+        let exprs = parse(&source).map_err(error)?;
+
+        let mut name = None;
+        let mut imports = vec![];
+        for (i, expr) in exprs.iter().enumerate() {
+            match expr {
+                Expr::Module(module, _) if i == 0 => {
+                    name = Some(module_name(module).map_err(error)?.to_owned())
+                }
+                Expr::Module(_, span) => {
+                    return Err(error(Diagnostic::error(
+                        *span,
+                        "A module is declared by the first item of its file",
+                    )));
+                }
+                Expr::Import(module, span) => {
+                    imports.push((module_name(module).map_err(error)?.to_owned(), *span))
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            name,
+            path,
+            source,
+            exprs,
+            imports,
+        })
+    }
+}
+
+fn module_name(expr: &Expr) -> Result<&str, Diagnostic> {
+    match expr {
+        Expr::Ident(name, _) if !name.contains("::") => Ok(name),
+        expr => Err(Diagnostic::error(
+            expr.span(),
+            "Module name must be an identifier",
+        )),
+    }
+}
+
+/// Loads the modules a file imports, from the directory of the importing file
+/// first and then from the search path.
+#[derive(Debug, Default, Clone)]
+pub struct Resolver {
+    pub search_path: Vec<PathBuf>,
+}
+
+impl Resolver {
+    pub fn new(search_path: Vec<PathBuf>) -> Self {
+        Self { search_path }
+    }
+
+    /// Parses `source`, read from `path`, and every module it imports. Modules
+    /// come before the modules importing them, the file at `path` last.
+    pub fn resolve(
+        &self,
+        path: impl Into<PathBuf>,
+        source: String,
+    ) -> Result<Vec<Module>, FileDiagnostic> {
+        let root = Module::parse(path, source)?;
+        let mut modules = vec![];
+        self.visit(root, &mut vec![], &mut modules)?;
+        Ok(modules)
+    }
+
+    /// Adds the imports of `module` depth first, `stack` holds the modules
+    /// whose imports are being loaded.
+    fn visit(
+        &self,
+        module: Module,
+        stack: &mut Vec<String>,
+        modules: &mut Vec<Module>,
+    ) -> Result<(), FileDiagnostic> {
+        let error = |diagnostic| FileDiagnostic::new(&module.path, &module.source, diagnostic);
+        stack.extend(module.name.clone());
+
+        for (import, span) in &module.imports {
+            if let Some(cycle) = stack.iter().position(|name| name == import) {
+                let mut cycle = stack[cycle..].to_vec();
+                cycle.push(import.clone());
+                return Err(error(Diagnostic::error(
+                    *span,
+                    format!("Import cycle: {}", cycle.join(" -> ")),
+                )));
+            }
+            if modules
+                .iter()
+                .any(|module| module.name.as_ref() == Some(import))
+            {
+                continue;
+            }
+
+            let Some(path) = self.find(&module.path, import) else {
+                return Err(error(Diagnostic::error(
+                    *span,
+                    format!("Module `{import}` not found"),
+                )));
+            };
+            let source = fs::read_to_string(&path).map_err(|err| {
+                error(Diagnostic::error(
+                    *span,
+                    format!("Failed to read `{}`: {err}", path.display()),
+                ))
+            })?;
+            let imported = Module::parse(&path, source)?;
+            if imported.name.as_ref() != Some(import) {
+                return Err(error(Diagnostic::error(
+                    *span,
+                    format!("`{}` does not start with `module {import}`", path.display()),
+                )));
+            }
+            self.visit(imported, stack, modules)?;
+        }
+
+        if module.name.is_some() {
+            stack.pop();
+        }
+        modules.push(module);
+        Ok(())
+    }
+
+    fn find(&self, importer: &Path, name: &str) -> Option<PathBuf> {
+        let file = Path::new(name).with_extension(EXTENSION);
+        importer
+            .parent()
+            .into_iter()
+            .chain(self.search_path.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(&file))
+            .find(|path| path.is_file())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::PathBuf};
+
+    use crate::frontend::module::Resolver;
+
+    fn dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("unicorn-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (file, source) in files {
+            fs::write(dir.join(file), source).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn resolve_orders_dependencies_first() {
+        let dir = dir(
+            "resolve",
+            &[
+                ("math.uniq", "module math\nsquare: x(i64) -> i64 { x * x }"),
+                ("geometry.uniq", "module geometry\nimport math"),
+            ],
+        );
+        let lib = dir.join("lib");
+        fs::create_dir_all(&lib).unwrap();
+        fs::write(lib.join("util.uniq"), "module util\nimport math").unwrap();
+
+        let modules = Resolver::new(vec![lib])
+            .resolve(
+                dir.join("main.uniq"),
+                "import geometry\nimport util\nimport math".to_owned(),
+            )
+            .unwrap();
+        let names = modules
+            .iter()
+            .map(|module| module.name.as_deref())
+            .collect::<Vec<_>>();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            names,
+            vec![Some("math"), Some("geometry"), Some("util"), None]
+        );
+    }
+
+    #[test]
+    fn resolve_rejects_cycles_and_missing_modules() {
+        let dir = dir(
+            "resolve-cycle",
+            &[
+                ("a.uniq", "module a\nimport b"),
+                ("b.uniq", "module b\nimport a"),
+                ("c.uniq", "module d"),
+            ],
+        );
+        let resolve = |source: &str| {
+            Resolver::default()
+                .resolve(dir.join("main.uniq"), source.to_owned())
+                .unwrap_err()
+        };
+        let cycle = resolve("import a");
+        let missing = resolve("import e");
+        let mismatch = resolve("import c");
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(cycle.diagnostic.message, "Import cycle: a -> b -> a");
+        assert_eq!(cycle.file, dir.join("b.uniq"));
+        assert_eq!(missing.diagnostic.message, "Module `e` not found");
+        assert!(
+            mismatch
+                .diagnostic
+                .message
+                .ends_with("does not start with `module c`")
+        );
+    }
+}
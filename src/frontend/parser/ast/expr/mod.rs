@@ -1,20 +1,190 @@
+use crate::diagnostics::Span;
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Expr {
-    Ident(String),
+    Ident(String, Span),
     Call {
         ident: Box<Expr>,
         args: Vec<Expr>,
+        span: Span,
     },
-    Lit(String),
+    Lit(String, Span),
+    /// `1.5`, `2e10`, typed `f64` unless `f32` is expected.
+    Float(String, Span),
+    Bool(bool, Span),
+    Char(char, Span),
+    /// `"..."`, read-only data.
+    Str(String, Span),
     Function {
         name: Box<Expr>,
         function_ty: Box<Expr>,
         body: Vec<Expr>,
+        span: Span,
     },
     FunctionType {
         params: Vec<(Expr, Expr)>,
         ret_ty: Box<Expr>,
+        span: Span,
     },
-    Assign((Box<Expr>, Box<Expr>), Box<Expr>),
-    GlobalDataAddr(Box<Expr>),
+    Assign((Box<Expr>, Box<Expr>), Box<Expr>, Span),
+    /// `&name` of a top-level `let name: str = ".."`.
+    GlobalDataAddr(Box<Expr>, Span),
+    Binary {
+        op: BinaryOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+        span: Span,
+    },
+    Unary {
+        op: UnaryOp,
+        expr: Box<Expr>,
+        span: Span,
+    },
+    /// `if cond { .. } else { .. }`, an `else if` chain nests in `otherwise`.
+    If {
+        cond: Box<Expr>,
+        then: Vec<Expr>,
+        otherwise: Vec<Expr>,
+        span: Span,
+    },
+    While {
+        cond: Box<Expr>,
+        body: Vec<Expr>,
+        span: Span,
+    },
+    Loop(Vec<Expr>, Span),
+    Break(Span),
+    /// `name = expr` on a variable bound by an earlier `let`.
+    Set(Box<Expr>, Box<Expr>, Span),
+    /// Top-level `type Point { x: i64, y: i64 }`.
+    TypeDef {
+        name: Box<Expr>,
+        fields: Vec<(Expr, Expr)>,
+        span: Span,
+    },
+    /// `Point { x: 1, y: 2 }`.
+    Struct {
+        name: Box<Expr>,
+        fields: Vec<(Expr, Expr)>,
+        span: Span,
+    },
+    /// `point.x`
+    Field(Box<Expr>, Box<Expr>, Span),
+    /// Top-level `type Option = Some(i64) | None`.
+    EnumDef {
+        name: Box<Expr>,
+        variants: Vec<(Expr, Vec<Expr>)>,
+        span: Span,
+    },
+    /// `Some(1)`, a variant without values is written as a plain identifier.
+    Variant {
+        name: Box<Expr>,
+        args: Vec<Expr>,
+        span: Span,
+    },
+    Match {
+        expr: Box<Expr>,
+        arms: Vec<MatchArm>,
+        span: Span,
+    },
+    /// `[i64]`
+    ArrayType(Box<Expr>, Span),
+    /// `[1 2 3]`
+    Array(Vec<Expr>, Span),
+    /// `array[index]`
+    Index(Box<Expr>, Box<Expr>, Span),
+    /// Takes the oldest message of the mailbox of the process, waiting for one.
+    Receive(Span),
+    /// `receive after timeout { otherwise }` waits for a message at most
+    /// `timeout` milliseconds, then evaluates `otherwise` instead.
+    ReceiveAfter {
+        timeout: Box<Expr>,
+        otherwise: Vec<Expr>,
+        span: Span,
+    },
+    /// Top-level `module math`, the file is the module `math` whose functions
+    /// are named `math::f` elsewhere.
+    Module(Box<Expr>, Span),
+    /// Top-level `import math`, makes the functions of `math` callable as `math::f`.
+    Import(Box<Expr>, Span),
+}
+
+/// `Some(x) => body` of a `match`, the variant `_` matches anything.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MatchArm {
+    pub variant: Expr,
+    pub bindings: Vec<Expr>,
+    pub body: Vec<Expr>,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum UnaryOp {
+    Not,
+    Neg,
+}
+
+impl Expr {
+    pub fn binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Self {
+        let span = lhs.span().to(rhs.span());
+        Expr::Binary {
+            op,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+            span,
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Ident(_, span)
+            | Expr::Call { span, .. }
+            | Expr::Lit(_, span)
+            | Expr::Float(_, span)
+            | Expr::Bool(_, span)
+            | Expr::Char(_, span)
+            | Expr::Str(_, span)
+            | Expr::Function { span, .. }
+            | Expr::FunctionType { span, .. }
+            | Expr::Assign(_, _, span)
+            | Expr::GlobalDataAddr(_, span)
+            | Expr::Binary { span, .. }
+            | Expr::Unary { span, .. }
+            | Expr::If { span, .. }
+            | Expr::While { span, .. }
+            | Expr::Loop(_, span)
+            | Expr::Break(span)
+            | Expr::Set(_, _, span)
+            | Expr::TypeDef { span, .. }
+            | Expr::Struct { span, .. }
+            | Expr::Field(_, _, span)
+            | Expr::EnumDef { span, .. }
+            | Expr::Variant { span, .. }
+            | Expr::Match { span, .. }
+            | Expr::ArrayType(_, span)
+            | Expr::Array(_, span)
+            | Expr::Index(_, _, span)
+            | Expr::Receive(span)
+            | Expr::ReceiveAfter { span, .. }
+            | Expr::Module(_, span)
+            | Expr::Import(_, span) => *span,
+        }
+    }
 }
@@ -1,2 +1 @@
 pub mod expr;
-pub mod stmt;
@@ -1,5 +0,0 @@
-use crate::frontend::parser::ast::expr::Expr;
-
-pub enum Stmt {
-    Expr(Expr),
-}
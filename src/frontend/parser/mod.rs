@@ -1,4 +1,5 @@
-use crate::frontend::parser::ast::expr::Expr;
+use crate::diagnostics::{Diagnostic, Span};
+use crate::frontend::parser::ast::expr::{BinaryOp, Expr, MatchArm, UnaryOp};
 pub use crate::frontend::parser::parser::*;
 use peg::*;
 
@@ -7,180 +8,391 @@ pub mod ast;
 parser! {
     pub grammar parser() for str {
         pub rule function() -> Expr
-            = _ name:ident() _ ":" _ t:ty() _ "{" _ body:exprs() _ "}" _
-            { Expr::Function { name: Box::new(name), function_ty: Box::new(t), body } }
+            = _ start:position!() name:ident() _ ":" _ t:ty() _ "{" _ body:exprs() _ "}" end:position!() _
+            {
+                Expr::Function {
+                    name: Box::new(name),
+                    function_ty: Box::new(t),
+                    body,
+                    span: Span::new(start, end),
+                }
+            }
 
         rule function_ty() -> Expr
-            = _ params:(( i:expr() "(" _ t:ty() _ ")" { (i, t) }) ** ([' ' | '\t' | '\n' | '\r']*))
-            _ "->" _ ret_ty:(_ i:ident() _ {i}) { Expr::FunctionType { params, ret_ty: Box::new(ret_ty) } }
+            = _ start:position!() params:(( i:ident() "(" _ t:ty() _ ")" { (i, t) }) ** ([' ' | '\t' | '\n' | '\r']*))
+            _ "->" _ ret_ty:(_ i:ident() _ {i}) end:position!()
+            {
+                Expr::FunctionType {
+                    params,
+                    ret_ty: Box::new(ret_ty),
+                    span: Span::new(start, end),
+                }
+            }
 
         pub rule exprs() -> Vec<Expr> = _ n:(_ e:expr() ** ([' ' | '\t' | '\n' | '\r']*) _ {e}) _ { n }
-        rule expr() -> Expr = function() / assign() / call() / ident() / literal()
+        rule expr() -> Expr
+            = function() / module() / import() / type_def() / enum_def() / assign() / if_() / match_() / while_() / loop_() / break_() / set() / binary(<atom()>)
+        rule module() -> Expr
+            = _ start:position!() "module" !ident_char() _ name:ident() end:position!() _
+            { Expr::Module(Box::new(name), Span::new(start, end)) }
+        rule import() -> Expr
+            = _ start:position!() "import" !ident_char() _ name:ident() end:position!() _
+            { Expr::Import(Box::new(name), Span::new(start, end)) }
+        rule type_def() -> Expr
+            = _ start:position!() "type" !ident_char() _ name:ident() _ "{" _ fields:field_def() ** (_ "," _) _ ","? _ "}" end:position!() _
+            { Expr::TypeDef { name: Box::new(name), fields, span: Span::new(start, end) } }
+        rule field_def() -> (Expr, Expr) = f:ident() _ ":" _ t:ty() { (f, t) }
+        rule enum_def() -> Expr
+            = _ start:position!() "type" !ident_char() _ name:ident() _ "=" _ variants:variant_def() ++ (_ "|" _) end:position!() _
+            { Expr::EnumDef { name: Box::new(name), variants, span: Span::new(start, end) } }
+        rule variant_def() -> (Expr, Vec<Expr>)
+            = v:ident() payload:("(" _ t:ty() ** (_ "," _) _ ")" { t })? { (v, payload.unwrap_or_default()) }
+        rule match_() -> Expr
+            = _ start:position!() "match" !ident_char() _ e:binary(<cond_atom()>) _ "{" _ arms:arm() ** (_ "," _) _ ","? _ "}" end:position!() _
+            { Expr::Match { expr: Box::new(e), arms, span: Span::new(start, end) } }
+        rule arm() -> MatchArm
+            = start:position!() variant:ident() bindings:("(" _ b:ident() ** (_ "," _) _ ")" { b })? _ "=>" _
+              body:("{" b:exprs() "}" { b } / e:expr() { vec![e] }) end:position!()
+            {
+                MatchArm {
+                    variant,
+                    bindings: bindings.unwrap_or_default(),
+                    body,
+                    span: Span::new(start, end),
+                }
+            }
+        rule if_() -> Expr
+            = _ start:position!() "if" !ident_char() _ cond:binary(<cond_atom()>) _ "{" then:exprs() "}"
+              otherwise:(_ "else" !ident_char() _ e:else_() { e })? end:position!() _
+            {
+                Expr::If {
+                    cond: Box::new(cond),
+                    then,
+                    otherwise: otherwise.unwrap_or_default(),
+                    span: Span::new(start, end),
+                }
+            }
+        rule else_() -> Vec<Expr> = e:if_() { vec![e] } / "{" e:exprs() "}" { e }
+        rule while_() -> Expr
+            = _ start:position!() "while" !ident_char() _ cond:binary(<cond_atom()>) _ "{" body:exprs() "}" end:position!() _
+            { Expr::While { cond: Box::new(cond), body, span: Span::new(start, end) } }
+        rule loop_() -> Expr
+            = _ start:position!() "loop" !ident_char() _ "{" body:exprs() "}" end:position!() _
+            { Expr::Loop(body, Span::new(start, end)) }
+        rule break_() -> Expr
+            = _ start:position!() "break" !ident_char() end:position!() _ { Expr::Break(Span::new(start, end)) }
+        rule set() -> Expr
+            = _ start:position!() i:ident() _ "=" !"=" _ e:expr() end:position!() _
+            { Expr::Set(Box::new(i), Box::new(e), Span::new(start, end)) }
+        rule binary(atom: rule<Expr>) -> Expr = precedence!{
+            x:(@) _ "||" _ y:@ { Expr::binary(BinaryOp::Or, x, y) }
+            --
+            x:(@) _ "&&" _ y:@ { Expr::binary(BinaryOp::And, x, y) }
+            --
+            x:(@) _ "==" _ y:@ { Expr::binary(BinaryOp::Eq, x, y) }
+            x:(@) _ "!=" _ y:@ { Expr::binary(BinaryOp::Ne, x, y) }
+            --
+            x:(@) _ "<=" _ y:@ { Expr::binary(BinaryOp::Le, x, y) }
+            x:(@) _ ">=" _ y:@ { Expr::binary(BinaryOp::Ge, x, y) }
+            x:(@) _ "<" _ y:@ { Expr::binary(BinaryOp::Lt, x, y) }
+            x:(@) _ ">" _ y:@ { Expr::binary(BinaryOp::Gt, x, y) }
+            --
+            x:(@) _ "+" _ y:@ { Expr::binary(BinaryOp::Add, x, y) }
+            x:(@) _ "-" _ y:@ { Expr::binary(BinaryOp::Sub, x, y) }
+            --
+            x:(@) _ "*" _ y:@ { Expr::binary(BinaryOp::Mul, x, y) }
+            x:(@) _ "/" _ y:@ { Expr::binary(BinaryOp::Div, x, y) }
+            x:(@) _ "%" _ y:@ { Expr::binary(BinaryOp::Rem, x, y) }
+            --
+            start:position!() "!" _ x:@ {
+                let span = Span::new(start, x.span().end);
+                Expr::Unary { op: UnaryOp::Not, expr: Box::new(x), span }
+            }
+            // Only before an operand: `f { a -1 }` passes `a - 1`, `f { a (-1) }` two arguments.
+            start:position!() "-" _ x:@ {
+                let span = Span::new(start, x.span().end);
+                Expr::Unary { op: UnaryOp::Neg, expr: Box::new(x), span }
+            }
+            --
+            x:(@) "." f:ident() {
+                let span = x.span().to(f.span());
+                Expr::Field(Box::new(x), Box::new(f), span)
+            }
+            x:(@) "[" _ i:expr() _ "]" end:position!() {
+                let span = Span::new(x.span().start, end);
+                Expr::Index(Box::new(x), Box::new(i), span)
+            }
+            --
+            e:atom() { e }
+        }
+        rule atom() -> Expr = struct_() / call() / variant() / receive() / ident() / literal() / array() / "(" _ e:expr() _ ")" { e }
+        // `if x { .. }` must not read as a call of `x`, calls need parentheses here.
+        rule cond_atom() -> Expr = variant() / ident() / literal() / "(" _ e:expr() _ ")" { e }
         rule assign() -> Expr
-            = _ "let" _ i:ident() _ ":" _ t:ty() _ "=" _ e:expr() _
-            { Expr::Assign((Box::new(i), Box::new(t)), Box::new(e)) }
-        rule ty() -> Expr = function_ty() / ident()
+            = _ start:position!() "let" _ i:ident() _ ":" _ t:ty() _ "=" _ e:expr() end:position!() _
+            { Expr::Assign((Box::new(i), Box::new(t)), Box::new(e), Span::new(start, end)) }
+        rule ty() -> Expr = function_ty() / array_ty() / ident()
+        rule array_ty() -> Expr
+            = start:position!() "[" _ t:ty() _ "]" end:position!() { Expr::ArrayType(Box::new(t), Span::new(start, end)) }
+        rule array() -> Expr
+            = _ start:position!() "[" _ elems:((e:expr() { e }) ** ([' ' | '\t' | '\n' | '\r']*)) _ "]" end:position!() _
+            { Expr::Array(elems, Span::new(start, end)) }
+        rule struct_() -> Expr
+            = _ start:position!() name:ident() _ "{" _ fields:(f:ident() _ ":" _ e:expr() { (f, e) }) ++ (_ "," _) _ ","? _ "}" end:position!() _
+            { Expr::Struct { name: Box::new(name), fields, span: Span::new(start, end) } }
+        rule variant() -> Expr
+            = _ start:position!() name:ident() "(" _ args:expr() ** (_ "," _) _ ")" end:position!() _
+            { Expr::Variant { name: Box::new(name), args, span: Span::new(start, end) } }
+        rule receive() -> Expr
+            = _ start:position!() "receive" !ident_char() _ "after" !ident_char() _ timeout:cond_atom() _ "{" otherwise:exprs() "}" end:position!() _
+            { Expr::ReceiveAfter { timeout: Box::new(timeout), otherwise, span: Span::new(start, end) } }
+            / _ start:position!() "receive" !ident_char() end:position!() _ { Expr::Receive(Span::new(start, end)) }
         rule call() -> Expr
-            = _ i:ident() _ "{" _ args:((e:expr() { e }) ** ([' ' | '\t' | '\n' | '\r']*)) _ "}" _
-            { Expr::Call { ident: Box::new(i), args } }
+            = _ start:position!() i:ident() _ "{" _ args:((e:expr() { e }) ** ([' ' | '\t' | '\n' | '\r']*)) _ "}" end:position!() _
+            { Expr::Call { ident: Box::new(i), args, span: Span::new(start, end) } }
 
+        // `math::square` names a function of an imported module.
         rule ident() -> Expr
-            = quiet!{ n:$(['a'..='z' | 'A'..='Z' | '_']['a'..='z' | 'A'..='Z' | '0'..='9' | '_']*)
-            { Expr::Ident(n.to_owned()) } } / expected!("identifier")
+            = quiet!{ !keyword() start:position!() n:$(name() ("::" !keyword() name())?) end:position!()
+            { Expr::Ident(n.to_owned(), Span::new(start, end)) } } / expected!("identifier")
+        rule name() = ['a'..='z' | 'A'..='Z' | '_']['a'..='z' | 'A'..='Z' | '0'..='9' | '_']*
 
         rule literal() -> Expr
-            = n:$(['0'..='9']+) { Expr::Lit(n.to_owned()) }
-            / "&" i:ident() { Expr::GlobalDataAddr(Box::new(i)) }
+            = start:position!() n:$(digits() ("." digits() exponent()? / exponent())) end:position!()
+            { Expr::Float(n.to_owned(), Span::new(start, end)) }
+            / start:position!() n:$(digits()) end:position!() { Expr::Lit(n.to_owned(), Span::new(start, end)) }
+            / start:position!() "true" !ident_char() end:position!() { Expr::Bool(true, Span::new(start, end)) }
+            / start:position!() "false" !ident_char() end:position!() { Expr::Bool(false, Span::new(start, end)) }
+            / start:position!() "'" c:char_() "'" end:position!() { Expr::Char(c, Span::new(start, end)) }
+            / start:position!() "\"" s:str_char()* "\"" end:position!() { Expr::Str(s.into_iter().collect(), Span::new(start, end)) }
+            / start:position!() "&" i:ident() end:position!() { Expr::GlobalDataAddr(Box::new(i), Span::new(start, end)) }
+
+        rule digits() = ['0'..='9']+
+        rule exponent() = ['e' | 'E'] ['+' | '-']? digits()
+        rule escape() -> char
+            = "\\" c:['n' | 't' | 'r' | '0' | '\\' | '\'' | '"'] {
+                match c {
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    '0' => '\0',
+                    c => c,
+                }
+            }
+        rule char_() -> char = escape() / c:[^ '\'' | '\\' | '\n'] { c }
+        rule str_char() -> char = escape() / c:[^ '"' | '\\' | '\n'] { c }
+
+        rule keyword() = ("if" / "else" / "let" / "while" / "loop" / "break" / "true" / "false" / "type" / "match" / "receive" / "after" / "module" / "import") !ident_char()
+        rule ident_char() = ['a'..='z' | 'A'..='Z' | '0'..='9' | '_']
 
         rule _() = quiet!{[' ' | '\t' | '\n' | '\r']*}
     }
 }
 
+/// Parses a whole source file into its top-level expressions.
+pub fn parse(input: &str) -> Result<Vec<Expr>, Diagnostic> {
+    parser::exprs(input).map_err(Diagnostic::from)
+}
+
 #[cfg(test)]
 mod test {
-    use std::vec;
+    use std::{fmt::Debug, vec};
+
+    use crate::diagnostics::Span;
+    use crate::frontend::parser::{
+        self,
+        ast::expr::{BinaryOp, Expr, MatchArm, UnaryOp},
+    };
+
+    const SPAN: Span = Span { start: 0, end: 0 };
+
+    /// Compares parse results without their spans, the expected trees are built
+    /// with [`SPAN`].
+    #[track_caller]
+    fn assert_parses<T: Debug>(parsed: T, expected: T) {
+        assert_eq!(without_spans(&parsed), without_spans(&expected))
+    }
 
-    use crate::frontend::parser::{self, ast::expr::Expr};
+    /// `Debug` of `value` with every span left out.
+    fn without_spans(value: &impl Debug) -> String {
+        let mut debug = format!("{value:?}");
+        while let Some(start) = debug.find("Span { start: ") {
+            let end = start + debug[start..].find('}').unwrap() + 1;
+            debug.replace_range(start..end, "Span");
+        }
+        debug
+    }
 
     #[test]
     fn simple_function_parse() {
-        assert_eq!(
+        assert_parses(
             parser::function("foo : a(T) b(None) -> nil {}"),
             Ok(Expr::Function {
-                name: Box::new(Expr::Ident(String::from("foo"))),
+                name: Box::new(Expr::Ident(String::from("foo"), SPAN)),
                 function_ty: Box::new(Expr::FunctionType {
                     params: vec![
                         (
-                            Expr::Ident(String::from("a")),
-                            Expr::Ident(String::from("T"))
+                            Expr::Ident(String::from("a"), SPAN),
+                            Expr::Ident(String::from("T"), SPAN),
                         ),
                         (
-                            Expr::Ident(String::from("b")),
-                            Expr::Ident(String::from("None"))
-                        )
+                            Expr::Ident(String::from("b"), SPAN),
+                            Expr::Ident(String::from("None"), SPAN),
+                        ),
                     ],
-                    ret_ty: Box::new(Expr::Ident(String::from("nil"))),
+                    ret_ty: Box::new(Expr::Ident(String::from("nil"), SPAN)),
+                    span: SPAN,
                 }),
-                body: vec![]
-            })
+                body: vec![],
+                span: SPAN,
+            }),
         )
     }
 
     #[test]
     fn complicated_function_parse() {
-        assert_eq!(
+        assert_parses(
             parser::function(
                 r#"foo : bar( a(i32) b(i32) -> nil ) -> nil {
                     buzz : a(i32) b(i32) -> nil {}
                     nil
-                }"#
+                }"#,
             ),
             Ok(Expr::Function {
-                name: Box::new(Expr::Ident("foo".into())),
+                name: Box::new(Expr::Ident("foo".into(), SPAN)),
                 function_ty: Box::new(Expr::FunctionType {
                     params: vec![(
-                        Expr::Ident("bar".into()),
+                        Expr::Ident("bar".into(), SPAN),
                         Expr::FunctionType {
                             params: vec![
-                                (Expr::Ident("a".into()), Expr::Ident("i32".into())),
-                                (Expr::Ident("b".into()), Expr::Ident("i32".into()))
+                                (
+                                    Expr::Ident("a".into(), SPAN),
+                                    Expr::Ident("i32".into(), SPAN),
+                                ),
+                                (
+                                    Expr::Ident("b".into(), SPAN),
+                                    Expr::Ident("i32".into(), SPAN),
+                                ),
                             ],
-                            ret_ty: Box::new(Expr::Ident("nil".into())),
-                        }
+                            ret_ty: Box::new(Expr::Ident("nil".into(), SPAN)),
+                            span: SPAN,
+                        },
                     )],
-                    ret_ty: Box::new(Expr::Ident("nil".into())),
+                    ret_ty: Box::new(Expr::Ident("nil".into(), SPAN)),
+                    span: SPAN,
                 }),
                 body: vec![
                     Expr::Function {
-                        name: Box::new(Expr::Ident("buzz".into())),
+                        name: Box::new(Expr::Ident("buzz".into(), SPAN)),
                         function_ty: Box::new(Expr::FunctionType {
                             params: vec![
-                                (Expr::Ident("a".into()), Expr::Ident("i32".into())),
-                                (Expr::Ident("b".into()), Expr::Ident("i32".into()))
+                                (
+                                    Expr::Ident("a".into(), SPAN),
+                                    Expr::Ident("i32".into(), SPAN),
+                                ),
+                                (
+                                    Expr::Ident("b".into(), SPAN),
+                                    Expr::Ident("i32".into(), SPAN),
+                                ),
                             ],
-                            ret_ty: Box::new(Expr::Ident("nil".into())),
+                            ret_ty: Box::new(Expr::Ident("nil".into(), SPAN)),
+                            span: SPAN,
                         }),
-                        body: vec![]
+                        body: vec![],
+                        span: SPAN,
                     },
-                    Expr::Ident("nil".into())
-                ]
-            })
+                    Expr::Ident("nil".into(), SPAN),
+                ],
+                span: SPAN,
+            }),
         )
     }
 
     #[test]
     fn let_expr_parse() {
-        assert_eq!(
+        assert_parses(
             parser::function("main : -> nil { let a : i32 = 20\n nil } "),
             Ok(Expr::Function {
-                name: Box::new(Expr::Ident("main".into())),
+                name: Box::new(Expr::Ident("main".into(), SPAN)),
                 function_ty: Box::new(Expr::FunctionType {
                     params: vec![],
-                    ret_ty: Box::new(Expr::Ident("nil".into()))
+                    ret_ty: Box::new(Expr::Ident("nil".into(), SPAN)),
+                    span: SPAN,
                 }),
                 body: vec![
                     Expr::Assign(
                         (
-                            Box::new(Expr::Ident("a".into())),
-                            Box::new(Expr::Ident("i32".into()))
+                            Box::new(Expr::Ident("a".into(), SPAN)),
+                            Box::new(Expr::Ident("i32".into(), SPAN)),
                         ),
-                        Box::new(Expr::Lit("20".into()))
+                        Box::new(Expr::Lit("20".into(), SPAN)),
+                        SPAN,
                     ),
-                    Expr::Ident("nil".into())
-                ]
-            })
+                    Expr::Ident("nil".into(), SPAN),
+                ],
+                span: SPAN,
+            }),
         )
     }
 
     /// It's fun, but I haven't found a use for it yet))
     #[test]
     fn some_strange_things() {
-        assert_eq!(
+        assert_parses(
             parser::function("main : i32 {} "),
             Ok(Expr::Function {
-                name: Box::new(Expr::Ident("main".into())),
-                function_ty: Box::new(Expr::Ident("i32".into())),
-                body: vec![]
-            })
+                name: Box::new(Expr::Ident("main".into(), SPAN)),
+                function_ty: Box::new(Expr::Ident("i32".into(), SPAN)),
+                body: vec![],
+                span: SPAN,
+            }),
         );
     }
 
     #[test]
     fn call_parse() {
-        assert_eq!(
+        assert_parses(
             parser::function(
                 r#"main : -> nil { 
                     b : a(i32) -> nil {}
                     b { 20 }
-                } "#
+                } "#,
             ),
             Ok(Expr::Function {
-                name: Box::new(Expr::Ident("main".into())),
+                name: Box::new(Expr::Ident("main".into(), SPAN)),
                 function_ty: Box::new(Expr::FunctionType {
                     params: vec![],
-                    ret_ty: Box::new(Expr::Ident("nil".into()))
+                    ret_ty: Box::new(Expr::Ident("nil".into(), SPAN)),
+                    span: SPAN,
                 }),
                 body: vec![
                     Expr::Function {
-                        name: Box::new(Expr::Ident("b".into())),
+                        name: Box::new(Expr::Ident("b".into(), SPAN)),
                         function_ty: Box::new(Expr::FunctionType {
-                            params: vec![(Expr::Ident("a".into()), Expr::Ident("i32".into())),],
-                            ret_ty: Box::new(Expr::Ident("nil".into()))
+                            params: vec![(
+                                Expr::Ident("a".into(), SPAN),
+                                Expr::Ident("i32".into(), SPAN),
+                            )],
+                            ret_ty: Box::new(Expr::Ident("nil".into(), SPAN)),
+                            span: SPAN,
                         }),
-                        body: vec![]
+                        body: vec![],
+                        span: SPAN,
                     },
                     Expr::Call {
-                        ident: Box::new(Expr::Ident("b".into())),
-                        args: vec![Expr::Lit("20".into())]
-                    }
-                ]
-            })
+                        ident: Box::new(Expr::Ident("b".into(), SPAN)),
+                        args: vec![Expr::Lit("20".into(), SPAN)],
+                        span: SPAN,
+                    },
+                ],
+                span: SPAN,
+            }),
         );
     }
 
     #[test]
     fn two_functions_parse() {
-        assert_eq!(
+        assert_parses(
             parser::exprs(
                 r#"
                     main: -> nil {
@@ -191,44 +403,420 @@ mod test {
                     b: a(i32) b(i32) -> i32 {
                         sum { a b }
                     }
-                "#
+                "#,
             ),
             Ok(vec![
                 Expr::Function {
-                    name: Box::new(Expr::Ident("main".into())),
+                    name: Box::new(Expr::Ident("main".into(), SPAN)),
                     function_ty: Box::new(Expr::FunctionType {
                         params: vec![],
-                        ret_ty: Box::new(Expr::Ident("nil".into()))
+                        ret_ty: Box::new(Expr::Ident("nil".into(), SPAN)),
+                        span: SPAN,
                     }),
                     body: vec![
                         Expr::Assign(
                             (
-                                Box::new(Expr::Ident("c".into())),
-                                Box::new(Expr::Ident("i32".into()))
+                                Box::new(Expr::Ident("c".into(), SPAN)),
+                                Box::new(Expr::Ident("i32".into(), SPAN)),
                             ),
                             Box::new(Expr::Call {
-                                ident: Box::new(Expr::Ident("b".into())),
-                                args: vec![Expr::Lit("20".into()), Expr::Lit("30".into())]
-                            })
+                                ident: Box::new(Expr::Ident("b".into(), SPAN)),
+                                args: vec![
+                                    Expr::Lit("20".into(), SPAN),
+                                    Expr::Lit("30".into(), SPAN),
+                                ],
+                                span: SPAN,
+                            }),
+                            SPAN,
                         ),
-                        Expr::Ident("nil".into())
+                        Expr::Ident("nil".into(), SPAN),
                     ],
+                    span: SPAN,
                 },
                 Expr::Function {
-                    name: Box::new(Expr::Ident("b".into())),
+                    name: Box::new(Expr::Ident("b".into(), SPAN)),
                     function_ty: Box::new(Expr::FunctionType {
                         params: vec![
-                            (Expr::Ident("a".into()), Expr::Ident("i32".into())),
-                            (Expr::Ident("b".into()), Expr::Ident("i32".into())),
+                            (
+                                Expr::Ident("a".into(), SPAN),
+                                Expr::Ident("i32".into(), SPAN),
+                            ),
+                            (
+                                Expr::Ident("b".into(), SPAN),
+                                Expr::Ident("i32".into(), SPAN),
+                            ),
                         ],
-                        ret_ty: Box::new(Expr::Ident("i32".into()))
+                        ret_ty: Box::new(Expr::Ident("i32".into(), SPAN)),
+                        span: SPAN,
                     }),
                     body: vec![Expr::Call {
-                        ident: Box::new(Expr::Ident("sum".into())),
-                        args: vec![Expr::Ident("a".into()), Expr::Ident("b".into())]
+                        ident: Box::new(Expr::Ident("sum".into(), SPAN)),
+                        args: vec![Expr::Ident("a".into(), SPAN), Expr::Ident("b".into(), SPAN)],
+                        span: SPAN,
                     }],
-                }
-            ])
+                    span: SPAN,
+                },
+            ]),
+        )
+    }
+
+    #[test]
+    fn binary_precedence_parse() {
+        let ident = |name: &str| Expr::Ident(name.into(), SPAN);
+        assert_parses(
+            parser::exprs("a + b * c == d || !e"),
+            Ok(vec![Expr::binary(
+                BinaryOp::Or,
+                Expr::binary(
+                    BinaryOp::Eq,
+                    Expr::binary(
+                        BinaryOp::Add,
+                        ident("a"),
+                        Expr::binary(BinaryOp::Mul, ident("b"), ident("c")),
+                    ),
+                    ident("d"),
+                ),
+                Expr::Unary {
+                    op: UnaryOp::Not,
+                    expr: Box::new(ident("e")),
+                    span: SPAN,
+                },
+            )]),
+        )
+    }
+
+    #[test]
+    fn unary_minus_parse() {
+        let lit = |n: &str| Expr::Lit(n.into(), SPAN);
+        let neg = |expr| Expr::Unary {
+            op: UnaryOp::Neg,
+            expr: Box::new(expr),
+            span: SPAN,
+        };
+        let f = |args| Expr::Call {
+            ident: Box::new(Expr::Ident("f".into(), SPAN)),
+            args,
+            span: SPAN,
+        };
+        assert_parses(
+            parser::exprs("-a.b * 2 - -1"),
+            Ok(vec![Expr::binary(
+                BinaryOp::Sub,
+                Expr::binary(
+                    BinaryOp::Mul,
+                    neg(Expr::Field(
+                        Box::new(Expr::Ident("a".into(), SPAN)),
+                        Box::new(Expr::Ident("b".into(), SPAN)),
+                        SPAN,
+                    )),
+                    lit("2"),
+                ),
+                neg(lit("1")),
+            )]),
+        );
+        assert_parses(
+            parser::exprs("f { 1 -1 }\nf { 1 (-1) }"),
+            Ok(vec![
+                f(vec![Expr::binary(BinaryOp::Sub, lit("1"), lit("1"))]),
+                f(vec![lit("1"), neg(lit("1"))]),
+            ]),
+        );
+    }
+
+    #[test]
+    fn binary_operands_parse() {
+        assert_parses(
+            parser::exprs("(1 - 2) - foo { 3 }"),
+            Ok(vec![Expr::binary(
+                BinaryOp::Sub,
+                Expr::binary(
+                    BinaryOp::Sub,
+                    Expr::Lit("1".into(), SPAN),
+                    Expr::Lit("2".into(), SPAN),
+                ),
+                Expr::Call {
+                    ident: Box::new(Expr::Ident("foo".into(), SPAN)),
+                    args: vec![Expr::Lit("3".into(), SPAN)],
+                    span: SPAN,
+                },
+            )]),
+        )
+    }
+
+    #[test]
+    fn spans_cover_their_source() {
+        assert_eq!(
+            parser::exprs("a + foo { 3 }"),
+            Ok(vec![Expr::binary(
+                BinaryOp::Add,
+                Expr::Ident("a".into(), Span::new(0, 1)),
+                Expr::Call {
+                    ident: Box::new(Expr::Ident("foo".into(), Span::new(4, 7))),
+                    args: vec![Expr::Lit("3".into(), Span::new(10, 11))],
+                    span: Span::new(4, 13),
+                },
+            )])
+        );
+    }
+
+    #[test]
+    fn if_else_parse() {
+        assert_parses(
+            parser::exprs("if a < 2 { 1 } else if (foo { a }) { } else { a }"),
+            Ok(vec![Expr::If {
+                cond: Box::new(Expr::binary(
+                    BinaryOp::Lt,
+                    Expr::Ident("a".into(), SPAN),
+                    Expr::Lit("2".into(), SPAN),
+                )),
+                then: vec![Expr::Lit("1".into(), SPAN)],
+                otherwise: vec![Expr::If {
+                    cond: Box::new(Expr::Call {
+                        ident: Box::new(Expr::Ident("foo".into(), SPAN)),
+                        args: vec![Expr::Ident("a".into(), SPAN)],
+                        span: SPAN,
+                    }),
+                    then: vec![],
+                    otherwise: vec![Expr::Ident("a".into(), SPAN)],
+                    span: SPAN,
+                }],
+                span: SPAN,
+            }]),
+        )
+    }
+
+    #[test]
+    fn keywords_are_not_identifiers() {
+        assert!(parser::exprs("else").is_err());
+        assert_parses(
+            parser::exprs("iffy"),
+            Ok(vec![Expr::Ident("iffy".into(), SPAN)]),
+        );
+    }
+
+    #[test]
+    fn loops_parse() {
+        assert_parses(
+            parser::exprs("while i != 0 { i = i - 1 } loop { break }"),
+            Ok(vec![
+                Expr::While {
+                    cond: Box::new(Expr::binary(
+                        BinaryOp::Ne,
+                        Expr::Ident("i".into(), SPAN),
+                        Expr::Lit("0".into(), SPAN),
+                    )),
+                    body: vec![Expr::Set(
+                        Box::new(Expr::Ident("i".into(), SPAN)),
+                        Box::new(Expr::binary(
+                            BinaryOp::Sub,
+                            Expr::Ident("i".into(), SPAN),
+                            Expr::Lit("1".into(), SPAN),
+                        )),
+                        SPAN,
+                    )],
+                    span: SPAN,
+                },
+                Expr::Loop(vec![Expr::Break(SPAN)], SPAN),
+            ]),
+        )
+    }
+
+    #[test]
+    fn scalar_literals_parse() {
+        assert_parses(
+            parser::exprs(r"1.5 2e10 3 true falsey 'a' '\n'"),
+            Ok(vec![
+                Expr::Float("1.5".into(), SPAN),
+                Expr::Float("2e10".into(), SPAN),
+                Expr::Lit("3".into(), SPAN),
+                Expr::Bool(true, SPAN),
+                Expr::Ident("falsey".into(), SPAN),
+                Expr::Char('a', SPAN),
+                Expr::Char('\n', SPAN),
+            ]),
+        )
+    }
+
+    #[test]
+    fn string_literals_parse() {
+        assert_parses(
+            parser::exprs(r#"let greeting: str = "say \"hi\"\n" stdprint { &greeting }"#),
+            Ok(vec![
+                Expr::Assign(
+                    (
+                        Box::new(Expr::Ident("greeting".into(), SPAN)),
+                        Box::new(Expr::Ident("str".into(), SPAN)),
+                    ),
+                    Box::new(Expr::Str("say \"hi\"\n".into(), SPAN)),
+                    SPAN,
+                ),
+                Expr::Call {
+                    ident: Box::new(Expr::Ident("stdprint".into(), SPAN)),
+                    args: vec![Expr::GlobalDataAddr(
+                        Box::new(Expr::Ident("greeting".into(), SPAN)),
+                        SPAN,
+                    )],
+                    span: SPAN,
+                },
+            ]),
+        )
+    }
+
+    #[test]
+    fn structs_parse() {
+        let point = |x: &str, y: &str| Expr::Struct {
+            name: Box::new(Expr::Ident("Point".into(), SPAN)),
+            fields: vec![
+                (Expr::Ident("x".into(), SPAN), Expr::Lit(x.into(), SPAN)),
+                (Expr::Ident("y".into(), SPAN), Expr::Lit(y.into(), SPAN)),
+            ],
+            span: SPAN,
+        };
+        assert_parses(
+            parser::exprs(
+                "type Point { x: i64, y: i64 } Point { x: 1, y: 2 }.x foo { Point { x: 3, y: 4 } }",
+            ),
+            Ok(vec![
+                Expr::TypeDef {
+                    name: Box::new(Expr::Ident("Point".into(), SPAN)),
+                    fields: vec![
+                        (
+                            Expr::Ident("x".into(), SPAN),
+                            Expr::Ident("i64".into(), SPAN),
+                        ),
+                        (
+                            Expr::Ident("y".into(), SPAN),
+                            Expr::Ident("i64".into(), SPAN),
+                        ),
+                    ],
+                    span: SPAN,
+                },
+                Expr::Field(
+                    Box::new(point("1", "2")),
+                    Box::new(Expr::Ident("x".into(), SPAN)),
+                    SPAN,
+                ),
+                Expr::Call {
+                    ident: Box::new(Expr::Ident("foo".into(), SPAN)),
+                    args: vec![point("3", "4")],
+                    span: SPAN,
+                },
+            ]),
+        )
+    }
+
+    #[test]
+    fn enums_parse() {
+        let ident = |name: &str| Expr::Ident(name.into(), SPAN);
+        assert_parses(
+            parser::exprs(
+                "type Option = Some(i64) | None match Some(1) { Some(x) => x, None => { 0 } }",
+            ),
+            Ok(vec![
+                Expr::EnumDef {
+                    name: Box::new(ident("Option")),
+                    variants: vec![(ident("Some"), vec![ident("i64")]), (ident("None"), vec![])],
+                    span: SPAN,
+                },
+                Expr::Match {
+                    expr: Box::new(Expr::Variant {
+                        name: Box::new(ident("Some")),
+                        args: vec![Expr::Lit("1".into(), SPAN)],
+                        span: SPAN,
+                    }),
+                    arms: vec![
+                        MatchArm {
+                            variant: ident("Some"),
+                            bindings: vec![ident("x")],
+                            body: vec![ident("x")],
+                            span: SPAN,
+                        },
+                        MatchArm {
+                            variant: ident("None"),
+                            bindings: vec![],
+                            body: vec![Expr::Lit("0".into(), SPAN)],
+                            span: SPAN,
+                        },
+                    ],
+                    span: SPAN,
+                },
+            ]),
+        )
+    }
+
+    #[test]
+    fn arrays_parse() {
+        let ident = |name: &str| Expr::Ident(name.into(), SPAN);
+        assert_parses(
+            parser::exprs("let a: [i64] = [1 2] a[0]"),
+            Ok(vec![
+                Expr::Assign(
+                    (
+                        Box::new(ident("a")),
+                        Box::new(Expr::ArrayType(Box::new(ident("i64")), SPAN)),
+                    ),
+                    Box::new(Expr::Array(
+                        vec![Expr::Lit("1".into(), SPAN), Expr::Lit("2".into(), SPAN)],
+                        SPAN,
+                    )),
+                    SPAN,
+                ),
+                Expr::Index(
+                    Box::new(ident("a")),
+                    Box::new(Expr::Lit("0".into(), SPAN)),
+                    SPAN,
+                ),
+            ]),
+        )
+    }
+
+    #[test]
+    fn receive_after_parse() {
+        let ident = |name: &str| Expr::Ident(name.into(), SPAN);
+        assert_parses(
+            parser::exprs("let m: i64 = receive after t { 0 - 1 } receive"),
+            Ok(vec![
+                Expr::Assign(
+                    (Box::new(ident("m")), Box::new(ident("i64"))),
+                    Box::new(Expr::ReceiveAfter {
+                        timeout: Box::new(ident("t")),
+                        otherwise: vec![Expr::binary(
+                            BinaryOp::Sub,
+                            Expr::Lit("0".into(), SPAN),
+                            Expr::Lit("1".into(), SPAN),
+                        )],
+                        span: SPAN,
+                    }),
+                    SPAN,
+                ),
+                Expr::Receive(SPAN),
+            ]),
+        )
+    }
+
+    #[test]
+    fn modules_parse() {
+        let ident = |name: &str| Expr::Ident(name.into(), SPAN);
+        assert_parses(
+            parser::exprs("module app import math main: -> i64 { math::square { 3 } }"),
+            Ok(vec![
+                Expr::Module(Box::new(ident("app")), SPAN),
+                Expr::Import(Box::new(ident("math")), SPAN),
+                Expr::Function {
+                    name: Box::new(ident("main")),
+                    function_ty: Box::new(Expr::FunctionType {
+                        params: vec![],
+                        ret_ty: Box::new(ident("i64")),
+                        span: SPAN,
+                    }),
+                    body: vec![Expr::Call {
+                        ident: Box::new(ident("math::square")),
+                        args: vec![Expr::Lit("3".into(), SPAN)],
+                        span: SPAN,
+                    }],
+                    span: SPAN,
+                },
+            ]),
         )
     }
 }
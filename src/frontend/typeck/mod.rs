@@ -0,0 +1,1612 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Display},
+};
+
+use crate::{
+    diagnostics::{Diagnostic, Span},
+    frontend::parser::ast::expr::{BinaryOp, Expr, MatchArm, UnaryOp},
+};
+use unicorn_runtime::scheduler::io;
+
+/// Type of a value as written in the source.
+#[derive(Debug, Clone, Eq)]
+pub enum Type {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    F32,
+    F64,
+    Bool,
+    Char,
+    /// Address of NUL-terminated read-only bytes.
+    Str,
+    /// Process id returned by `spawn` and `self`.
+    Pid,
+    /// Message telling that a linked or monitored process exited, read with
+    /// `exit_pid` and `exit_reason`.
+    Exit,
+    Nil,
+    Function {
+        params: Vec<Type>,
+        ret: Box<Type>,
+    },
+    /// User-defined `type`, its values are pointers to its fields.
+    Struct {
+        name: String,
+        fields: Vec<(String, Type)>,
+    },
+    /// User-defined sum type, its values are pointers to a tag and the values of
+    /// the variant. Within its own definition it has no variants yet.
+    Enum {
+        name: String,
+        variants: Vec<(String, Vec<Type>)>,
+    },
+    /// Growable `[T]`, its values are pointers to its length, capacity and elements.
+    Array(Box<Type>),
+}
+
+impl PartialEq for Type {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            // Type names are unique, and a recursive enum refers to itself by name only.
+            (Type::Struct { name, .. }, Type::Struct { name: other, .. })
+            | (Type::Enum { name, .. }, Type::Enum { name: other, .. }) => name == other,
+            (
+                Type::Function { params, ret },
+                Type::Function {
+                    params: other_params,
+                    ret: other_ret,
+                },
+            ) => params == other_params && ret == other_ret,
+            (Type::Array(ty), Type::Array(other)) => ty == other,
+            (ty, other) => std::mem::discriminant(ty) == std::mem::discriminant(other),
+        }
+    }
+}
+
+impl Type {
+    pub fn is_integer(&self) -> bool {
+        self.bits().is_some()
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, Type::F32 | Type::F64)
+    }
+
+    /// Types whose values fit in a variable slot and can be compared.
+    pub fn is_scalar(&self) -> bool {
+        self.is_integer() || self.is_float() || matches!(self, Type::Bool | Type::Char)
+    }
+
+    /// Types whose values can be sent to and spawned into other processes,
+    /// which get a copy of the value and nothing it points to.
+    pub fn is_message(&self) -> bool {
+        self.is_scalar() || matches!(self, Type::Pid | Type::Exit)
+    }
+
+    /// Width of an integer type.
+    pub fn bits(&self) -> Option<u32> {
+        match self {
+            Type::I8 | Type::U8 => Some(8),
+            Type::I16 | Type::U16 => Some(16),
+            Type::I32 | Type::U32 => Some(32),
+            Type::I64 | Type::U64 => Some(64),
+            _ => None,
+        }
+    }
+
+    pub fn is_signed(&self) -> bool {
+        matches!(self, Type::I8 | Type::I16 | Type::I32 | Type::I64)
+    }
+
+    /// Smallest and largest value of an integer type.
+    pub fn range(&self) -> Option<(i128, i128)> {
+        let bits = self.bits()?;
+        Some(if self.is_signed() {
+            (-(1 << (bits - 1)), (1 << (bits - 1)) - 1)
+        } else {
+            (0, (1 << bits) - 1)
+        })
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::I8 => write!(f, "i8"),
+            Type::I16 => write!(f, "i16"),
+            Type::I32 => write!(f, "i32"),
+            Type::I64 => write!(f, "i64"),
+            Type::U8 => write!(f, "u8"),
+            Type::U16 => write!(f, "u16"),
+            Type::U32 => write!(f, "u32"),
+            Type::U64 => write!(f, "u64"),
+            Type::F32 => write!(f, "f32"),
+            Type::F64 => write!(f, "f64"),
+            Type::Bool => write!(f, "bool"),
+            Type::Char => write!(f, "char"),
+            Type::Str => write!(f, "str"),
+            Type::Pid => write!(f, "pid"),
+            Type::Exit => write!(f, "exit"),
+            Type::Nil => write!(f, "nil"),
+            Type::Struct { name, .. } | Type::Enum { name, .. } => write!(f, "{name}"),
+            Type::Array(ty) => write!(f, "[{ty}]"),
+            Type::Function { params, ret } => {
+                write!(f, "(")?;
+                for (i, param) in params.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{param}")?;
+                }
+                write!(f, ") -> {ret}")
+            }
+        }
+    }
+}
+
+/// Types the middleware lowers by: the ones of literals, of the operands of
+/// operators, of the argument of `stdprint`, of the struct built or accessed
+/// by a struct literal or a field access, of the enum a variant builds or a
+/// `match` inspects, of the array built, indexed or passed to `len` and
+/// `push` and of the result of the other builtins. The types of the values of
+/// all expressions are kept apart, see [`TypeTable::value`].
+///
+/// Both are keyed by the node, its module and its span. Within one module no
+/// two nodes share a span: the parser gives every node the source it was
+/// parsed from and a parent always covers more of it than its children. Nodes
+/// the middleware desugars into reuse the span of the node they replace, so
+/// they are never looked up.
+#[derive(Debug, Default)]
+pub struct TypeTable {
+    /// Module of the checked nodes, `None` for the program.
+    module: Option<String>,
+    types: HashMap<(Option<String>, Span), Type>,
+    values: HashMap<(Option<String>, Span), Type>,
+}
+
+impl TypeTable {
+    pub fn get(&self, span: Span) -> Option<&Type> {
+        self.types.get(&(self.module.clone(), span))
+    }
+
+    /// Type of the value of the expression at `span`.
+    pub fn value(&self, span: Span) -> Option<&Type> {
+        self.values.get(&(self.module.clone(), span))
+    }
+
+    fn insert(&mut self, span: Span, ty: Type) {
+        self.types.insert((self.module.clone(), span), ty);
+    }
+}
+
+/// Functions provided by the compiler unless a function of the same name shadows them.
+pub const BUILTINS: &[&str] = &[
+    "len",
+    "push",
+    "spawn",
+    "send",
+    "self",
+    "link",
+    "monitor",
+    "restart",
+    "exit_pid",
+    "exit_reason",
+    "sleep",
+    "now_ms",
+];
+
+/// Checks a whole source file before it is lowered into the middleware.
+pub fn check(exprs: &[Expr]) -> Result<TypeTable, Diagnostic> {
+    let mut checker = Checker::default();
+    checker.check_program(exprs)?;
+    Ok(checker.table)
+}
+
+/// Checks the module `name`, or a program for `None`, whose calls can name the
+/// functions of `imports` by their qualified names. Returns the types of its
+/// own functions too, qualified with `name`.
+pub fn check_module(
+    exprs: &[Expr],
+    name: Option<&str>,
+    imports: &[(String, Type)],
+) -> Result<(TypeTable, Vec<(String, Type)>), Diagnostic> {
+    let mut checker = Checker::default();
+    checker.table.module = name.map(str::to_owned);
+    for (function, ty) in imports {
+        checker.declare(function, ty.clone());
+    }
+    checker.check_program(exprs)?;
+
+    let mut exports = vec![];
+    for expr in exprs {
+        if let Expr::Function { name: function, .. } = expr {
+            let function = ident(function, "Function name must be an identifier")?;
+            let qualified = match name {
+                Some(name) => format!("{name}::{function}"),
+                None => function.to_owned(),
+            };
+            exports.push((qualified, checker.lookup(function).unwrap()));
+        }
+    }
+    Ok((checker.table, exports))
+}
+
+/// Walks the frontend AST with the types of every visible name.
+pub struct Checker {
+    /// Named types: the builtin scalars and user-defined types.
+    types: HashMap<String, Type>,
+    /// Innermost scope last, the first one holds the top-level functions.
+    scopes: Vec<HashMap<String, Type>>,
+    /// Top-level names of runtime functions, which are called but have no
+    /// function value.
+    runtime: HashSet<String>,
+    /// Top-level data, whose address `&name` takes.
+    data: HashMap<String, Type>,
+    /// Enum and index of every variant.
+    variants: HashMap<String, (String, usize)>,
+    /// Loops enclosing the expression being checked within the current function.
+    loops: usize,
+    table: TypeTable,
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        let types = [
+            Type::I8,
+            Type::I16,
+            Type::I32,
+            Type::I64,
+            Type::U8,
+            Type::U16,
+            Type::U32,
+            Type::U64,
+            Type::F32,
+            Type::F64,
+            Type::Bool,
+            Type::Char,
+            Type::Str,
+            Type::Pid,
+            Type::Exit,
+            Type::Nil,
+        ]
+        .into_iter()
+        .map(|ty| (ty.to_string(), ty))
+        .collect::<HashMap<_, _>>();
+
+        let runtime: HashMap<_, _> = unicorn_runtime::FUNCTIONS
+            .iter()
+            .map(|function| {
+                let ty = Type::Function {
+                    params: function
+                        .params
+                        .iter()
+                        .map(|param| types[*param].clone())
+                        .collect(),
+                    ret: Box::new(function.returns.map_or(Type::Nil, |ret| types[ret].clone())),
+                };
+                (function.name.to_owned(), ty)
+            })
+            .collect();
+
+        Self {
+            types,
+            runtime: runtime.keys().cloned().collect(),
+            scopes: vec![runtime],
+            data: HashMap::new(),
+            variants: HashMap::new(),
+            loops: 0,
+            table: TypeTable::default(),
+        }
+    }
+}
+
+impl Checker {
+    /// Defines the top-level types, declares every top-level function and data,
+    /// then checks the function bodies.
+    pub fn check_program(&mut self, exprs: &[Expr]) -> Result<(), Diagnostic> {
+        for expr in exprs {
+            match expr {
+                Expr::TypeDef { name, fields, .. } => self.define_type(name, fields)?,
+                Expr::EnumDef { name, variants, .. } => self.define_enum(name, variants)?,
+                _ => {}
+            }
+        }
+
+        for expr in exprs {
+            match expr {
+                Expr::TypeDef { .. }
+                | Expr::EnumDef { .. }
+                | Expr::Module(..)
+                | Expr::Import(..) => {}
+                Expr::Function {
+                    name: name_expr,
+                    function_ty,
+                    ..
+                } => {
+                    let name = ident(name_expr, "Function name must be an identifier")?;
+                    if name.contains("::") {
+                        return Err(Diagnostic::error(
+                            expr.span(),
+                            "Only functions of imported modules are named with `::`",
+                        ));
+                    }
+                    let ty = self.resolve(function_ty)?;
+                    if name == "main" && self.table.module.is_none() {
+                        entry_point(&ty, name_expr.span())?;
+                    }
+                    self.declare(name, ty);
+                }
+                Expr::Assign((name, ty), value, _) => {
+                    let name = ident(name, "Expected a variable name")?;
+                    let ty = self.resolve(ty)?;
+                    let Expr::Str(..) = **value else {
+                        return Err(Diagnostic::error(
+                            value.span(),
+                            "Top-level data must be a string literal",
+                        ));
+                    };
+                    expect(&ty, &Type::Str, value.span())?;
+                    self.data.insert(name.to_owned(), ty);
+                }
+                expr => {
+                    return Err(Diagnostic::error(
+                        expr.span(),
+                        "Only functions and data are allowed at the top level",
+                    ));
+                }
+            }
+        }
+
+        for expr in exprs {
+            if let Expr::Function {
+                name,
+                function_ty,
+                body,
+                span,
+            } = expr
+            {
+                self.check_function(name, function_ty, body, *span)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Defines a struct type, a field can be of a type defined above it.
+    fn define_type(&mut self, name: &Expr, fields: &[(Expr, Expr)]) -> Result<(), Diagnostic> {
+        let name_str = self.type_name(name)?;
+        let mut resolved: Vec<(String, Type)> = vec![];
+        for (field, ty) in fields {
+            let field_str = ident(field, "Expected a field name")?;
+            if resolved.iter().any(|(defined, _)| defined == field_str) {
+                return Err(Diagnostic::error(
+                    field.span(),
+                    format!("Field `{field_str}` is defined twice"),
+                ));
+            }
+            resolved.push((field_str.to_owned(), self.resolve(ty)?));
+        }
+        let ty = Type::Struct {
+            name: name_str.to_owned(),
+            fields: resolved,
+        };
+        self.types.insert(name_str.to_owned(), ty);
+        Ok(())
+    }
+
+    /// Defines a sum type, a variant can hold the enum itself.
+    fn define_enum(
+        &mut self,
+        name: &Expr,
+        variants: &[(Expr, Vec<Expr>)],
+    ) -> Result<(), Diagnostic> {
+        let name_str = self.type_name(name)?;
+        let mut ty = Type::Enum {
+            name: name_str.to_owned(),
+            variants: vec![],
+        };
+        self.types.insert(name_str.to_owned(), ty.clone());
+
+        let mut resolved: Vec<(String, Vec<Type>)> = vec![];
+        for (variant, payload) in variants {
+            let variant_str = ident(variant, "Expected a variant name")?;
+            if self.variants.contains_key(variant_str)
+                || resolved.iter().any(|(defined, _)| defined == variant_str)
+            {
+                return Err(Diagnostic::error(
+                    variant.span(),
+                    format!("Variant `{variant_str}` is already defined"),
+                ));
+            }
+            let payload = payload
+                .iter()
+                .map(|ty| self.resolve(ty))
+                .collect::<Result<_, _>>()?;
+            resolved.push((variant_str.to_owned(), payload));
+        }
+        for (i, (variant, _)) in resolved.iter().enumerate() {
+            self.variants
+                .insert(variant.clone(), (name_str.to_owned(), i));
+        }
+        if let Type::Enum { variants, .. } = &mut ty {
+            *variants = resolved;
+        }
+        self.types.insert(name_str.to_owned(), ty);
+        Ok(())
+    }
+
+    fn type_name<'a>(&self, name: &'a Expr) -> Result<&'a str, Diagnostic> {
+        let name_str = ident(name, "Expected a type name")?;
+        if self.types.contains_key(name_str) {
+            return Err(Diagnostic::error(
+                name.span(),
+                format!("Type `{name_str}` is already defined"),
+            ));
+        }
+        Ok(name_str)
+    }
+
+    /// The enum as defined, for a reference to it from within its own definition.
+    fn defined(&self, ty: Type) -> Type {
+        match &ty {
+            Type::Enum { name, .. } => self.types.get(name).cloned().unwrap_or(ty),
+            _ => ty,
+        }
+    }
+
+    /// Checks the values of a variant, a variant without any is written as a plain identifier.
+    fn check_variant(
+        &mut self,
+        name: &Expr,
+        args: &[Expr],
+        span: Span,
+    ) -> Result<Type, Diagnostic> {
+        let name_str = ident(name, "Expected a variant name")?;
+        let Some((enum_name, index)) = self.variants.get(name_str).cloned() else {
+            return Err(Diagnostic::error(
+                name.span(),
+                format!("Variant `{name_str}` is not defined"),
+            ));
+        };
+        let ty = self.types[&enum_name].clone();
+        let Type::Enum { variants, .. } = &ty else {
+            unreachable!("variants are defined with their enum")
+        };
+        let payload = &variants[index].1;
+        if payload.len() != args.len() {
+            return Err(Diagnostic::error(
+                span,
+                format!(
+                    "Variant `{name_str}` holds {} values but {} were given",
+                    payload.len(),
+                    args.len()
+                ),
+            ));
+        }
+        for (arg, param) in args.iter().zip(payload) {
+            let found = self.check_expr(arg, Some(param))?;
+            expect(param, &found, arg.span())?;
+        }
+        self.table.insert(span, ty.clone());
+        Ok(ty)
+    }
+
+    /// Checks that the arms of a `match` cover every variant exactly once, its type
+    /// is the one of the arms when they agree.
+    fn check_match(
+        &mut self,
+        expr: &Expr,
+        arms: &[MatchArm],
+        span: Span,
+        expected: Option<&Type>,
+    ) -> Result<Type, Diagnostic> {
+        let ty = self.check_expr(expr, None)?;
+        let ty = self.defined(ty);
+        let Type::Enum { variants, .. } = &ty else {
+            return Err(Diagnostic::error(
+                expr.span(),
+                format!("`{ty}` is not an enum"),
+            ));
+        };
+        self.table.insert(span, ty.clone());
+
+        let mut covered = vec![false; variants.len()];
+        let mut arms_ty: Option<Type> = None;
+        let mut agree = true;
+        for arm in arms {
+            if covered.iter().all(|covered| *covered) {
+                return Err(Diagnostic::error(arm.span, "Unreachable pattern"));
+            }
+            let name = ident(&arm.variant, "Expected a variant name")?;
+            let mut scope = HashMap::new();
+            if name == "_" {
+                covered.fill(true);
+            } else {
+                let Some(index) = variants.iter().position(|(variant, _)| variant == name) else {
+                    return Err(Diagnostic::error(
+                        arm.variant.span(),
+                        format!("`{ty}` has no variant `{name}`"),
+                    ));
+                };
+                if covered[index] {
+                    return Err(Diagnostic::error(
+                        arm.variant.span(),
+                        format!("Variant `{name}` is matched twice"),
+                    ));
+                }
+                covered[index] = true;
+                let payload = &variants[index].1;
+                if payload.len() != arm.bindings.len() {
+                    return Err(Diagnostic::error(
+                        arm.span,
+                        format!(
+                            "Variant `{name}` holds {} values but {} were bound",
+                            payload.len(),
+                            arm.bindings.len()
+                        ),
+                    ));
+                }
+                for (binding, ty) in arm.bindings.iter().zip(payload) {
+                    let binding = ident(binding, "Expected a variable name")?;
+                    scope.insert(binding.to_owned(), ty.clone());
+                }
+            }
+
+            self.scopes.push(scope);
+            let arm_ty = self.check_body(&arm.body, expected.or(arms_ty.as_ref()));
+            self.scopes.pop();
+            let arm_ty = arm_ty?;
+            match &arms_ty {
+                None => arms_ty = Some(arm_ty),
+                Some(ty) if *ty != arm_ty => agree = false,
+                Some(_) => {}
+            }
+        }
+        if let Some(missing) = covered.iter().position(|covered| !covered) {
+            return Err(Diagnostic::error(
+                span,
+                format!(
+                    "Non-exhaustive match, `{}` is not covered",
+                    variants[missing].0
+                ),
+            ));
+        }
+        // Arms of different types only make sense when the value is unused.
+        Ok(match arms_ty {
+            Some(ty) if agree => ty,
+            _ => Type::Nil,
+        })
+    }
+
+    /// Resolves a type written in the source.
+    pub fn resolve(&self, ty: &Expr) -> Result<Type, Diagnostic> {
+        match ty {
+            Expr::Ident(name, span) => self
+                .types
+                .get(name)
+                .cloned()
+                .ok_or_else(|| Diagnostic::error(*span, format!("Unknown type `{name}`"))),
+            Expr::FunctionType { params, ret_ty, .. } => Ok(Type::Function {
+                params: params
+                    .iter()
+                    .map(|(_, ty)| self.resolve(ty))
+                    .collect::<Result<_, _>>()?,
+                ret: Box::new(self.resolve(ret_ty)?),
+            }),
+            Expr::ArrayType(ty, _) => Ok(Type::Array(Box::new(self.resolve(ty)?))),
+            ty => Err(Diagnostic::error(ty.span(), "Expected a type")),
+        }
+    }
+
+    fn check_function(
+        &mut self,
+        name: &Expr,
+        function_ty: &Expr,
+        body: &[Expr],
+        span: Span,
+    ) -> Result<Type, Diagnostic> {
+        let name = ident(name, "Function name must be an identifier")?;
+        let ty = self.resolve(function_ty)?;
+        let Type::Function { params, ret } = &ty else {
+            return Err(Diagnostic::error(
+                function_ty.span(),
+                "Expected a function type",
+            ));
+        };
+
+        let mut scope = HashMap::new();
+        if let Expr::FunctionType {
+            params: param_names,
+            ..
+        } = function_ty
+        {
+            for ((param, _), ty) in param_names.iter().zip(params) {
+                let param = ident(param, "Parameter name must be an identifier")?;
+                scope.insert(param.to_owned(), ty.clone());
+            }
+        }
+
+        let loops = std::mem::take(&mut self.loops);
+        self.scopes.push(scope);
+        let expected = (**ret != Type::Nil).then_some(&**ret);
+        let body_ty = self.check_body(body, expected);
+        self.scopes.pop();
+        self.loops = loops;
+        let body_ty = body_ty?;
+
+        if **ret != Type::Nil && body_ty != **ret {
+            let span = body.last().map_or(span, Expr::span);
+            return Err(Diagnostic::error(
+                span,
+                format!("Function `{name}` returns `{ret}` but its body evaluates to `{body_ty}`"),
+            ));
+        }
+        Ok(ty)
+    }
+
+    /// Checks a sequence of expressions in its own scope, its type is the one of the last.
+    fn check_body(&mut self, body: &[Expr], expected: Option<&Type>) -> Result<Type, Diagnostic> {
+        self.scopes.push(HashMap::new());
+        let mut ty = Ok(Type::Nil);
+        for (i, expr) in body.iter().enumerate() {
+            let expected = if i == body.len() - 1 { expected } else { None };
+            ty = self.check_expr(expr, expected);
+            if ty.is_err() {
+                break;
+            }
+        }
+        self.scopes.pop();
+        ty
+    }
+
+    /// Checks `expr` and returns its type, `expected` only guides the type of literals.
+    fn check_expr(&mut self, expr: &Expr, expected: Option<&Type>) -> Result<Type, Diagnostic> {
+        let ty = self.check_node(expr, expected)?;
+        let node = (self.table.module.clone(), expr.span());
+        self.table.values.insert(node, ty.clone());
+        Ok(ty)
+    }
+
+    fn check_node(&mut self, expr: &Expr, expected: Option<&Type>) -> Result<Type, Diagnostic> {
+        match expr {
+            Expr::Lit(_, span) => {
+                let ty = match expected {
+                    Some(ty) if ty.is_integer() => ty.clone(),
+                    _ => Type::I64,
+                };
+                self.table.insert(*span, ty.clone());
+                Ok(ty)
+            }
+            Expr::Float(_, span) => {
+                let ty = match expected {
+                    Some(Type::F32) => Type::F32,
+                    _ => Type::F64,
+                };
+                self.table.insert(*span, ty.clone());
+                Ok(ty)
+            }
+            Expr::Bool(..) => Ok(Type::Bool),
+            Expr::Char(..) => Ok(Type::Char),
+            Expr::Str(..) => Ok(Type::Str),
+            Expr::Ident(name, span) => match self.lookup(name) {
+                Some(_) if self.runtime.contains(name) => Err(Diagnostic::error(
+                    *span,
+                    format!("Runtime function `{name}` can only be called"),
+                )),
+                Some(ty) => Ok(ty),
+                None if self.variants.contains_key(name) => self.check_variant(expr, &[], *span),
+                None => Err(Diagnostic::error(
+                    *span,
+                    format!("Variable `{name}` is not defined"),
+                )),
+            },
+            Expr::Call {
+                ident: callee,
+                args,
+                span,
+            } => {
+                let name = ident(callee, "Callee must be an identifier")?;
+                if name == "stdprint" && args.len() == 1 {
+                    return self.check_print(&args[0], *span);
+                }
+                let Some(ty) = self.lookup(name) else {
+                    if let Some(ty) = self.check_builtin(name, args, *span)? {
+                        return Ok(ty);
+                    }
+                    return Err(Diagnostic::error(
+                        *span,
+                        format!("Function `{name}` is not defined"),
+                    ));
+                };
+                let Type::Function { params, ret } = ty else {
+                    return Err(Diagnostic::error(
+                        callee.span(),
+                        format!("`{name}` is not a function but `{ty}`"),
+                    ));
+                };
+                if params.len() != args.len() {
+                    return Err(Diagnostic::error(
+                        *span,
+                        format!(
+                            "Function `{name}` takes {} arguments but {} were given",
+                            params.len(),
+                            args.len()
+                        ),
+                    ));
+                }
+                for (arg, param) in args.iter().zip(&params) {
+                    let ty = self.check_expr(arg, Some(param))?;
+                    expect(param, &ty, arg.span())?;
+                }
+                Ok(*ret)
+            }
+            Expr::Function {
+                name,
+                function_ty,
+                body,
+                span,
+            } => {
+                // Bound before its body is checked, so it can call itself.
+                let ty = self.resolve(function_ty)?;
+                self.declare(ident(name, "Function name must be an identifier")?, ty);
+                self.check_function(name, function_ty, body, *span)
+            }
+            Expr::FunctionType { span, .. } => {
+                Err(Diagnostic::error(*span, "A function type is not a value"))
+            }
+            Expr::Assign((name, ty), expr, _) => {
+                let ty = self.resolve(ty)?;
+                let found = self.check_expr(expr, Some(&ty))?;
+                expect(&ty, &found, expr.span())?;
+                self.declare(ident(name, "Expected a variable name")?, ty.clone());
+                Ok(ty)
+            }
+            Expr::GlobalDataAddr(name, _) => {
+                let name_str = ident(name, "Expected a data name")?;
+                self.data.get(name_str).cloned().ok_or_else(|| {
+                    Diagnostic::error(name.span(), format!("Data `{name_str}` is not defined"))
+                })
+            }
+            Expr::Binary {
+                op, lhs, rhs, span, ..
+            } => {
+                let expected = match op {
+                    BinaryOp::Add
+                    | BinaryOp::Sub
+                    | BinaryOp::Mul
+                    | BinaryOp::Div
+                    | BinaryOp::Rem => expected,
+                    _ => None,
+                };
+                // A literal takes the type of the other operand.
+                let (lhs_ty, rhs_ty) = if is_literal(lhs) {
+                    let rhs_ty = self.check_expr(rhs, expected)?;
+                    (self.check_expr(lhs, Some(&rhs_ty))?, rhs_ty)
+                } else {
+                    let lhs_ty = self.check_expr(lhs, expected)?;
+                    let rhs_ty = self.check_expr(rhs, Some(&lhs_ty))?;
+                    (lhs_ty, rhs_ty)
+                };
+                self.table.insert(*span, lhs_ty.clone());
+                match op {
+                    BinaryOp::And | BinaryOp::Or => {
+                        condition(&lhs_ty, lhs.span())?;
+                        condition(&rhs_ty, rhs.span())?;
+                        return Ok(Type::Bool);
+                    }
+                    BinaryOp::Eq | BinaryOp::Ne if !lhs_ty.is_scalar() => {
+                        return Err(Diagnostic::error(
+                            lhs.span(),
+                            format!("`{lhs_ty}` values cannot be compared"),
+                        ));
+                    }
+                    BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge
+                        if !(lhs_ty.is_integer() || lhs_ty.is_float() || lhs_ty == Type::Char) =>
+                    {
+                        return Err(Diagnostic::error(
+                            lhs.span(),
+                            format!("`{lhs_ty}` values cannot be ordered"),
+                        ));
+                    }
+                    BinaryOp::Rem => integer(&lhs_ty, lhs.span())?,
+                    BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div
+                        if !(lhs_ty.is_integer() || lhs_ty.is_float()) =>
+                    {
+                        return Err(Diagnostic::error(
+                            lhs.span(),
+                            format!("Expected a number, found `{lhs_ty}`"),
+                        ));
+                    }
+                    _ => {}
+                }
+                expect(&lhs_ty, &rhs_ty, rhs.span())?;
+                Ok(match op {
+                    BinaryOp::Add
+                    | BinaryOp::Sub
+                    | BinaryOp::Mul
+                    | BinaryOp::Div
+                    | BinaryOp::Rem => lhs_ty,
+                    _ => Type::Bool,
+                })
+            }
+            Expr::Unary { op, expr, span } => match op {
+                UnaryOp::Not => {
+                    let ty = self.check_expr(expr, expected)?;
+                    condition(&ty, expr.span())?;
+                    self.table.insert(*span, ty.clone());
+                    Ok(ty)
+                }
+                UnaryOp::Neg => {
+                    let ty = self.check_expr(expr, expected)?;
+                    if !(ty.is_signed() || ty.is_float()) {
+                        return Err(Diagnostic::error(
+                            expr.span(),
+                            format!("Expected a signed number, found `{ty}`"),
+                        ));
+                    }
+                    self.table.insert(*span, ty.clone());
+                    Ok(ty)
+                }
+            },
+            Expr::If {
+                cond,
+                then,
+                otherwise,
+                ..
+            } => {
+                let cond_ty = self.check_expr(cond, None)?;
+                condition(&cond_ty, cond.span())?;
+                let then_ty = self.check_body(then, expected)?;
+                if otherwise.is_empty() {
+                    // The missing branch evaluates to `0`.
+                    return Ok(if then_ty.is_scalar() {
+                        then_ty
+                    } else {
+                        Type::Nil
+                    });
+                }
+                let otherwise_ty = self.check_body(otherwise, expected.or(Some(&then_ty)))?;
+                // Branches of different types only make sense when the value is unused.
+                Ok(if then_ty == otherwise_ty {
+                    then_ty
+                } else {
+                    Type::Nil
+                })
+            }
+            Expr::While { cond, body, .. } => {
+                let cond_ty = self.check_expr(cond, None)?;
+                condition(&cond_ty, cond.span())?;
+                self.check_loop(body)
+            }
+            Expr::Loop(body, _) => self.check_loop(body),
+            Expr::Break(span) => {
+                if self.loops == 0 {
+                    return Err(Diagnostic::error(*span, "`break` outside of a loop"));
+                }
+                Ok(Type::Nil)
+            }
+            Expr::Variant { name, args, span } => self.check_variant(name, args, *span),
+            Expr::Match { expr, arms, span } => self.check_match(expr, arms, *span, expected),
+            Expr::TypeDef { span, .. } | Expr::EnumDef { span, .. } => Err(Diagnostic::error(
+                *span,
+                "Types can only be defined at the top level",
+            )),
+            Expr::Module(_, span) | Expr::Import(_, span) => Err(Diagnostic::error(
+                *span,
+                "Modules can only be declared and imported at the top level",
+            )),
+            Expr::Struct { name, fields, span } => {
+                let ty = self.resolve(name)?;
+                let Type::Struct {
+                    fields: defined, ..
+                } = &ty
+                else {
+                    return Err(Diagnostic::error(
+                        name.span(),
+                        format!("`{ty}` is not a struct"),
+                    ));
+                };
+                let given = |name: &str| {
+                    fields
+                        .iter()
+                        .filter(
+                            |(field, _)| matches!(field, Expr::Ident(field, _) if field == name),
+                        )
+                        .count()
+                };
+                for (field, value) in fields {
+                    let field_str = ident(field, "Expected a field name")?;
+                    let Some((_, field_ty)) = defined.iter().find(|(name, _)| name == field_str)
+                    else {
+                        return Err(Diagnostic::error(
+                            field.span(),
+                            format!("Struct `{ty}` has no field `{field_str}`"),
+                        ));
+                    };
+                    if given(field_str) > 1 {
+                        return Err(Diagnostic::error(
+                            field.span(),
+                            format!("Field `{field_str}` is given twice"),
+                        ));
+                    }
+                    let found = self.check_expr(value, Some(field_ty))?;
+                    expect(field_ty, &found, value.span())?;
+                }
+                if let Some((missing, _)) = defined.iter().find(|(name, _)| given(name) == 0) {
+                    return Err(Diagnostic::error(
+                        *span,
+                        format!("Missing field `{missing}` of `{ty}`"),
+                    ));
+                }
+                self.table.insert(*span, ty.clone());
+                Ok(ty)
+            }
+            Expr::Field(expr, field, span) => {
+                let ty = self.check_expr(expr, None)?;
+                let field_str = ident(field, "Expected a field name")?;
+                let Type::Struct { fields, .. } = &ty else {
+                    return Err(Diagnostic::error(
+                        expr.span(),
+                        format!("`{ty}` is not a struct"),
+                    ));
+                };
+                let Some((_, field_ty)) = fields.iter().find(|(name, _)| name == field_str) else {
+                    return Err(Diagnostic::error(
+                        field.span(),
+                        format!("Struct `{ty}` has no field `{field_str}`"),
+                    ));
+                };
+                let field_ty = field_ty.clone();
+                self.table.insert(*span, ty);
+                Ok(field_ty)
+            }
+            Expr::ArrayType(_, span) => {
+                Err(Diagnostic::error(*span, "An array type is not a value"))
+            }
+            Expr::Array(elems, span) => {
+                let elem_ty = match (expected, elems.first()) {
+                    (Some(Type::Array(ty)), _) => (**ty).clone(),
+                    (_, Some(first)) => self.check_expr(first, None)?,
+                    (_, None) => {
+                        return Err(Diagnostic::error(
+                            *span,
+                            "The type of an empty array must be declared",
+                        ));
+                    }
+                };
+                for elem in elems {
+                    let found = self.check_expr(elem, Some(&elem_ty))?;
+                    expect(&elem_ty, &found, elem.span())?;
+                }
+                let ty = Type::Array(Box::new(elem_ty));
+                self.table.insert(*span, ty.clone());
+                Ok(ty)
+            }
+            Expr::Index(expr, index, span) => {
+                let elem_ty = self.check_array(expr, *span)?;
+                let index_ty = self.check_expr(index, None)?;
+                integer(&index_ty, index.span())?;
+                Ok(elem_ty)
+            }
+            // Messages carry no type, a `receive` is read as the type it is used as.
+            Expr::Receive(span) => match expected {
+                Some(ty) if ty.is_message() => Ok(ty.clone()),
+                Some(ty) => Err(Diagnostic::error(
+                    *span,
+                    format!("`{ty}` values cannot be received"),
+                )),
+                None => Ok(Type::I64),
+            },
+            Expr::ReceiveAfter {
+                timeout,
+                otherwise,
+                span,
+            } => {
+                let ty = match expected {
+                    Some(ty) if ty.is_message() => ty.clone(),
+                    Some(ty) => {
+                        return Err(Diagnostic::error(
+                            *span,
+                            format!("`{ty}` values cannot be received"),
+                        ));
+                    }
+                    None => Type::I64,
+                };
+                let timeout_ty = self.check_expr(timeout, None)?;
+                integer(&timeout_ty, timeout.span())?;
+                let found = self.check_body(otherwise, Some(&ty))?;
+                expect(&ty, &found, *span)?;
+                Ok(ty)
+            }
+            Expr::Set(name, expr, _) => {
+                let name_str = ident(name, "Expected a variable name")?;
+                let Some(ty) = self.lookup(name_str) else {
+                    return Err(Diagnostic::error(
+                        name.span(),
+                        format!("Variable `{name_str}` is not defined"),
+                    ));
+                };
+                let found = self.check_expr(expr, Some(&ty))?;
+                expect(&ty, &found, expr.span())?;
+                Ok(ty)
+            }
+        }
+    }
+
+    /// `stdprint` takes any scalar, the middleware calls the variant of the
+    /// runtime printer for the type recorded at the call.
+    fn check_print(&mut self, arg: &Expr, span: Span) -> Result<Type, Diagnostic> {
+        let ty = self.check_expr(arg, None)?;
+        if !ty.is_scalar() && ty != Type::Str {
+            return Err(Diagnostic::error(
+                arg.span(),
+                format!("`{ty}` values cannot be printed"),
+            ));
+        }
+        self.table.insert(span, ty);
+        Ok(Type::Nil)
+    }
+
+    /// `len { array }` is the number of elements of an array and `push { array
+    /// value }` appends to it. `spawn { f args }` starts a process running `f`,
+    /// `send { pid message }` adds to its mailbox and `self {}` is the id of the
+    /// current process. `sleep { ms }` parks the process for `ms` milliseconds
+    /// and `now_ms {}` reads the clock of the scheduler. [`BUILTINS`] records
+    /// their result at the call.
+    fn check_builtin(
+        &mut self,
+        name: &str,
+        args: &[Expr],
+        span: Span,
+    ) -> Result<Option<Type>, Diagnostic> {
+        let ret = match (name, args) {
+            ("len", [array]) => {
+                self.check_array(array, span)?;
+                Type::I64
+            }
+            ("push", [array, value]) => {
+                let elem_ty = self.check_array(array, span)?;
+                let found = self.check_expr(value, Some(&elem_ty))?;
+                expect(&elem_ty, &found, value.span())?;
+                Type::Nil
+            }
+            ("spawn", [function, args @ ..]) => {
+                self.check_spawn(function, args, span)?;
+                Type::Pid
+            }
+            ("send", [pid, message]) => {
+                let ty = self.check_expr(pid, None)?;
+                expect(&Type::Pid, &ty, pid.span())?;
+                let ty = self.check_expr(message, None)?;
+                message_type(&ty, message.span())?;
+                self.table.insert(span, Type::Nil);
+                Type::Nil
+            }
+            ("self", []) => {
+                self.table.insert(span, Type::Pid);
+                Type::Pid
+            }
+            ("link" | "monitor" | "restart", [pid]) => {
+                let ty = self.check_expr(pid, None)?;
+                expect(&Type::Pid, &ty, pid.span())?;
+                let ret = if name == "restart" {
+                    Type::Pid
+                } else {
+                    Type::Nil
+                };
+                self.table.insert(span, ret.clone());
+                ret
+            }
+            ("exit_pid" | "exit_reason", [exit]) => {
+                let ty = self.check_expr(exit, None)?;
+                expect(&Type::Exit, &ty, exit.span())?;
+                let ret = if name == "exit_pid" {
+                    Type::Pid
+                } else {
+                    Type::I64
+                };
+                self.table.insert(span, ret.clone());
+                ret
+            }
+            ("sleep", [ms]) => {
+                let ty = self.check_expr(ms, None)?;
+                integer(&ty, ms.span())?;
+                self.table.insert(span, Type::Nil);
+                Type::Nil
+            }
+            ("now_ms", []) => {
+                self.table.insert(span, Type::I64);
+                Type::I64
+            }
+            _ => return self.check_io(name, args, span),
+        };
+        Ok(Some(ret))
+    }
+
+    /// Calls of the runtime [`io::FUNCTIONS`] are checked like the ones of the
+    /// runtime functions, their result is recorded like the one of a builtin.
+    fn check_io(
+        &mut self,
+        name: &str,
+        args: &[Expr],
+        span: Span,
+    ) -> Result<Option<Type>, Diagnostic> {
+        let Some(function) = io::FUNCTIONS.iter().find(|function| function.name == name) else {
+            return Ok(None);
+        };
+        if function.params.len() != args.len() {
+            return Err(Diagnostic::error(
+                span,
+                format!(
+                    "Function `{name}` takes {} arguments but {} were given",
+                    function.params.len(),
+                    args.len()
+                ),
+            ));
+        }
+        for (arg, param) in args.iter().zip(function.params) {
+            let param = self.types[*param].clone();
+            let ty = self.check_expr(arg, Some(&param))?;
+            expect(&param, &ty, arg.span())?;
+        }
+        let ret = function
+            .returns
+            .map_or(Type::Nil, |ret| self.types[ret].clone());
+        self.table.insert(span, ret.clone());
+        Ok(Some(ret))
+    }
+
+    fn check_spawn(
+        &mut self,
+        function: &Expr,
+        args: &[Expr],
+        span: Span,
+    ) -> Result<(), Diagnostic> {
+        let name = ident(function, "Expected the name of a function")?;
+        let ty = self.lookup(name);
+        let Some(Type::Function { params, .. }) = ty else {
+            return Err(Diagnostic::error(
+                function.span(),
+                format!("`{name}` is not a function"),
+            ));
+        };
+        if params.len() != args.len() {
+            return Err(Diagnostic::error(
+                span,
+                format!(
+                    "Function `{name}` takes {} arguments but {} were given",
+                    params.len(),
+                    args.len()
+                ),
+            ));
+        }
+        for (arg, param) in args.iter().zip(&params) {
+            let ty = self.check_expr(arg, Some(param))?;
+            expect(param, &ty, arg.span())?;
+            message_type(&ty, arg.span())?;
+        }
+        self.table.insert(span, Type::Pid);
+        Ok(())
+    }
+
+    /// Checks that `expr` is an array, records its type at `span` and returns
+    /// the type of its elements.
+    fn check_array(&mut self, expr: &Expr, span: Span) -> Result<Type, Diagnostic> {
+        let ty = self.check_expr(expr, None)?;
+        let Type::Array(elem_ty) = &ty else {
+            return Err(Diagnostic::error(
+                expr.span(),
+                format!("`{ty}` is not an array"),
+            ));
+        };
+        let elem_ty = (**elem_ty).clone();
+        self.table.insert(span, ty);
+        Ok(elem_ty)
+    }
+
+    fn check_loop(&mut self, body: &[Expr]) -> Result<Type, Diagnostic> {
+        self.loops += 1;
+        let ty = self.check_body(body, None);
+        self.loops -= 1;
+        ty.map(|_| Type::Nil)
+    }
+
+    fn lookup(&self, name: &str) -> Option<Type> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .cloned()
+    }
+
+    fn declare(&mut self, name: &str, ty: Type) {
+        if self.scopes.len() == 1 {
+            self.runtime.remove(name);
+        }
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_owned(), ty);
+        }
+    }
+}
+
+fn ident<'a>(expr: &'a Expr, message: &str) -> Result<&'a str, Diagnostic> {
+    match expr {
+        Expr::Ident(name, _) => Ok(name),
+        expr => Err(Diagnostic::error(expr.span(), message)),
+    }
+}
+
+fn expect(expected: &Type, found: &Type, span: Span) -> Result<(), Diagnostic> {
+    if expected != found {
+        return Err(Diagnostic::error(
+            span,
+            format!("Expected `{expected}`, found `{found}`"),
+        ));
+    }
+    Ok(())
+}
+
+fn integer(ty: &Type, span: Span) -> Result<(), Diagnostic> {
+    if !ty.is_integer() {
+        return Err(Diagnostic::error(
+            span,
+            format!("Expected an integer, found `{ty}`"),
+        ));
+    }
+    Ok(())
+}
+
+fn message_type(ty: &Type, span: Span) -> Result<(), Diagnostic> {
+    if !ty.is_message() {
+        return Err(Diagnostic::error(
+            span,
+            format!("`{ty}` values cannot be passed to another process"),
+        ));
+    }
+    Ok(())
+}
+
+/// The executable runs `main` without arguments and exits with its value.
+fn entry_point(ty: &Type, span: Span) -> Result<(), Diagnostic> {
+    // `check_function` reports a signature which is not a function type.
+    let Type::Function { params, ret } = ty else {
+        return Ok(());
+    };
+    if !params.is_empty() {
+        return Err(Diagnostic::error(span, "`main` takes no parameters"));
+    }
+    if !(ret.is_integer() || **ret == Type::Nil) {
+        return Err(Diagnostic::error(
+            span,
+            format!("`main` returns an integer or `nil`, found `{ret}`"),
+        ));
+    }
+    Ok(())
+}
+
+/// Number literals, negated or not, which take the type they are expected to have.
+fn is_literal(expr: &Expr) -> bool {
+    match expr {
+        Expr::Lit(..) | Expr::Float(..) => true,
+        Expr::Unary {
+            op: UnaryOp::Neg,
+            expr,
+            ..
+        } => is_literal(expr),
+        _ => false,
+    }
+}
+
+/// Conditions are `bool`s, integers are true when they are not `0`.
+fn condition(ty: &Type, span: Span) -> Result<(), Diagnostic> {
+    if *ty != Type::Bool && !ty.is_integer() {
+        return Err(Diagnostic::error(
+            span,
+            format!("Expected `bool` or an integer, found `{ty}`"),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        diagnostics::Span,
+        frontend::{
+            parser::parse,
+            typeck::{Type, check, check_module},
+        },
+    };
+
+    fn error(code: &str) -> (String, String) {
+        let exprs = parse(code).unwrap();
+        let diagnostic = check(&exprs).unwrap_err();
+        let spanned = code[diagnostic.span.start..diagnostic.span.end].to_owned();
+        (diagnostic.message, spanned)
+    }
+
+    #[test]
+    fn well_typed_program() {
+        let code = r#"
+            apply: f(x(i32) -> i32) v(i32) -> i32 { f { v } }
+            main: -> i64 {
+                inc: x(i32) -> i32 { 1 + x }
+                let a: i32 = apply { inc 41 }
+                let b: i64 = if a > 1 { add { 1 2 } } else { 0 }
+                stdprint { b }
+                b
+            }
+        "#;
+        assert!(check(&parse(code).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn exit_messages() {
+        let code =
+            "main: -> i64 { let e: exit = receive\n link { exit_pid { e } }\n exit_reason { e } }";
+        assert!(check(&parse(code).unwrap()).is_ok());
+        let code = "main: -> i64 { let p: pid = self {}\n exit_reason { p } }";
+        assert_eq!(
+            error(code),
+            ("Expected `exit`, found `pid`".into(), "p".into())
+        );
+    }
+
+    #[test]
+    fn timers() {
+        let code = "main: -> i64 { sleep { 5 }\n let m: i64 = receive after 10 { now_ms {} }\n m }";
+        assert!(check(&parse(code).unwrap()).is_ok());
+        let code = "main: -> i64 { let p: pid = receive after 10 { 0 }\n 1 }";
+        assert_eq!(
+            error(code),
+            (
+                "Expected `pid`, found `i64`".into(),
+                "receive after 10 { 0 }".into()
+            )
+        );
+        let code = "main: -> i64 { sleep { true }\n 1 }";
+        assert_eq!(
+            error(code),
+            ("Expected an integer, found `bool`".into(), "true".into())
+        );
+    }
+
+    #[test]
+    fn io_functions() {
+        let code = "main: -> i64 { let fd: i64 = connect { 80 }\n write { fd \"hi\" } }";
+        assert!(check(&parse(code).unwrap()).is_ok());
+        let code = "main: -> i64 { let fd: i64 = 1\n write { fd 2 } }";
+        assert_eq!(
+            error(code),
+            ("Expected `str`, found `i64`".into(), "2".into())
+        );
+        let code = "read: -> i64 { 1 }\nmain: -> i64 { read {} }";
+        assert!(check(&parse(code).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn argument_type_mismatch() {
+        let code = "id: x(i32) -> i32 { x }\nmain: -> i64 { let a: i64 = 1\n id { a } }";
+        assert_eq!(
+            error(code),
+            ("Expected `i32`, found `i64`".into(), "a".into())
+        );
+    }
+
+    #[test]
+    fn return_type_mismatch() {
+        let code = "main: -> i64 { stdprint { 1 } }";
+        assert_eq!(
+            error(code),
+            (
+                "Function `main` returns `i64` but its body evaluates to `nil`".into(),
+                "stdprint { 1 }".into()
+            )
+        );
+    }
+
+    #[test]
+    fn unknown_type() {
+        let code = "main: -> i64 { let a: Point = 1\n a }";
+        assert_eq!(error(code), ("Unknown type `Point`".into(), "Point".into()));
+    }
+
+    #[test]
+    fn calling_a_non_function() {
+        let code = "main: -> i64 { let a: i64 = 1\n a { 2 } }";
+        assert_eq!(
+            error(code),
+            ("`a` is not a function but `i64`".into(), "a".into())
+        );
+    }
+
+    #[test]
+    fn condition_must_be_bool_or_integer() {
+        let code = "main: -> i64 { let a: f64 = 1.0\n if a { 1 } else { 2 } }";
+        assert_eq!(
+            error(code),
+            (
+                "Expected `bool` or an integer, found `f64`".into(),
+                "a".into()
+            )
+        );
+    }
+
+    #[test]
+    fn negation_needs_signed_numbers() {
+        let code = "main: -> i64 { let a: u8 = 1\n let b: u8 = -a\n 0 }";
+        assert_eq!(
+            error(code),
+            ("Expected a signed number, found `u8`".into(), "a".into())
+        );
+        let code = "main: -> i64 { let a: i32 = 1\n let b: i32 = -1 + a\n 0 }";
+        assert!(check(&parse(code).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn main_takes_nothing_and_returns_an_integer() {
+        assert_eq!(
+            error("main: x(i64) -> i64 { x }"),
+            ("`main` takes no parameters".into(), "main".into())
+        );
+        assert_eq!(
+            error("main: -> f64 { 2.5 }"),
+            (
+                "`main` returns an integer or `nil`, found `f64`".into(),
+                "main".into()
+            )
+        );
+        for code in ["main: -> u8 { 1 }", "main: -> nil { }"] {
+            assert!(check(&parse(code).unwrap()).is_ok());
+        }
+        let module = parse("module m\nmain: x(f64) -> f64 { x }").unwrap();
+        assert!(check_module(&module, Some("m"), &[]).is_ok());
+    }
+
+    #[test]
+    fn runtime_functions_are_not_values() {
+        let code = r#"
+            apply: f(x(i64) -> nil) v(i64) -> nil { f { v } }
+            main: -> i64 {
+                apply { stdprint 1 }
+                0
+            }
+        "#;
+        assert_eq!(
+            error(code),
+            (
+                "Runtime function `stdprint` can only be called".into(),
+                "stdprint".into()
+            )
+        );
+    }
+
+    #[test]
+    fn struct_fields() {
+        let code = r#"
+            type Point { x: i64, y: i64 }
+            type Line { start: Point, end: Point }
+            main: -> i64 {
+                let a: Point = Point { x: 1, y: 2 }
+                let l: Line = Line { start: a, end: Point { y: 4, x: 3 } }
+                l.end.x + l.start.y
+            }
+        "#;
+        assert!(check(&parse(code).unwrap()).is_ok());
+
+        let code = "type Point { x: i64, y: i64 }\nmain: -> i64 { Point { x: 1 }.z }";
+        assert_eq!(
+            error(code),
+            (
+                "Missing field `y` of `Point`".into(),
+                "Point { x: 1 }".into()
+            )
+        );
+        let code = "type Point { x: i64 }\nmain: -> i64 { Point { x: 1 }.z }";
+        assert_eq!(
+            error(code),
+            ("Struct `Point` has no field `z`".into(), "z".into())
+        );
+    }
+
+    #[test]
+    fn match_exhaustiveness() {
+        let code = r#"
+            type List = Cons(i64, List) | Nil
+            sum: l(List) -> i64 {
+                match l {
+                    Cons(head, tail) => head + sum { tail },
+                    Nil => 0
+                }
+            }
+            main: -> i64 { sum { Cons(1, Cons(2, Nil)) } }
+        "#;
+        assert!(check(&parse(code).unwrap()).is_ok());
+
+        let code = "type Option = Some(i64) | None\nmain: -> i64 { match None { Some(x) => x } }";
+        assert_eq!(
+            error(code),
+            (
+                "Non-exhaustive match, `None` is not covered".into(),
+                "match None { Some(x) => x }".into()
+            )
+        );
+        let code =
+            "type Option = Some(i64) | None\nmain: -> i64 { match None { _ => 1, None => 0 } }";
+        assert_eq!(
+            error(code),
+            ("Unreachable pattern".into(), "None => 0".into())
+        );
+    }
+
+    #[test]
+    fn array_elements() {
+        let code = "main: -> i64 { let xs: [u8] = [1 2]\n push { xs 3 }\n len { xs } }";
+        assert!(check(&parse(code).unwrap()).is_ok());
+
+        let code = "main: -> i64 { let xs: [i64] = [1 true]\n xs[0] }";
+        assert_eq!(
+            error(code),
+            ("Expected `i64`, found `bool`".into(), "true".into())
+        );
+        let code = "main: -> i64 { let xs: [i64] = [1]\n xs[1.0] }";
+        assert_eq!(
+            error(code),
+            ("Expected an integer, found `f64`".into(), "1.0".into())
+        );
+        let code = "main: -> i64 { len { [] } }";
+        assert_eq!(
+            error(code),
+            (
+                "The type of an empty array must be declared".into(),
+                "[]".into()
+            )
+        );
+        let code = "main: -> i64 { let a: i64 = 1\n a[0] }";
+        assert_eq!(error(code), ("`i64` is not an array".into(), "a".into()));
+    }
+
+    #[test]
+    fn operators_and_their_values_are_kept_apart() {
+        let code = "main: -> i64 { let b: bool = 1 < 2\n 0 }";
+        let types = check(&parse(code).unwrap()).unwrap();
+        let start = code.find("1 < 2").unwrap();
+        let span = Span::new(start, start + 5);
+        assert_eq!(types.get(span), Some(&Type::I64));
+        assert_eq!(types.value(span), Some(&Type::Bool));
+    }
+
+    #[test]
+    fn modules_export_qualified_functions() {
+        let math = parse("module math\nsquare: x(i64) -> i64 { x * x }").unwrap();
+        let (_, exports) = check_module(&math, Some("math"), &[]).unwrap();
+        let square = Type::Function {
+            params: vec![Type::I64],
+            ret: Box::new(Type::I64),
+        };
+        assert_eq!(exports, vec![("math::square".to_owned(), square)]);
+
+        let app = parse("import math\nmain: -> i64 { math::square { 3 } }").unwrap();
+        assert!(check_module(&app, None, &exports).is_ok());
+        let diagnostic = check(&app).unwrap_err();
+        assert_eq!(diagnostic.message, "Function `math::square` is not defined");
+
+        let (message, spanned) = error("main: -> i64 { import math\n1 }");
+        assert_eq!(
+            message,
+            "Modules can only be declared and imported at the top level"
+        );
+        assert_eq!(spanned, "import math");
+        let (message, _) = error("math::square: x(i64) -> i64 { x }");
+        assert_eq!(
+            message,
+            "Only functions of imported modules are named with `::`"
+        );
+    }
+}
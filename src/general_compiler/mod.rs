@@ -1,124 +1,49 @@
-use crate::frontend::parser::ast::expr::Expr;
-use crate::general_compiler::function_translator::{FunctionTranslator, translate};
-use crate::general_compiler::runtime::init_runtime;
-use crate::general_compiler::runtime::virtual_process::create_process;
-use anyhow::*;
 use cranelift::codegen::ir::BlockArg;
 use cranelift::module::Linkage;
-use cranelift::prelude::{IntCC, MemFlags, TrapCode};
+use cranelift::prelude::{IntCC, types};
 use cranelift::{
-    codegen::Context,
-    module::{DataDescription, Module},
-    prelude::{AbiParam, Block, FunctionBuilder, FunctionBuilderContext, InstBuilder, Value},
+    module::Module,
+    prelude::{AbiParam, Block, FunctionBuilder, InstBuilder, Value},
 };
-use std::collections::HashMap;
-use whirlpool::Digest;
-
-mod function_translator;
-mod runtime;
-mod trap;
-mod type_def;
-const REDUCTIONS_LIMIT: i64 = 2;
-
-pub trait GeneralCompiler<T: Module> {
-    fn from_general_compiler(
-        builder_ctx: FunctionBuilderContext,
-        ctx: Context,
-        data_description: DataDescription,
-        module: T,
-    ) -> Self
-    where
-        Self: Sized;
-    fn unwrap(self) -> (FunctionBuilderContext, Context, DataDescription, T);
-    fn translate(self, exprs: Vec<Expr>) -> Result<Self>
-    where
-        Self: Sized,
-    {
-        let (mut builder_ctx, mut ctx, data_description, mut module) = self.unwrap();
-
-        let builder = FunctionBuilder::new(&mut ctx.func, &mut builder_ctx);
-        let target_type = module.target_config().pointer_type();
-
-        translate(
-            FunctionTranslator::new(
-                target_type,
-                HashMap::new(),
-                &mut module,
-                &mut ctx,
-                &mut builder_ctx,
-            ),
-            exprs[0].clone(),
-        )?;
-
-        let mut builder = FunctionBuilder::new(&mut ctx.func, &mut builder_ctx);
-        let target_type = module.target_config().pointer_type();
-
-        builder
-            .func
-            .signature
-            .returns
-            .push(AbiParam::new(target_type));
-
-        let entry_block = builder.create_block();
-        builder.switch_to_block(entry_block);
-        builder.seal_block(entry_block);
-
-        let after_build_runtime_block = builder.create_block();
-        let runtime = init_runtime(&mut module, &mut builder, after_build_runtime_block);
-        builder.switch_to_block(after_build_runtime_block);
-        builder.seal_block(after_build_runtime_block);
-
-        let processes_ptr = builder.use_var(runtime.processes_ptr);
-
-        let new_process = create_process(&mut module, &mut builder);
-        let ptr = builder.use_var(new_process);
-        builder.ins().store(MemFlags::new(), ptr, processes_ptr, 0);
-
-        let process_ptr = builder
-            .ins()
-            .load(target_type, MemFlags::new(), processes_ptr, 0);
-        let process_ctx_ptr = builder
-            .ins()
-            .load(target_type, MemFlags::new(), process_ptr, 0);
-        let func_addr = builder
-            .ins()
-            .load(target_type, MemFlags::new(), process_ctx_ptr, 24);
-
-        let mut sig = module.make_signature();
-        sig.params.push(AbiParam::new(target_type));
-        sig.returns.push(AbiParam::new(target_type));
-        let sig_ref = builder.import_signature(sig);
-        let v = builder.ins().iconst(target_type, 20);
-
-        builder.ins().call_indirect(sig_ref, func_addr, &[v]);
-
-        call_free(&mut module, &mut builder, process_ctx_ptr);
-        call_free(&mut module, &mut builder, process_ptr);
-        call_free(&mut module, &mut builder, processes_ptr);
-
-        let zero = builder.ins().iconst(target_type, 0);
-        builder.ins().return_(&[zero]);
-
-        let id = module.declare_function("main", Linkage::Export, &mut builder.func.signature)?;
-        builder.finalize();
-        module.define_function(id, &mut ctx)?;
-        module.clear_context(&mut ctx);
-
-        Ok(Self::from_general_compiler(
-            builder_ctx,
-            ctx,
-            data_description,
-            module,
-        ))
+
+use crate::{backend::fault, general_compiler::trap::CompilerTrapCode};
+
+pub mod trap;
+pub mod type_def;
+/// Resumable blocks a process runs before the scheduler switches to the next
+/// one, unless the compiler is created [`with_reductions`](crate::backend::Compiler::with_reductions).
+pub const REDUCTIONS_LIMIT: i64 = 2000;
+
+/// A failed allocation fails the running process, whose runtime buffer is
+/// `runtime_ptr`. Without a process to fail, the function returns null instead.
+pub fn allocation_failed(
+    builder: &mut FunctionBuilder,
+    target_type: types::Type,
+    runtime_ptr: Option<Value>,
+) {
+    match runtime_ptr {
+        Some(runtime_ptr) => fault(
+            builder,
+            target_type,
+            runtime_ptr,
+            CompilerTrapCode::OutOfMemory,
+        ),
+        None => {
+            let null = builder.ins().iconst(target_type, 0);
+            builder.ins().return_(&[null]);
+        }
     }
 }
 
+/// Allocates `buffer_size` bytes and jumps to `block_after_call` with the
+/// pointer after `block_args`, see [`allocation_failed`] for `runtime_ptr`.
 pub fn call_malloc(
     module: &mut dyn Module,
     builder: &mut FunctionBuilder,
     buffer_size: Value,
     block_after_call: Block,
     block_args: &[BlockArg],
+    runtime_ptr: Option<Value>,
 ) {
     let ty = module.target_config().pointer_type();
     let mut malloc_sig = module.make_signature();
@@ -131,7 +56,7 @@ pub fn call_malloc(
     let local_callee_malloc = module.declare_func_in_func(callee_malloc, builder.func);
 
     let call = builder.ins().call(local_callee_malloc, &[buffer_size]);
-    let ptr: Value = *builder.inst_results(call).get(0).unwrap();
+    let ptr: Value = *builder.inst_results(call).first().unwrap();
 
     let cond_block = builder.create_block();
     for _ in block_args {
@@ -150,7 +75,7 @@ pub fn call_malloc(
 
     let len = builder.block_params(cond_block).len();
     let ptr = *builder.block_params(cond_block).last().unwrap();
-    let block_args: Vec<BlockArg> = (&builder.block_params(cond_block)[..len - 1])
+    let block_args: Vec<BlockArg> = builder.block_params(cond_block)[..len - 1]
         .iter()
         .map(|x| BlockArg::Value(*x))
         .collect();
@@ -166,7 +91,7 @@ pub fn call_malloc(
 
     builder.switch_to_block(trap_block);
     builder.seal_block(trap_block);
-    builder.ins().trap(TrapCode::HEAP_OUT_OF_BOUNDS);
+    allocation_failed(builder, ty, runtime_ptr);
 }
 
 pub fn call_free(module: &mut dyn Module, builder: &mut FunctionBuilder, ptr: Value) -> Value {
@@ -181,19 +106,5 @@ pub fn call_free(module: &mut dyn Module, builder: &mut FunctionBuilder, ptr: Va
     let local_callee_free = module.declare_func_in_func(callee_free, builder.func);
 
     let call = builder.ins().call(local_callee_free, &[ptr]);
-    *builder.inst_results(call).get(0).unwrap()
-}
-
-pub fn call_stdprint(module: &mut dyn Module, builder: &mut FunctionBuilder, value: Value) {
-    let ty = module.target_config().pointer_type();
-    let mut print_sig = module.make_signature();
-    print_sig.params.push(AbiParam::new(ty));
-    print_sig.returns.push(AbiParam::new(ty));
-
-    let callee_print = module
-        .declare_function("stdprint", Linkage::Import, &print_sig)
-        .unwrap();
-    let local_callee_print = module.declare_func_in_func(callee_print, builder.func);
-
-    let call = builder.ins().call(local_callee_print, &[value]);
+    *builder.inst_results(call).first().unwrap()
 }
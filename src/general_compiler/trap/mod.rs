@@ -1,13 +1,26 @@
-use cranelift::prelude::TrapCode;
+use unicorn_runtime::scheduler::{
+    EXIT_DIVISION_BY_ZERO, EXIT_END_OF_BLOCKS, EXIT_INDEX_OUT_OF_BOUNDS, EXIT_OUT_OF_MEMORY,
+    EXIT_OVERFLOW,
+};
 
+/// Faults of a process, which stop it without stopping the other processes.
 pub enum CompilerTrapCode {
     EndOfBlocks,
+    DivisionByZero,
+    IndexOutOfBounds,
+    Overflow,
+    OutOfMemory,
 }
 
-impl From<CompilerTrapCode> for TrapCode {
-    fn from(value: CompilerTrapCode) -> Self {
-        match value {
-            CompilerTrapCode::EndOfBlocks => TrapCode::user(25).unwrap(),
+impl CompilerTrapCode {
+    /// Reason the process exits with, which its links and monitors get.
+    pub fn reason(self) -> i64 {
+        match self {
+            CompilerTrapCode::EndOfBlocks => EXIT_END_OF_BLOCKS,
+            CompilerTrapCode::DivisionByZero => EXIT_DIVISION_BY_ZERO,
+            CompilerTrapCode::IndexOutOfBounds => EXIT_INDEX_OUT_OF_BOUNDS,
+            CompilerTrapCode::Overflow => EXIT_OVERFLOW,
+            CompilerTrapCode::OutOfMemory => EXIT_OUT_OF_MEMORY,
         }
     }
 }
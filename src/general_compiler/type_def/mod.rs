@@ -1,5 +1,6 @@
 use cranelift::prelude::types;
 
+/// C-like layout of a struct type.
 #[derive(Debug)]
 pub struct TypeDef {
     name: String,
@@ -19,12 +20,35 @@ impl TypeDef {
         compute_layout(&mut ty);
         ty
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn align(&self) -> usize {
+        self.align
+    }
 }
 
 #[derive(Debug)]
 pub struct Field {
     name: String,
-    ty: types::Type,
+    /// `None` for a nested struct, which is stored inline.
+    ty: Option<types::Type>,
+    size: usize,
+    align: usize,
     offset: usize,
 }
 
@@ -32,10 +56,42 @@ impl Field {
     pub fn new(name: &str, ty: types::Type) -> Self {
         Self {
             name: name.to_string(),
-            ty,
+            ty: Some(ty),
+            size: ty.bytes() as usize,
+            align: ty.bytes() as usize,
+            offset: 0,
+        }
+    }
+
+    pub fn nested(name: &str, ty: &TypeDef) -> Self {
+        Self {
+            name: name.to_string(),
+            ty: None,
+            size: ty.size(),
+            align: ty.align(),
             offset: 0,
         }
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn ty(&self) -> Option<types::Type> {
+        self.ty
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn align(&self) -> usize {
+        self.align
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
 }
 
 fn compute_layout(ty: &mut TypeDef) {
@@ -43,10 +99,10 @@ fn compute_layout(ty: &mut TypeDef) {
     let mut struct_align = 1;
 
     for field in &mut ty.fields {
-        offset = align_up(offset, field.ty.bytes() as usize);
+        offset = align_up(offset, field.align);
         field.offset = offset;
-        offset += field.ty.bytes() as usize;
-        struct_align = struct_align.max(field.ty.bytes() as usize);
+        offset += field.size;
+        struct_align = struct_align.max(field.align);
     }
 
     ty.size = align_up(offset, struct_align);
@@ -56,3 +112,31 @@ fn compute_layout(ty: &mut TypeDef) {
 fn align_up(offset: usize, align: usize) -> usize {
     (offset + align - 1) & !(align - 1)
 }
+
+#[cfg(test)]
+mod test {
+    use cranelift::prelude::types;
+
+    use crate::general_compiler::type_def::{Field, TypeDef};
+
+    #[test]
+    fn nested_layout() {
+        let point = TypeDef::new(
+            "Point",
+            vec![Field::new("x", types::I32), Field::new("y", types::I8)],
+        );
+        assert_eq!((point.size(), point.align()), (8, 4));
+
+        let line = TypeDef::new(
+            "Line",
+            vec![
+                Field::new("tag", types::I8),
+                Field::nested("start", &point),
+                Field::new("len", types::F64),
+            ],
+        );
+        let offsets: Vec<_> = line.fields().iter().map(Field::offset).collect();
+        assert_eq!(offsets, [0, 4, 16]);
+        assert_eq!((line.size(), line.align()), (24, 8));
+    }
+}
@@ -1,7 +1,7 @@
 extern crate core;
 
-pub mod aot;
 pub mod backend;
-mod frontend;
+pub mod diagnostics;
+pub mod frontend;
 pub mod general_compiler;
 pub mod middleware;
@@ -1,107 +1,1038 @@
-use crate::frontend::parser::ast::expr::Expr;
+use std::collections::{HashMap, HashSet};
 
-#[derive(Debug)]
+use crate::{
+    diagnostics::{Diagnostic, FileDiagnostic, Span},
+    frontend::{
+        module::Module,
+        parser::ast::expr::{BinaryOp, Expr, MatchArm, UnaryOp},
+        typeck::{BUILTINS, Type, TypeTable, check_module},
+    },
+};
+use unicorn_runtime::{PRINTERS, scheduler::io};
+
+#[derive(Debug, Default)]
 pub struct Expressions(pub Vec<Expression>);
 
 #[derive(Debug)]
 pub enum Expression {
-    Lit(i64),
-    Ident(String),
+    /// Scalar literal as the 64-bit pattern of its value: integers sign-extended
+    /// for signed types and zero-extended for unsigned ones, the IEEE bits of
+    /// floats, `0`/`1` for booleans and the code point of a `char`.
+    Lit(i64, Span),
+    /// String literal, evaluates to the address of its read-only NUL-terminated bytes.
+    Str(String, Span),
+    Ident(String, Span),
     Call {
         ident: Box<Expression>,
         args: Expressions,
+        span: Span,
     },
     ReturnCall {
         ident: Box<Expression>,
         args: Expressions,
+        span: Span,
     },
     FFICall {
         ident: Box<Expression>,
         args: Expressions,
+        span: Span,
     },
-    BeforeCall(usize),
-    Assign((Box<Expression>, Box<Expression>), Box<Expression>),
+    BeforeCall(usize, Span),
+    Assign((Box<Expression>, Box<Expression>), Box<Expression>, Span),
     Function {
         name: Box<Expression>,
         function_ty: Box<Expression>,
         body: Expressions,
+        span: Span,
     },
     FunctionType {
         params: Vec<(Expression, Expression)>,
         ret_ty: Box<Expression>,
+        span: Span,
+    },
+    Block(Expressions, Span),
+    GlobalDataAddr(Box<Expression>, Span),
+    /// Operator over identifiers and literals only, see `Lowering::operand`.
+    /// `ty` is the type of the operands.
+    Binary {
+        op: BinaryOp,
+        ty: Type,
+        lhs: Box<Expression>,
+        rhs: Box<Expression>,
+        span: Span,
+    },
+    Unary {
+        op: UnaryOp,
+        ty: Type,
+        expr: Box<Expression>,
+        span: Span,
+    },
+    /// Both branches are non-empty, a missing one evaluates to `0`.
+    If {
+        cond: Box<Expression>,
+        then: Expressions,
+        otherwise: Expressions,
+        span: Span,
+    },
+    /// Runs its body until a [`Expression::Break`] and evaluates to `0`,
+    /// `while` loops are lowered into it.
+    Loop(Expressions, Span),
+    Break(Span),
+    Set(Box<Expression>, Box<Expression>, Span),
+    /// Struct literal of type `ty`, with the field values in the order of its definition.
+    Struct {
+        ty: Type,
+        fields: Expressions,
+        span: Span,
+    },
+    /// Field of a struct of type `ty`, `expr` is an identifier, see `Lowering::operand`.
+    Field {
+        ty: Type,
+        expr: Box<Expression>,
+        field: String,
+        span: Span,
+    },
+    /// Variant number `variant` of the enum `ty` with its values.
+    Variant {
+        ty: Type,
+        variant: usize,
+        fields: Expressions,
+        span: Span,
+    },
+    /// Whether the enum in the identifier `expr` is variant number `variant`,
+    /// `match` is lowered into these tag comparisons.
+    IsVariant {
+        expr: Box<Expression>,
+        variant: usize,
+        span: Span,
+    },
+    /// Value number `index` of variant number `variant` of the enum `ty` in the identifier `expr`.
+    Payload {
+        ty: Type,
+        expr: Box<Expression>,
+        variant: usize,
+        index: usize,
+        span: Span,
+    },
+    /// Array literal, its elements are evaluated like call arguments.
+    Array(Expressions, Span),
+    /// Element of the array in the identifier `expr`, `index` is an identifier or a literal.
+    Index {
+        expr: Box<Expression>,
+        index: Box<Expression>,
+        span: Span,
+    },
+    /// `len` of the array in an identifier.
+    ArrayLen(Box<Expression>, Span),
+    /// `push` of an identifier or a literal to the array in an identifier.
+    ArrayPush {
+        array: Box<Expression>,
+        value: Box<Expression>,
+        span: Span,
+    },
+    /// New process running the function named by the identifier `function`
+    /// with the arguments, evaluates to its id.
+    Spawn {
+        function: Box<Expression>,
+        args: Expressions,
+        span: Span,
+    },
+    /// Adds the identifier or literal `message` to the mailbox of the process in
+    /// the identifier `pid`.
+    Send {
+        pid: Box<Expression>,
+        message: Box<Expression>,
+        span: Span,
+    },
+    SelfPid(Span),
+    /// Takes the oldest message of the mailbox, the process yields to the
+    /// scheduler while it is empty.
+    Receive(Span),
+    /// `link` of the running process with the process in an identifier.
+    Link(Box<Expression>, Span),
+    /// `monitor` of the process in an identifier by the running process.
+    Monitor(Box<Expression>, Span),
+    /// New process spawned like the one in an identifier, evaluates to its id.
+    Restart(Box<Expression>, Span),
+    /// Id of the process an exit message in an identifier tells about.
+    ExitPid(Box<Expression>, Span),
+    /// Reason of an exit message in an identifier.
+    ExitReason(Box<Expression>, Span),
+    /// Parks the process for the milliseconds in an identifier or a literal.
+    Sleep(Box<Expression>, Span),
+    /// Milliseconds on the clock of the scheduler.
+    NowMs(Span),
+    /// Whether a message arrives within the milliseconds in an identifier or a
+    /// literal, the process yields to the scheduler until one does or the time
+    /// is up. A `receive after` takes the message only then.
+    AwaitMessage(Box<Expression>, Span),
+    /// Call of the runtime I/O function `function` with identifiers and
+    /// literals, the process yields to the scheduler until its I/O is ready.
+    Io {
+        function: String,
+        args: Expressions,
+        span: Span,
     },
-    Block(Expressions),
 }
 
-impl From<Vec<Expr>> for Expressions {
-    fn from(value: Vec<Expr>) -> Self {
+impl Expression {
+    pub fn span(&self) -> Span {
+        match self {
+            Expression::Lit(_, span)
+            | Expression::Str(_, span)
+            | Expression::Ident(_, span)
+            | Expression::Call { span, .. }
+            | Expression::ReturnCall { span, .. }
+            | Expression::FFICall { span, .. }
+            | Expression::BeforeCall(_, span)
+            | Expression::Assign(_, _, span)
+            | Expression::Function { span, .. }
+            | Expression::FunctionType { span, .. }
+            | Expression::Block(_, span)
+            | Expression::GlobalDataAddr(_, span)
+            | Expression::Binary { span, .. }
+            | Expression::Unary { span, .. }
+            | Expression::If { span, .. }
+            | Expression::Loop(_, span)
+            | Expression::Break(span)
+            | Expression::Set(_, _, span)
+            | Expression::Struct { span, .. }
+            | Expression::Field { span, .. }
+            | Expression::Variant { span, .. }
+            | Expression::IsVariant { span, .. }
+            | Expression::Payload { span, .. }
+            | Expression::Array(_, span)
+            | Expression::Index { span, .. }
+            | Expression::ArrayLen(_, span)
+            | Expression::ArrayPush { span, .. }
+            | Expression::Spawn { span, .. }
+            | Expression::Send { span, .. }
+            | Expression::SelfPid(span)
+            | Expression::Receive(span)
+            | Expression::Link(_, span)
+            | Expression::Monitor(_, span)
+            | Expression::Restart(_, span)
+            | Expression::ExitPid(_, span)
+            | Expression::ExitReason(_, span)
+            | Expression::Sleep(_, span)
+            | Expression::NowMs(span)
+            | Expression::AwaitMessage(_, span)
+            | Expression::Io { span, .. } => *span,
+        }
+    }
+}
+
+impl Expressions {
+    /// Collects every name these expressions refer to, including inside nested functions.
+    pub fn idents(&self, names: &mut HashSet<String>) {
+        for expression in &self.0 {
+            expression.idents(names);
+        }
+    }
+}
+
+impl Expression {
+    /// Collects every name this expression refers to, including inside nested functions.
+    pub fn idents(&self, names: &mut HashSet<String>) {
+        match self {
+            Expression::Ident(name, _) => {
+                names.insert(name.clone());
+            }
+            Expression::Call { ident, args, .. }
+            | Expression::ReturnCall { ident, args, .. }
+            | Expression::FFICall { ident, args, .. } => {
+                ident.idents(names);
+                args.idents(names);
+            }
+            Expression::Assign(_, expr, _) | Expression::GlobalDataAddr(expr, _) => {
+                expr.idents(names)
+            }
+            Expression::Set(name, expr, _) => {
+                name.idents(names);
+                expr.idents(names);
+            }
+            Expression::Function { body, .. }
+            | Expression::Block(body, _)
+            | Expression::Loop(body, _)
+            | Expression::Struct { fields: body, .. }
+            | Expression::Variant { fields: body, .. }
+            | Expression::Array(body, _)
+            | Expression::Io { args: body, .. } => body.idents(names),
+            Expression::Spawn { function, args, .. } => {
+                function.idents(names);
+                args.idents(names);
+            }
+            Expression::Field { expr, .. }
+            | Expression::IsVariant { expr, .. }
+            | Expression::Payload { expr, .. }
+            | Expression::ArrayLen(expr, _)
+            | Expression::Link(expr, _)
+            | Expression::Monitor(expr, _)
+            | Expression::Restart(expr, _)
+            | Expression::ExitPid(expr, _)
+            | Expression::ExitReason(expr, _)
+            | Expression::Sleep(expr, _)
+            | Expression::AwaitMessage(expr, _) => expr.idents(names),
+            Expression::Binary { lhs, rhs, .. }
+            | Expression::Index {
+                expr: lhs,
+                index: rhs,
+                ..
+            }
+            | Expression::ArrayPush {
+                array: lhs,
+                value: rhs,
+                ..
+            }
+            | Expression::Send {
+                pid: lhs,
+                message: rhs,
+                ..
+            } => {
+                lhs.idents(names);
+                rhs.idents(names);
+            }
+            Expression::Unary { expr, .. } => expr.idents(names),
+            Expression::If {
+                cond,
+                then,
+                otherwise,
+                ..
+            } => {
+                cond.idents(names);
+                then.idents(names);
+                otherwise.idents(names);
+            }
+            Expression::Lit(..)
+            | Expression::Str(..)
+            | Expression::SelfPid(_)
+            | Expression::Receive(_)
+            | Expression::NowMs(_)
+            | Expression::BeforeCall(..)
+            | Expression::FunctionType { .. }
+            | Expression::Break(_) => {}
+        }
+    }
+}
+
+impl Expressions {
+    /// Lowers checked top-level expressions, `types` comes from [`check`](crate::frontend::typeck::check).
+    pub fn lower(exprs: Vec<Expr>, types: &TypeTable) -> Result<Self, Diagnostic> {
+        // Type definitions are only read by the checker, which resolved them into
+        // `types`, and modules by the resolver.
+        let exprs = exprs
+            .into_iter()
+            .filter(|expr| {
+                !matches!(
+                    expr,
+                    Expr::TypeDef { .. }
+                        | Expr::EnumDef { .. }
+                        | Expr::Module(..)
+                        | Expr::Import(..)
+                )
+            })
+            .collect();
+        Lowering { types }.body(exprs)
+    }
+}
+
+/// Lowered file of a program, which the backend compiles on its own.
+#[derive(Debug)]
+pub struct LoweredModule {
+    /// Functions are qualified with the name of a module, `None` for the program.
+    pub name: Option<String>,
+    pub expressions: Expressions,
+    /// Qualified names and arities of the functions of the imported modules.
+    pub imports: Vec<(String, usize)>,
+}
+
+impl LoweredModule {
+    /// Checks and lowers `modules`, ordered as by
+    /// [`Resolver::resolve`](crate::frontend::module::Resolver::resolve): a
+    /// module can call the functions of the modules it imports.
+    pub fn lower_all(modules: Vec<Module>) -> Result<Vec<Self>, FileDiagnostic> {
+        let mut exports: HashMap<String, Vec<(String, Type)>> = HashMap::new();
+        let mut lowered = vec![];
+        for module in modules {
+            let imports = module
+                .imports
+                .iter()
+                .flat_map(|(import, _)| exports[import].iter().cloned())
+                .collect::<Vec<_>>();
+            let error = |diagnostic| FileDiagnostic::new(&module.path, &module.source, diagnostic);
+            let (types, functions) =
+                check_module(&module.exprs, module.name.as_deref(), &imports).map_err(error)?;
+            let expressions = Expressions::lower(module.exprs, &types).map_err(error)?;
+
+            if let Some(name) = &module.name {
+                exports.insert(name.clone(), functions);
+            }
+            lowered.push(Self {
+                name: module.name,
+                expressions,
+                imports: imports
+                    .into_iter()
+                    .map(|(name, ty)| match ty {
+                        Type::Function { params, .. } => (name, params.len()),
+                        _ => (name, 0),
+                    })
+                    .collect(),
+            });
+        }
+        Ok(lowered)
+    }
+}
+
+/// Frontend to middleware lowering, reading the types the checker inferred.
+struct Lowering<'a> {
+    types: &'a TypeTable,
+}
+
+impl Lowering<'_> {
+    /// Lowers a function body, whose last call is a tail call.
+    fn body(&self, exprs: Vec<Expr>) -> Result<Expressions, Diagnostic> {
         let mut expressions = vec![];
-        let exprs_len = value.len();
-        for (i, expr) in value.into_iter().enumerate() {
+        let exprs_len = exprs.len();
+        for (i, expr) in exprs.into_iter().enumerate() {
             match expr {
-                Expr::Call { ident, args } => {
-                    let ident = Box::new(Expression::from(*ident));
-                    let args = Expressions::from(args);
+                Expr::Call { ident, args, span } if !self.is_builtin(&ident, span) => {
+                    let ident = Box::new(self.callee(*ident, span)?);
+                    let args = self.arguments(args)?;
                     expressions.append(&mut vec![
-                        Expression::BeforeCall(args.0.len()),
+                        Expression::BeforeCall(args.0.len(), span),
                         if i == exprs_len - 1 {
-                            Expression::ReturnCall { ident, args }
+                            Expression::ReturnCall { ident, args, span }
                         } else {
-                            Expression::Call { ident, args }
+                            Expression::Call { ident, args, span }
                         },
                     ])
                 }
-                expr => expressions.push(Expression::from(expr)),
+                Expr::If {
+                    cond,
+                    then,
+                    otherwise,
+                    span,
+                } if i == exprs_len - 1 => expressions.push(Expression::If {
+                    cond: Box::new(self.expr(*cond)?),
+                    then: self.branch(then, span, true)?,
+                    otherwise: self.branch(otherwise, span, true)?,
+                    span,
+                }),
+                Expr::Match { expr, arms, span } if i == exprs_len - 1 => {
+                    expressions.push(self.match_(*expr, arms, span, true)?)
+                }
+                expr => expressions.push(self.expr(expr)?),
             }
         }
-        Expressions(expressions)
+        Ok(Expressions(expressions))
     }
-}
 
-impl From<Expr> for Expression {
-    fn from(value: Expr) -> Self {
-        match value {
-            Expr::Lit(lit) => Expression::Lit(lit.parse().unwrap()),
-            Expr::Ident(ident) => Expression::Ident(ident),
-            Expr::FunctionType { params, ret_ty } => {
+    fn expr(&self, expr: Expr) -> Result<Expression, Diagnostic> {
+        Ok(match expr {
+            Expr::Lit(lit, span) => self.literal(&lit, span)?,
+            Expr::Float(lit, span) => self.float(&lit, span)?,
+            Expr::Bool(value, span) => Expression::Lit(value as i64, span),
+            Expr::Char(value, span) => Expression::Lit(value as i64, span),
+            Expr::Str(value, span) => Expression::Str(value, span),
+            Expr::Ident(ident, span) => match self.types.get(span) {
+                // The checker only records the type of an identifier naming a variant.
+                Some(Type::Enum { .. }) => self.variant(&ident, vec![], span)?,
+                _ => Expression::Ident(ident, span),
+            },
+            Expr::FunctionType {
+                params,
+                ret_ty,
+                span,
+            } => {
                 let params = params
                     .into_iter()
-                    .map(|(ident, ty)| (Expression::from(ident), Expression::from(ty)))
-                    .collect::<Vec<_>>();
+                    .map(|(ident, ty)| Ok((self.expr(ident)?, self.expr(ty)?)))
+                    .collect::<Result<Vec<_>, Diagnostic>>()?;
 
-                let ret_ty = Box::new(Expression::from(*ret_ty));
-                Expression::FunctionType { params, ret_ty }
+                let ret_ty = Box::new(self.expr(*ret_ty)?);
+                Expression::FunctionType {
+                    params,
+                    ret_ty,
+                    span,
+                }
             }
             Expr::Function {
                 name,
                 function_ty,
                 body,
+                span,
             } => {
-                let name = Box::new(Expression::from(*name));
-                let function_ty = Box::new(Expression::from(*function_ty));
-                let body = Expressions::from(body);
+                let name = Box::new(self.expr(*name)?);
+                let function_ty = Box::new(self.expr(*function_ty)?);
+                let body = self.body(body)?;
                 Expression::Function {
                     name,
                     function_ty,
                     body,
+                    span,
                 }
             }
-            Expr::Assign((ident, ty), expr) => {
-                let ident = Box::new(Expression::from(*ident));
-                let ty = Box::new(Expression::from(*ty));
-                let expr = Box::new(Expression::from(*expr));
-                Expression::Assign((ident, ty), expr)
+            Expr::Assign((ident, ty), expr, span) => {
+                let ident = Box::new(self.expr(*ident)?);
+                let ty = Box::new(self.expr(*ty)?);
+                let expr = Box::new(self.expr(*expr)?);
+                Expression::Assign((ident, ty), expr, span)
+            }
+            Expr::Call { ident, args, span } if self.is_builtin(&ident, span) => {
+                self.builtin(*ident, args, span)?
             }
-            Expr::Call { ident, args } => {
-                let ident = Box::new(Expression::from(*ident));
-                let args = Expressions::from(args);
-                Expression::Block(Expressions(vec![
-                    Expression::BeforeCall(args.0.len()),
-                    Expression::Call { ident, args },
-                ]))
+            Expr::Call { ident, args, span } => {
+                let ident = Box::new(self.callee(*ident, span)?);
+                let args = self.arguments(args)?;
+                Expression::Block(
+                    Expressions(vec![
+                        Expression::BeforeCall(args.0.len(), span),
+                        Expression::Call { ident, args, span },
+                    ]),
+                    span,
+                )
             }
+            Expr::GlobalDataAddr(ident, span) => {
+                Expression::GlobalDataAddr(Box::new(self.expr(*ident)?), span)
+            }
+            // `a && b` is `if a { b } else { false }` and `a || b` is
+            // `if a { true } else { b }`, so that `b` only runs when it decides.
+            Expr::Binary {
+                op: op @ (BinaryOp::And | BinaryOp::Or),
+                lhs,
+                rhs,
+                span,
+            } => {
+                let rhs = self.truth(*rhs)?;
+                let decided = Expressions(vec![Expression::Lit((op == BinaryOp::Or) as i64, span)]);
+                let (then, otherwise) = match op {
+                    BinaryOp::And => (rhs, decided),
+                    _ => (decided, rhs),
+                };
+                Expression::If {
+                    cond: Box::new(self.expr(*lhs)?),
+                    then,
+                    otherwise,
+                    span,
+                }
+            }
+            Expr::Binary { op, lhs, rhs, span } => {
+                let ty = self.type_of(span);
+                let mut spills = vec![];
+                let lhs = Box::new(self.operand(*lhs, &mut spills)?);
+                let rhs = Box::new(self.operand(*rhs, &mut spills)?);
+                with_spills(
+                    spills,
+                    Expression::Binary {
+                        op,
+                        ty,
+                        lhs,
+                        rhs,
+                        span,
+                    },
+                )
+            }
+            Expr::Unary { op, expr, span } => {
+                let ty = self.type_of(span);
+                let mut spills = vec![];
+                let expr = Box::new(self.operand(*expr, &mut spills)?);
+                with_spills(spills, Expression::Unary { op, ty, expr, span })
+            }
+            Expr::If {
+                cond,
+                then,
+                otherwise,
+                span,
+            } => Expression::If {
+                cond: Box::new(self.expr(*cond)?),
+                then: self.branch(then, span, false)?,
+                otherwise: self.branch(otherwise, span, false)?,
+                span,
+            },
+            Expr::While { cond, body, span } => Expression::Loop(
+                Expressions(vec![Expression::If {
+                    cond: Box::new(self.expr(*cond)?),
+                    then: self.branch(body, span, false)?,
+                    otherwise: Expressions(vec![Expression::Break(span)]),
+                    span,
+                }]),
+                span,
+            ),
+            Expr::Loop(body, span) => Expression::Loop(self.branch(body, span, false)?, span),
+            Expr::Break(span) => Expression::Break(span),
+            Expr::Set(ident, expr, span) => Expression::Set(
+                Box::new(self.expr(*ident)?),
+                Box::new(self.expr(*expr)?),
+                span,
+            ),
+            Expr::Variant { name, args, span } => {
+                let Expr::Ident(name, _) = *name else {
+                    return Err(Diagnostic::error(name.span(), "Expected a variant name"));
+                };
+                self.variant(&name, args, span)?
+            }
+            Expr::Match { expr, arms, span } => self.match_(*expr, arms, span, false)?,
+            Expr::TypeDef { span, .. } | Expr::EnumDef { span, .. } => {
+                return Err(Diagnostic::error(
+                    span,
+                    "Types can only be defined at the top level",
+                ));
+            }
+            Expr::Module(_, span) | Expr::Import(_, span) => {
+                return Err(Diagnostic::error(
+                    span,
+                    "Modules can only be declared and imported at the top level",
+                ));
+            }
+            Expr::Struct { fields, span, .. } => {
+                let ty = self.struct_type(span)?;
+                let Type::Struct {
+                    fields: defined, ..
+                } = &ty
+                else {
+                    unreachable!("`struct_type` only returns structs")
+                };
+                let mut fields = fields
+                    .into_iter()
+                    .map(|(field, value)| match field {
+                        Expr::Ident(field, _) => Ok((field, value)),
+                        field => Err(Diagnostic::error(field.span(), "Expected a field name")),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let mut values = vec![];
+                for (name, _) in defined {
+                    let Some(i) = fields.iter().position(|(field, _)| field == name) else {
+                        return Err(Diagnostic::error(
+                            span,
+                            format!("Missing field `{name}` of `{ty}`"),
+                        ));
+                    };
+                    values.push(self.expr(fields.remove(i).1)?);
+                }
+                Expression::Struct {
+                    ty,
+                    fields: Expressions(values),
+                    span,
+                }
+            }
+            Expr::Field(expr, field, span) => {
+                let ty = self.struct_type(span)?;
+                let Expr::Ident(field, _) = *field else {
+                    return Err(Diagnostic::error(field.span(), "Expected a field name"));
+                };
+                let mut spills = vec![];
+                let expr = Box::new(self.operand(*expr, &mut spills)?);
+                with_spills(
+                    spills,
+                    Expression::Field {
+                        ty,
+                        expr,
+                        field,
+                        span,
+                    },
+                )
+            }
+            // Types are only spelled out for the backend, like the name of a struct.
+            Expr::ArrayType(ty, span) => match self.expr(*ty)? {
+                Expression::Ident(name, _) => Expression::Ident(format!("[{name}]"), span),
+                ty => return Err(Diagnostic::error(ty.span(), "Expected a type name")),
+            },
+            Expr::Array(elems, span) => Expression::Array(self.arguments(elems)?, span),
+            Expr::Receive(span) => Expression::Receive(span),
+            Expr::ReceiveAfter {
+                timeout,
+                otherwise,
+                span,
+            } => {
+                let mut spills = vec![];
+                let timeout = Box::new(self.operand(*timeout, &mut spills)?);
+                with_spills(
+                    spills,
+                    Expression::If {
+                        cond: Box::new(Expression::AwaitMessage(timeout, span)),
+                        then: Expressions(vec![Expression::Receive(span)]),
+                        otherwise: self.branch(otherwise, span, false)?,
+                        span,
+                    },
+                )
+            }
+            Expr::Index(expr, index, span) => {
+                let mut spills = vec![];
+                let expr = Box::new(self.operand(*expr, &mut spills)?);
+                let index = Box::new(self.operand(*index, &mut spills)?);
+                with_spills(spills, Expression::Index { expr, index, span })
+            }
+        })
+    }
+
+    /// The checker records the result of a call of one of the [`BUILTINS`] or
+    /// the runtime [`io::FUNCTIONS`] it did not find a function for.
+    fn is_builtin(&self, ident: &Expr, span: Span) -> bool {
+        let Expr::Ident(name, _) = ident else {
+            return false;
+        };
+        (BUILTINS.contains(&name.as_str())
+            || io::FUNCTIONS.iter().any(|function| function.name == name))
+            && self.types.get(span).is_some()
+    }
+
+    fn builtin(&self, ident: Expr, args: Vec<Expr>, span: Span) -> Result<Expression, Diagnostic> {
+        let Expr::Ident(name, _) = ident else {
+            return Err(Diagnostic::error(ident.span(), "Expected a builtin"));
+        };
+        // The function is named, not evaluated, and its arguments are evaluated
+        // like the ones of a call.
+        if name == "spawn" {
+            let mut args = args.into_iter();
+            let Some(Expr::Ident(function, function_span)) = args.next() else {
+                return Err(Diagnostic::error(span, "Expected the name of a function"));
+            };
+            return Ok(Expression::Spawn {
+                function: Box::new(Expression::Ident(function, function_span)),
+                args: self.arguments(args.collect())?,
+                span,
+            });
+        }
+        let mut spills = vec![];
+        let mut args = args
+            .into_iter()
+            .map(|arg| self.operand(arg, &mut spills))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter();
+        if io::FUNCTIONS.iter().any(|function| function.name == name) {
+            let expression = Expression::Io {
+                function: name,
+                args: Expressions(args.collect()),
+                span,
+            };
+            return Ok(with_spills(spills, expression));
+        }
+        let expression = match (name.as_str(), args.next(), args.next()) {
+            ("len", Some(array), None) => Expression::ArrayLen(Box::new(array), span),
+            ("push", Some(array), Some(value)) => Expression::ArrayPush {
+                array: Box::new(array),
+                value: Box::new(value),
+                span,
+            },
+            ("send", Some(pid), Some(message)) => Expression::Send {
+                pid: Box::new(pid),
+                message: Box::new(message),
+                span,
+            },
+            ("self", None, None) => Expression::SelfPid(span),
+            ("link", Some(pid), None) => Expression::Link(Box::new(pid), span),
+            ("monitor", Some(pid), None) => Expression::Monitor(Box::new(pid), span),
+            ("restart", Some(pid), None) => Expression::Restart(Box::new(pid), span),
+            ("exit_pid", Some(exit), None) => Expression::ExitPid(Box::new(exit), span),
+            ("exit_reason", Some(exit), None) => Expression::ExitReason(Box::new(exit), span),
+            ("sleep", Some(ms), None) => Expression::Sleep(Box::new(ms), span),
+            ("now_ms", None, None) => Expression::NowMs(span),
+            _ => {
+                return Err(Diagnostic::error(
+                    span,
+                    format!("Wrong arguments for `{name}`"),
+                ));
+            }
+        };
+        Ok(with_spills(spills, expression))
+    }
+
+    fn variant(&self, name: &str, args: Vec<Expr>, span: Span) -> Result<Expression, Diagnostic> {
+        let ty = self.enum_type(span)?;
+        let Type::Enum { variants, .. } = &ty else {
+            unreachable!("`enum_type` only returns enums")
+        };
+        let Some(variant) = variants.iter().position(|(defined, _)| defined == name) else {
+            return Err(Diagnostic::error(
+                span,
+                format!("Variant `{name}` is not defined"),
+            ));
+        };
+        Ok(Expression::Variant {
+            ty,
+            variant,
+            fields: self.arguments(args)?,
+            span,
+        })
+    }
+
+    /// Binds the inspected enum to a hidden variable and tests its tag arm by arm,
+    /// the last arm is taken without a test since the checker proved the match exhaustive.
+    fn match_(
+        &self,
+        expr: Expr,
+        arms: Vec<MatchArm>,
+        span: Span,
+        tail: bool,
+    ) -> Result<Expression, Diagnostic> {
+        let ty = self.enum_type(span)?;
+        let Type::Enum { variants, .. } = &ty else {
+            unreachable!("`enum_type` only returns enums")
+        };
+        let hidden = format!("$match@{}..{}", span.start, span.end);
+        let hidden_ident = || Box::new(Expression::Ident(hidden.clone(), span));
+
+        let mut otherwise: Option<Expressions> = None;
+        for arm in arms.into_iter().rev() {
+            let variant = match &arm.variant {
+                Expr::Ident(name, _) => variants.iter().position(|(defined, _)| defined == name),
+                variant => {
+                    return Err(Diagnostic::error(variant.span(), "Expected a variant name"));
+                }
+            };
+            let mut body = vec![];
+            if let Some(variant) = variant {
+                for (index, binding) in arm.bindings.into_iter().enumerate() {
+                    let payload = &variants[variant].1[index];
+                    body.push(Expression::Assign(
+                        (Box::new(self.expr(binding)?), type_ident(payload, arm.span)),
+                        Box::new(Expression::Payload {
+                            ty: ty.clone(),
+                            expr: hidden_ident(),
+                            variant,
+                            index,
+                            span: arm.span,
+                        }),
+                        arm.span,
+                    ));
+                }
+            }
+            body.append(&mut self.branch(arm.body, arm.span, tail)?.0);
+            otherwise = Some(match (variant, otherwise) {
+                (Some(variant), Some(otherwise)) => Expressions(vec![Expression::If {
+                    cond: Box::new(Expression::IsVariant {
+                        expr: hidden_ident(),
+                        variant,
+                        span: arm.span,
+                    }),
+                    then: Expressions(body),
+                    otherwise,
+                    span: arm.span,
+                }]),
+                // The last arm, a `_` is always the last one.
+                _ => Expressions(body),
+            });
+        }
+
+        let mut block = vec![Expression::Assign(
+            (hidden_ident(), type_ident(&ty, span)),
+            Box::new(self.expr(expr)?),
+            span,
+        )];
+        block.append(&mut otherwise.unwrap_or_default().0);
+        Ok(Expression::Block(Expressions(block), span))
+    }
+
+    fn enum_type(&self, span: Span) -> Result<Type, Diagnostic> {
+        match self.types.get(span) {
+            Some(ty @ Type::Enum { .. }) => Ok(ty.clone()),
+            _ => Err(Diagnostic::error(span, "Expected an enum")),
+        }
+    }
+
+    /// Struct type the checker recorded for a struct literal or a field access.
+    fn struct_type(&self, span: Span) -> Result<Type, Diagnostic> {
+        match self.types.get(span) {
+            Some(ty @ Type::Struct { .. }) => Ok(ty.clone()),
+            _ => Err(Diagnostic::error(span, "Expected a struct")),
+        }
+    }
+
+    /// Integer literals are range checked against their type and kept as the
+    /// 64-bit pattern of their value, see [`Expression::Lit`].
+    fn literal(&self, lit: &str, span: Span) -> Result<Expression, Diagnostic> {
+        let ty = self.type_of(span);
+        let (min, max) = ty.range().unwrap_or((i64::MIN.into(), i64::MAX.into()));
+        match lit.parse::<i128>() {
+            Ok(value) if (min..=max).contains(&value) => Ok(Expression::Lit(value as i64, span)),
+            _ => Err(Diagnostic::error(
+                span,
+                format!("Literal `{lit}` does not fit in `{ty}`"),
+            )),
+        }
+    }
+
+    fn float(&self, lit: &str, span: Span) -> Result<Expression, Diagnostic> {
+        let ty = self.type_of(span);
+        let bits = match ty {
+            Type::F32 => lit
+                .parse::<f32>()
+                .ok()
+                .filter(|value| value.is_finite())
+                .map(|value| value.to_bits() as i64),
+            _ => lit
+                .parse::<f64>()
+                .ok()
+                .filter(|value| value.is_finite())
+                .map(|value| value.to_bits() as i64),
+        };
+        bits.map(|bits| Expression::Lit(bits, span)).ok_or_else(|| {
+            Diagnostic::error(span, format!("Literal `{lit}` does not fit in `{ty}`"))
+        })
+    }
+
+    /// `stdprint` of anything but a signed integer calls its variant for the
+    /// type of the argument, see [`PRINTERS`].
+    fn callee(&self, ident: Expr, span: Span) -> Result<Expression, Diagnostic> {
+        match (ident, self.types.get(span)) {
+            (Expr::Ident(name, ident_span), Some(ty)) if name == "stdprint" => {
+                let ty = ty.to_string();
+                let name = PRINTERS
+                    .iter()
+                    .find(|(printed, _)| *printed == ty)
+                    .map_or(name, |(_, printer)| (*printer).to_owned());
+                Ok(Expression::Ident(name, ident_span))
+            }
+            (ident, _) => self.expr(ident),
+        }
+    }
+
+    /// Type the checker recorded for a literal or an operator, `i64` for trees it never saw.
+    fn type_of(&self, span: Span) -> Type {
+        self.types.get(span).cloned().unwrap_or(Type::I64)
+    }
+
+    /// Binds a compound operand to a hidden variable, so that operators only read
+    /// identifiers and literals and the backend can lower them within one block.
+    fn operand(&self, expr: Expr, spills: &mut Vec<Expression>) -> Result<Expression, Diagnostic> {
+        match expr {
+            Expr::Ident(..) | Expr::Lit(..) | Expr::Float(..) | Expr::Bool(..) | Expr::Char(..) => {
+                self.expr(expr)
+            }
+            expr => {
+                let span = expr.span();
+                let hidden = format!("$operand@{}..{}", span.start, span.end);
+                let ty = self.types.value(span).cloned().unwrap_or(Type::I64);
+                spills.push(Expression::Assign(
+                    (
+                        Box::new(Expression::Ident(hidden.clone(), span)),
+                        type_ident(&ty, span),
+                    ),
+                    Box::new(self.expr(expr)?),
+                    span,
+                ));
+                Ok(Expression::Ident(hidden, span))
+            }
+        }
+    }
 
-            _ => unimplemented!(),
+    /// `expr` as a `bool`, an integer condition is `1` when it is not `0`.
+    fn truth(&self, expr: Expr) -> Result<Expressions, Diagnostic> {
+        let span = expr.span();
+        let expr = self.expr(expr)?;
+        if self.types.value(span) == Some(&Type::Bool) {
+            return Ok(Expressions(vec![expr]));
         }
+        Ok(Expressions(vec![Expression::If {
+            cond: Box::new(expr),
+            then: Expressions(vec![Expression::Lit(1, span)]),
+            otherwise: Expressions(vec![Expression::Lit(0, span)]),
+            span,
+        }]))
+    }
+
+    /// Branch bodies only end in a tail call when the `if` is the last expression
+    /// of a function, otherwise the `if` still has to store the value of the taken branch.
+    fn branch(&self, body: Vec<Expr>, span: Span, tail: bool) -> Result<Expressions, Diagnostic> {
+        if body.is_empty() {
+            return Ok(Expressions(vec![Expression::Lit(0, span)]));
+        }
+        if tail {
+            return self.body(body);
+        }
+        Ok(Expressions(
+            body.into_iter()
+                .map(|expr| self.expr(expr))
+                .collect::<Result<_, _>>()?,
+        ))
+    }
+
+    /// Call arguments are values, none of them is in tail position.
+    fn arguments(&self, args: Vec<Expr>) -> Result<Expressions, Diagnostic> {
+        Ok(Expressions(
+            args.into_iter()
+                .map(|expr| self.expr(expr))
+                .collect::<Result<_, _>>()?,
+        ))
+    }
+}
+
+fn with_spills(mut spills: Vec<Expression>, expression: Expression) -> Expression {
+    if spills.is_empty() {
+        return expression;
+    }
+    let span = expression.span();
+    spills.push(expression);
+    Expression::Block(Expressions(spills), span)
+}
+
+/// Type of a hidden variable, written the way a `let` names it.
+fn type_ident(ty: &Type, span: Span) -> Box<Expression> {
+    Box::new(Expression::Ident(ty.to_string(), span))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        frontend::{parser::parse, typeck::check},
+        middleware::{Expression, Expressions},
+    };
+
+    /// Names and types of the hidden variables bound in `expressions`.
+    fn hidden(expressions: &Expressions, found: &mut Vec<(String, String)>) {
+        for expression in &expressions.0 {
+            match expression {
+                Expression::Assign((name, ty), value, _) => {
+                    if let (Expression::Ident(name, _), Expression::Ident(ty, _)) = (&**name, &**ty)
+                        && name.starts_with('$')
+                    {
+                        found.push((name.split('@').next().unwrap().to_owned(), ty.clone()));
+                    }
+                    if let Expression::Block(body, _) = &**value {
+                        hidden(body, found);
+                    }
+                }
+                Expression::Function { body, .. }
+                | Expression::Block(body, _)
+                | Expression::Loop(body, _) => hidden(body, found),
+                Expression::If {
+                    then, otherwise, ..
+                } => {
+                    hidden(then, found);
+                    hidden(otherwise, found);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn hidden_variables_have_checked_types() {
+        let code = r#"
+            type Shape = Circle(f32) | Square(u8)
+            main: -> i64 {
+                let s: Shape = Square(3)
+                let a: u8 = 2
+                let same: bool = ((a * 3) > 4) == (a < 3)
+                let side: u8 = match s { Circle(r) => 0, Square(x) => x }
+                0
+            }
+        "#;
+        let exprs = parse(code).unwrap();
+        let types = check(&exprs).unwrap();
+        let mut found = vec![];
+        hidden(&Expressions::lower(exprs, &types).unwrap(), &mut found);
+        found.sort();
+        found.dedup();
+
+        let expected = [
+            ("$match", "Shape"),
+            ("$operand", "bool"),
+            ("$operand", "u8"),
+        ];
+        assert_eq!(
+            found,
+            expected.map(|(name, ty)| (name.to_owned(), ty.to_owned()))
+        );
     }
 }